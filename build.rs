@@ -0,0 +1,8 @@
+/// Let the OpenAPI document see through the id, time and XP type aliases
+fn main() {
+    utoipa_config::Config::new()
+        .alias_for("IdType", "u64")
+        .alias_for("TimeType", "u64")
+        .alias_for("XpType", "u64")
+        .write_to_file();
+}
@@ -0,0 +1,14 @@
+#!/bin/sh
+# Run the tests with the postgres feature, against a throwaway PostgreSQL instance that is
+# created in a temporary directory and removed afterwards. Needs initdb and pg_ctl on the
+# PATH. Arguments are passed on to `cargo test`.
+set -eu
+
+dir=$(mktemp -d)
+port=${PGPORT:-54329}
+trap 'pg_ctl -D "$dir/data" -m immediate stop >/dev/null 2>&1 || true; rm -rf "$dir"' EXIT
+
+initdb -D "$dir/data" -U postgres -A trust >/dev/null
+pg_ctl -D "$dir/data" -l "$dir/log" -w -o "-p $port -k $dir -c listen_addresses=127.0.0.1 -c fsync=off" start >/dev/null
+
+GOL_TEST_DATABASE_URL="postgres://postgres@127.0.0.1:$port/postgres" cargo test --features postgres "$@"
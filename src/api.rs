@@ -1,72 +1,141 @@
-use actix_web::{body::BoxBody, get, guard, http::header::ContentType, post, web, HttpResponse, Responder, Result};
-use serde::{ Serialize, Deserialize };
-use futures::future::join_all;
-use crate::db::{ execute, Pool, Query, QueryResult };
-use crate::AppError;
-
-use crate::model::character::CharacterList;
+use std::time::{ Duration, UNIX_EPOCH };
+use actix_web::{get, http::header::{ContentType, HttpDate}, middleware::{Condition, DefaultHeaders}, post, web, HttpResponse, Responder, Result};
+use crate::config::{ ApiConfig, DeprecationConfig };
+use crate::model::user::AuthUser;
+use crate::repo::CharacterRepo;
+use version::ApiVersion;
 
+pub mod auth;
+pub mod body;
 mod character;
+mod openapi;
+mod response;
 mod skill;
 mod task;
+mod trash;
+pub mod version;
+#[cfg(test)]
+mod tests;
+
+/// Mount the routes once per version under `/api/<version>`, and right under `/api` as v1 for
+/// the clients from before versions. The versions `api` deprecates say so in their headers.
+pub fn config(api: &ApiConfig) -> impl FnOnce(&mut web::ServiceConfig) {
+    let api = api.clone();
+    move |cfg| {
+        cfg.configure(openapi::config);
+        // before the unversioned scope, which would take their requests otherwise
+        for version in ApiVersion::ALL {
+            cfg.service(
+                web::scope(&format!("/api/{}", version.name()))
+                    .app_data(version)
+                    .wrap(deprecation(api.deprecated.get(version.name())))
+                    .configure(routes)
+            );
+        }
+        cfg.service(
+            web::scope("/api")
+                .app_data(ApiVersion::V1)
+                .wrap(deprecation(api.deprecated.get("unversioned")))
+                .configure(routes)
+        );
+    }
+}
 
-pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/api")
-            .guard(guard::Host("localhost").scheme("http"))
+/// `Deprecation` and `Sunset` headers for every response of a deprecated version
+fn deprecation(config: Option<&DeprecationConfig>) -> Condition<DefaultHeaders> {
+    let headers = config.map(|config| {
+        let (since, sunset) = config.days();
+        DefaultHeaders::new()
+            .add(("Deprecation", format!("@{}", since / 1000)))
+            .add(("Sunset", HttpDate::from(UNIX_EPOCH + Duration::from_millis(sunset)).to_string()))
+    });
+    Condition::new(headers.is_some(), headers.unwrap_or_default())
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // USER ROUTES
+        .service(auth::register)
+        .service(auth::login)
+        .service(auth::logout)
+
+        // CHARACTER ROUTES
+        .service(character::get_characters)
+        .service(character::get_character)
+        .service(character::create_character)
+        .service(character::update_character)
+        .service(character::patch_character)
+        .service(character::delete_character)
+        .service(character::restore_character)
+        .service(character::get_character_skills)   // FIXME
+        .service(character::create_character_skill) // FIXME
+        .service(character::get_character_tasks)    // FIXME
+        .service(character::get_character_activity)
 
-            // CHARACTER ROUTES
-            .service(character::get_characters)
-            .service(character::get_character)
-            .service(character::create_character)
-            .service(character::update_character)
-            .service(character::delete_character)
-            .service(character::get_character_skills)   // FIXME
-            .service(character::create_character_skill) // FIXME
-            .service(character::get_character_tasks)    // FIXME
+        // SKILL ROUTES
+        .service(skill::get_skills)
+        .service(skill::get_skill)
+        .service(skill::get_skill_tasks)
+        .service(skill::create_skill_task)
+        .service(skill::update_skill)
+        .service(skill::patch_skill)
+        .service(skill::delete_skill)
+        .service(skill::restore_skill)
 
-            // SKILL ROUTES
-            .service(skill::get_skills)
-            .service(skill::get_skill)
-            .service(skill::get_skill_tasks)
-            .service(skill::create_skill_task)
-            .service(skill::update_skill)
-            .service(skill::delete_skill)
+        // TASK ROUTES
+        .service(task::get_tasks)
+        .service(task::get_task)
+        .service(task::update_task)
+        .service(task::patch_task)
+        .service(task::delete_task)
+        .service(task::restore_task)
+        .service(task::get_task_history)
 
-            // TASK ROUTES
-            .service(task::get_tasks)
-            .service(task::get_task)
-            .service(task::update_task)
-            .service(task::delete_task)
+        // TRASH ROUTES
+        .service(trash::get_trash)
 
-            .service(reset_db)
+        .service(reset_db)
 
-            .service(hello)
-            .service(echo)
-    );
+        .service(hello)
+        .service(echo);
 }
 
+#[utoipa::path(
+    tag = "misc",
+    responses(
+        (status = 201, description = "Deleted every character of the user", body = String, content_type = "text/plain"),
+    ),
+)]
 #[post("/reset_db")]
-async fn reset_db(db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let query = Query::ResetDB;
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Success => {
-            let msg = format!("db is reset");
-            let res = HttpResponse::Created()
-                .content_type(ContentType::plaintext())
-                .body(msg);
-            Ok(res)
-        }
-        _ => Err(AppError::DBError { error_msg: "cannot reset db /reset_db".to_string() }.into())
-    }
+async fn reset_db(repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    repo.delete_all_characters(&user).await?;
+    let msg = "db is reset";
+    let res = HttpResponse::Created()
+        .content_type(ContentType::plaintext())
+        .body(msg);
+    Ok(res)
 }
 
+#[utoipa::path(
+    tag = "misc",
+    security(()),
+    responses(
+        (status = 200, body = String, content_type = "text/plain"),
+    ),
+)]
 #[get("/")]
 async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello World!")
 }
 
+#[utoipa::path(
+    tag = "misc",
+    security(()),
+    request_body(content = String, content_type = "text/plain"),
+    responses(
+        (status = 200, description = "The request body", body = String, content_type = "text/plain"),
+    ),
+)]
 #[post("/echo")]
 async fn echo(req_body: String) -> impl Responder {
     HttpResponse::Ok().body(req_body)
@@ -0,0 +1,107 @@
+use actix_web::{
+    delete, dev::Payload, http::header::AUTHORIZATION, post,
+    web, FromRequest, HttpRequest, HttpResponse, Responder,
+};
+use futures::future::LocalBoxFuture;
+use crate::AppError;
+use crate::TimeType;
+use crate::api::body::Body;
+use crate::model::user::{ AuthUser, Credentials, Session, User };
+use crate::problem::Problem;
+use crate::repo::UserRepo;
+use crate::validation::Validate;
+
+/// How long a login stays valid
+pub const SESSION_TTL: TimeType = 30 * 24 * 60 * 60 * 1000; // 30 days
+
+#[utoipa::path(
+    tag = "users",
+    security(()),
+    request_body = Credentials,
+    responses(
+        (status = 201, body = User),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Username is already taken", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[post("/users")]
+pub async fn register(body: Body<Credentials>, repo: web::Data<dyn UserRepo>) -> Result<impl Responder, actix_web::Error> {
+    let credentials = body.into_inner();
+    credentials.validate()?;
+    Ok(repo.register(credentials).await?)
+}
+
+#[utoipa::path(
+    tag = "users",
+    security(()),
+    request_body = Credentials,
+    responses(
+        (status = 201, description = "New session, send its token as a bearer token", body = Session),
+        (status = 401, description = "Wrong username or password"),
+    ),
+)]
+#[post("/sessions")]
+pub async fn login(body: Body<Credentials>, repo: web::Data<dyn UserRepo>) -> Result<impl Responder, actix_web::Error> {
+    let credentials = body.into_inner();
+    Ok(repo.login(credentials, SESSION_TTL).await?)
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 204, description = "The session is over"),
+    ),
+)]
+#[delete("/sessions")]
+pub async fn logout(req: HttpRequest, repo: web::Data<dyn UserRepo>) -> Result<impl Responder, actix_web::Error> {
+    let token = bearer_token(&req).ok_or(AppError::Unauthorized)?;
+    repo.logout(token).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Token of an `Authorization: Bearer <token>` header
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token.to_string())
+    } else {
+        None
+    }
+}
+
+/// Handlers that take an `AuthUser` answer 401 Unauthorized unless the request carries
+/// the bearer token of an unexpired session.
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = bearer_token(req);
+        let repo = req.app_data::<web::Data<dyn UserRepo>>().cloned();
+        Box::pin(async move {
+            let token = token.ok_or(AppError::Unauthorized)?;
+            let repo = repo.ok_or(AppError::InternalError)?;
+            match repo.authenticate(token).await? {
+                Some(user) => Ok(user),
+                None => Err(AppError::Unauthorized.into()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn reads_bearer_token() {
+        let req = TestRequest::default().insert_header((AUTHORIZATION, "Bearer abc123")).to_http_request();
+        assert_eq!(bearer_token(&req).as_deref(), Some("abc123"));
+        let req = TestRequest::default().insert_header((AUTHORIZATION, "Basic abc123")).to_http_request();
+        assert_eq!(bearer_token(&req), None);
+        assert_eq!(bearer_token(&TestRequest::default().to_http_request()), None);
+    }
+}
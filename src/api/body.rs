@@ -0,0 +1,129 @@
+use actix_web::{
+    dev::Payload, error::{ JsonPayloadError, UrlencodedError },
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use crate::AppError;
+
+/// Largest accepted request body, for both JSON and forms
+pub const BODY_LIMIT: usize = 4096; // 4kb
+
+/// Request body read as JSON or as a urlencoded form, depending on the Content-Type.
+/// Anything that isn't JSON is treated as a form, which keeps the old form-only clients working.
+pub struct Body<T>(pub T);
+
+impl<T> Body<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if is_json(req) {
+            let json = web::Json::<T>::from_request(req, payload);
+            Box::pin(async move { json.await.map(|json| Body(json.into_inner())) })
+        } else {
+            let form = web::Form::<T>::from_request(req, payload);
+            Box::pin(async move { form.await.map(|form| Body(form.into_inner())) })
+        }
+    }
+}
+
+/// `application/json` and `application/*+json`
+fn is_json(req: &HttpRequest) -> bool {
+    match req.mime_type() {
+        Ok(Some(mime)) => mime.subtype() == "json" || mime.suffix().is_some_and(|suffix| suffix == "json"),
+        _ => false,
+    }
+}
+
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(BODY_LIMIT)
+        .error_handler(|err, _req| {
+            match err {
+                JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } =>
+                    AppError::PayloadTooLarge { limit: BODY_LIMIT },
+                JsonPayloadError::ContentType => AppError::UnsupportedMediaType,
+                _ => AppError::InvalidBody { reason: err.to_string() },
+            }.into()
+        })
+}
+
+pub fn form_config() -> web::FormConfig {
+    web::FormConfig::default()
+        .limit(BODY_LIMIT)
+        .error_handler(|err, _req| {
+            match err {
+                UrlencodedError::Overflow { .. } => AppError::PayloadTooLarge { limit: BODY_LIMIT },
+                UrlencodedError::ContentType => AppError::UnsupportedMediaType,
+                _ => AppError::InvalidBody { reason: err.to_string() },
+            }.into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ http::StatusCode, test::TestRequest };
+    use crate::model::task::TaskFields;
+
+    async fn extract(req: TestRequest) -> Result<TaskFields, actix_web::Error> {
+        let (req, mut payload) = req
+            .app_data(json_config())
+            .app_data(form_config())
+            .to_http_parts();
+        Body::<TaskFields>::from_request(&req, &mut payload).await.map(Body::into_inner)
+    }
+
+    fn status(err: actix_web::Error) -> StatusCode {
+        err.as_response_error().status_code()
+    }
+
+    #[actix_web::test]
+    async fn reads_json() {
+        let req = TestRequest::post()
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(r#"{"name": "scales", "description": "", "completed": 1}"#);
+        let fields = extract(req).await.unwrap();
+        assert_eq!((fields.name.as_str(), fields.completed), ("scales", 1));
+    }
+
+    #[actix_web::test]
+    async fn reads_form() {
+        let req = TestRequest::post()
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .set_payload("name=scales&description=&completed=0");
+        let fields = extract(req).await.unwrap();
+        assert_eq!((fields.name.as_str(), fields.completed), ("scales", 0));
+    }
+
+    #[actix_web::test]
+    async fn malformed_json_is_bad_request() {
+        let req = TestRequest::post()
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(r#"{"name": "scales","#);
+        assert_eq!(status(extract(req).await.unwrap_err()), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn oversized_body_is_rejected() {
+        let req = TestRequest::post()
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(format!(r#"{{"name": "{}", "description": "", "completed": 1}}"#, "x".repeat(BODY_LIMIT)));
+        assert_eq!(status(extract(req).await.unwrap_err()), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn unknown_content_type_is_unsupported() {
+        let req = TestRequest::post()
+            .insert_header(("Content-Type", "text/plain"))
+            .set_payload("scales");
+        assert_eq!(status(extract(req).await.unwrap_err()), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
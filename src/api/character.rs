@@ -1,98 +1,198 @@
 use actix_web::http::header::ContentType;
 use actix_web::{
-    delete, get, post, put,
-    web, HttpResponse, Responder
+    delete, get, patch, post, put,
+    web, HttpRequest, HttpResponse, Responder
 };
 use crate::{ AppError, IdType };
-use crate::db::{ execute, Pool, Query, QueryResult };
-use crate::model::character::{ CharacterList, CharacterFields };
-use crate::model::skill::{ SkillFields, SkillList };
-use crate::model::task::TaskList;
+use crate::api::body::Body;
+use crate::etag::if_match;
+use crate::model::activity::{ Activity, ActivityFilter, ACTIVITY_SORTABLE };
+use crate::model::character::{ Character, CharacterFields, CharacterFilter, CharacterPatch, CharacterTree, IncludeParams, CHARACTER_SORTABLE };
+use crate::model::page::{ Page, PageParams };
+use crate::model::user::AuthUser;
+use crate::model::skill::{ Skill, SkillFields };
+use crate::model::task::Task;
+use crate::problem::Problem;
+use crate::progression::LevelCurve;
+use crate::repo::{ ActivityRepo, CharacterRepo, SkillRepo, TaskRepo };
+use crate::validation::{ FieldError, Validate, ID_RULE };
 
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character"), IncludeParams),
+    responses(
+        (status = 200, body = CharacterTree),
+        (status = 304, description = "Unchanged since the `If-None-Match` version"),
+        (status = 404, description = "Missing or owned by another user", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[get("/characters/{id}")]
-pub async fn get_character(path: web::Path<IdType>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    // using string and parsing manually like below, we can use custom err types?
-    //let id: IdType = path.into_inner().parse().map_err(|_| AppError::ValidationError { field: "id".to_string() })?;
+pub async fn get_character(path: web::Path<IdType>, include: web::Query<IncludeParams>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query_result = execute(&db, Query::GetCharacter(id)).await?;
-    match query_result {
-        QueryResult::Character(character) => Ok(character),
-        _ => Err(AppError::InternalError.into())
-    }
+    let include = include.to_include()?;
+    Ok(repo.get_character(&user, id, include).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    params(PageParams, CharacterFilter, IncludeParams),
+    responses(
+        (status = 200, body = Page<CharacterTree>),
+    ),
+)]
 #[get("/characters")]
-pub async fn get_characters(db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let query_result = execute(&db, Query::GetCharacterList).await?;
-    match query_result {
-        QueryResult::CharacterList(character_list) => Ok(CharacterList(character_list)),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn get_characters(page: web::Query<PageParams>, filter: web::Query<CharacterFilter>, include: web::Query<IncludeParams>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let page = page.to_request(CHARACTER_SORTABLE)?;
+    let include = include.to_include()?;
+    Ok(repo.list_characters(&user, filter.into_inner(), page, include).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character")),
+    responses(
+        (status = 200, body = Vec<Skill>),
+    ),
+)]
 #[get("/characters/{id}/skills")]
-pub async fn get_character_skills(path: web::Path<String>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let id: IdType = path.into_inner().parse().map_err(|_| AppError::ValidationError { field: "id".to_string() })?;
-    let query_result = execute(&db, Query::GetCharacterSkillList(id)).await?;
-    match query_result {
-        QueryResult::SkillList(skill_list) => Ok(SkillList(skill_list)),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn get_character_skills(path: web::Path<String>, repo: web::Data<dyn SkillRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id: IdType = path.into_inner().parse().map_err(|_| AppError::from(FieldError::new("id", ID_RULE)))?;
+    Ok(repo.character_skills(&user, id).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character")),
+    request_body = SkillFields,
+    responses(
+        (status = 200, body = Skill),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing character", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[post("/characters/{id}/skills")]
-pub async fn create_character_skill(path: web::Path<IdType>, form: web::Form<SkillFields>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn create_character_skill(path: web::Path<IdType>, body: Body<SkillFields>, repo: web::Data<dyn SkillRepo>, curve: web::Data<LevelCurve>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query = Query::CreateCharacterSkill(id, form.into_inner());
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Skill(skill) => Ok(skill),
-        _ => Err(AppError::InternalError.into())
-    }
+    let fields = body.into_inner();
+    fields.validate()?;
+    Ok(repo.create_skill(&user, id, fields, **curve).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character"), PageParams, ActivityFilter),
+    responses(
+        (status = 200, body = Page<Activity>),
+        (status = 404, description = "Missing character", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[get("/characters/{id}/activity")]
+pub async fn get_character_activity(path: web::Path<IdType>, page: web::Query<PageParams>, filter: web::Query<ActivityFilter>, repo: web::Data<dyn ActivityRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let page = page.to_request(ACTIVITY_SORTABLE)?;
+    let filter = filter.into_inner();
+    filter.validate()?;
+    Ok(repo.character_activity(&user, path.into_inner(), filter, page).await?)
+}
+
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character")),
+    responses(
+        (status = 200, body = Vec<Task>),
+    ),
+)]
 #[get("/characters/{id}/tasks")]
-pub async fn get_character_tasks(path: web::Path<String>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let id: IdType = path.into_inner().parse().map_err(|_| AppError::ValidationError { field: "id".to_string() })?;
-    let query_result = execute(&db, Query::GetCharacterTaskList(id)).await?;
-    match query_result {
-        QueryResult::TaskList(task_list) => Ok(TaskList(task_list)),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn get_character_tasks(path: web::Path<String>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id: IdType = path.into_inner().parse().map_err(|_| AppError::from(FieldError::new("id", ID_RULE)))?;
+    Ok(repo.character_tasks(&user, id).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    request_body = CharacterFields,
+    responses(
+        (status = 200, body = Character),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[post("/characters")]
-pub async fn create_character(form: web::Form<CharacterFields>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let form = form.into_inner();
-    let query_result = execute(&db, Query::CreateCharacter(form)).await?;
-    match query_result {
-        QueryResult::Character(character) => Ok(character),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn create_character(body: Body<CharacterFields>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let fields = body.into_inner();
+    fields.validate()?;
+    Ok(repo.create_character(&user, fields).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    request_body = CharacterFields,
+    responses(
+        (status = 200, body = Character),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing character", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[put("/characters/{id}")]
-pub async fn update_character(path: web::Path<String>, form: web::Form<CharacterFields>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let id: IdType = path.into_inner().parse().map_err(|_| AppError::ValidationError { field: "id".to_string() })?;
-    let query_result = execute(&db, Query::UpdateCharacter(id, form.into_inner())).await?;
-    match query_result {
-        QueryResult::Character(character) => Ok(character),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn update_character(req: HttpRequest, path: web::Path<String>, body: Body<CharacterFields>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id: IdType = path.into_inner().parse().map_err(|_| AppError::from(FieldError::new("id", ID_RULE)))?;
+    let fields = body.into_inner();
+    fields.validate()?;
+    Ok(repo.update_character(&user, id, fields.into(), if_match(&req)).await?)
+}
+
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    request_body = CharacterPatch,
+    responses(
+        (status = 200, body = Character),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing character", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[patch("/characters/{id}")]
+pub async fn patch_character(req: HttpRequest, path: web::Path<String>, body: Body<CharacterPatch>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id: IdType = path.into_inner().parse().map_err(|_| AppError::from(FieldError::new("id", ID_RULE)))?;
+    let patch = body.into_inner();
+    patch.validate()?;
+    Ok(repo.update_character(&user, id, patch, if_match(&req)).await?)
 }
 
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    responses(
+        (status = 200, description = "Moved to the trash with its skills and tasks", body = String, content_type = "text/plain"),
+        (status = 404, description = "Missing character", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[delete("/characters/{id}")]
-pub async fn delete_character(path: web::Path<String>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let id: IdType = path.into_inner().parse().map_err(|_| AppError::ValidationError { field: "id".to_string() })?;
-    let query_result = execute(&db, Query::DeleteCharacter(id)).await?;
-    match query_result {
-        QueryResult::Success => {
-            let msg = format!("Character with id {} is deleted", id);
-            let res = HttpResponse::Ok()
-                .content_type(ContentType::plaintext())
-                .body(msg);
-            Ok(res)
-        },
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn delete_character(req: HttpRequest, path: web::Path<String>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id: IdType = path.into_inner().parse().map_err(|_| AppError::from(FieldError::new("id", ID_RULE)))?;
+    repo.delete_character(&user, id, if_match(&req)).await?;
+    let msg = format!("Character with id {} is moved to the trash", id);
+    let res = HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(msg);
+    Ok(res)
+}
+
+#[utoipa::path(
+    tag = "characters",
+    params(("id" = u64, Path, description = "Id of the character")),
+    responses(
+        (status = 200, body = Character),
+        (status = 404, description = "Not in the trash", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[post("/characters/{id}/restore")]
+pub async fn restore_character(path: web::Path<IdType>, repo: web::Data<dyn CharacterRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id = path.into_inner();
+    Ok(repo.restore_character(&user, id).await?)
 }
@@ -0,0 +1,114 @@
+use actix_web::web;
+use utoipa::openapi::{
+    path::Operation,
+    security::{ HttpAuthScheme, HttpBuilder, SecurityScheme },
+    ContentBuilder, Ref, ResponseBuilder,
+};
+use utoipa::{ Modify, OpenApi };
+use utoipa_swagger_ui::SwaggerUi;
+use crate::problem::{ self, Problem };
+use super::{ auth, character, skill, task, trash };
+
+/// Handlers registered in `api::config`, relative to the version
+#[derive(OpenApi)]
+#[openapi(paths(
+    auth::register, auth::login, auth::logout,
+    character::get_characters, character::get_character, character::create_character,
+    character::update_character, character::patch_character, character::delete_character,
+    character::restore_character, character::get_character_skills, character::create_character_skill,
+    character::get_character_tasks, character::get_character_activity,
+    skill::get_skills, skill::get_skill, skill::get_skill_tasks, skill::create_skill_task,
+    skill::update_skill, skill::patch_skill, skill::delete_skill, skill::restore_skill,
+    task::get_tasks, task::get_task, task::update_task, task::patch_task, task::delete_task,
+    task::restore_task, task::get_task_history,
+    trash::get_trash,
+    super::reset_db, super::hello, super::echo,
+))]
+struct Routes;
+
+/// OpenAPI document of the API, generated from the handlers and the model types
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Game of Life API",
+        description = "Characters, their skills and the tasks that level them up. The paths below are v1, \
+            v2 takes the same requests and answers with the `fields` of characters, skills and tasks next to their id.",
+    ),
+    nest((path = "/api/v1", api = Routes)),
+    components(schemas(Problem)),
+    modifiers(&Conventions),
+    security(("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+/// What holds for every route: the bearer token and errors as problems
+struct Conventions;
+
+impl Modify for Conventions {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+        }
+        let error = ResponseBuilder::new()
+            .description("Any other error, `type` tells which")
+            .content(problem::CONTENT_TYPE, ContentBuilder::new().schema(Some(Ref::from_schema_name("Problem"))).build())
+            .build();
+        for item in openapi.paths.paths.values_mut() {
+            for operation in operations(item) {
+                operation.responses.responses.entry("default".to_string()).or_insert(error.clone().into());
+            }
+        }
+    }
+}
+
+fn operations(item: &mut utoipa::openapi::PathItem) -> impl Iterator<Item = &mut Operation> {
+    [&mut item.get, &mut item.put, &mut item.post, &mut item.delete, &mut item.patch].into_iter().flatten()
+}
+
+/// Serve the document at `/api/openapi.json` and Swagger UI, bundled with the server, at
+/// `/api/docs/`. Registered before the `/api` scope, which would take the requests otherwise.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ http::StatusCode, test::{ call_service, init_service, read_body_json, TestRequest }, App };
+    use serde_json::Value;
+
+    /// Handler names registered in `api::config`, read from its source
+    fn registered_handlers() -> Vec<&'static str> {
+        include_str!("../api.rs").lines()
+            .filter_map(|line| line.trim().strip_prefix(".service(")?.split(')').next())
+            .filter(|handler| handler.chars().all(|c| c.is_ascii_lowercase() || c == '_' || c == ':'))
+            .map(|handler| handler.rsplit("::").next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn documents_every_registered_route() {
+        let mut spec = ApiDoc::openapi();
+        let documented: Vec<String> = spec.paths.paths.values_mut()
+            .flat_map(|item| operations(item).filter_map(|operation| operation.operation_id.clone()).collect::<Vec<_>>())
+            .collect();
+        let handlers = registered_handlers();
+        assert!(handlers.len() > 30, "cannot read the routes of api::config");
+        for handler in handlers {
+            assert!(documented.iter().any(|id| id == handler), "{} is registered but missing from the OpenAPI document", handler);
+        }
+    }
+
+    #[actix_web::test]
+    async fn serves_the_document_and_the_ui() {
+        let app = init_service(App::new().configure(super::super::config(&Default::default()))).await;
+        let res = call_service(&app, TestRequest::get().uri("/api/openapi.json").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let spec: Value = read_body_json(res).await;
+        assert!(spec["paths"]["/api/v1/characters/{id}"]["get"].is_object());
+        assert!(spec["components"]["schemas"]["Character"].is_object());
+
+        let res = call_service(&app, TestRequest::get().uri("/api/docs/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
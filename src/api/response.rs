@@ -0,0 +1,158 @@
+use actix_web::{
+    body::BoxBody, http::{ header::{ ContentType, EntityTag, IfNoneMatch, ETAG }, Method },
+    HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use serde::Serialize;
+use crate::etag::entity_tag;
+use crate::model::character::{ Character, CharacterTree };
+use crate::model::page::Page;
+use crate::model::skill::{ Skill, SkillList };
+use crate::model::task::{ Task, TaskHistory, TaskList };
+use crate::model::trash::Trash;
+use crate::model::user::{ Session, User };
+use super::version::json_body;
+
+impl Responder for Character {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let etag = entity_tag(self.id, self.updated_at);
+        entity_response(req, &self, etag)
+    }
+}
+
+impl Responder for CharacterTree {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        // changes to the children touch the character, so its version covers them
+        let etag = entity_tag(self.character.id, self.character.updated_at);
+        entity_response(req, &self, etag)
+    }
+}
+
+impl Responder for Skill {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let etag = entity_tag(self.id, self.updated_at);
+        entity_response(req, &self, etag)
+    }
+}
+
+impl Responder for SkillList {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        json_response(req, &self.0)
+    }
+}
+
+impl Responder for Task {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let etag = entity_tag(self.id, self.updated_at);
+        entity_response(req, &self, etag)
+    }
+}
+
+impl Responder for TaskHistory {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        json_response(req, &self)
+    }
+}
+
+impl Responder for TaskList {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        json_response(req, &self.0)
+    }
+}
+
+impl<T: Serialize> Responder for Page<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        json_response(req, &self)
+    }
+}
+
+impl Responder for Trash {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        json_response(req, &self)
+    }
+}
+
+impl Responder for User {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        HttpResponse::Created()
+            .content_type(ContentType::json())
+            .body(json_body(req, &self))
+    }
+}
+
+impl Responder for Session {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        HttpResponse::Created()
+            .content_type(ContentType::json())
+            .body(json_body(req, &self))
+    }
+}
+
+/// 200 OK with `value` as JSON in the shape of the request's version
+fn json_response<T: Serialize>(req: &HttpRequest, value: &T) -> HttpResponse<BoxBody> {
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(json_body(req, value))
+}
+
+/// JSON response for a single entity with its ETag. Answers 304 Not Modified to a GET
+/// whose If-None-Match already has the current version.
+fn entity_response<T: Serialize>(req: &HttpRequest, entity: &T, etag: EntityTag) -> HttpResponse<BoxBody> {
+    if req.method() == Method::GET || req.method() == Method::HEAD {
+        let not_modified = match req.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            None => false,
+        };
+        if not_modified {
+            return HttpResponse::NotModified()
+                .insert_header((ETAG, etag))
+                .finish();
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .insert_header((ETAG, etag))
+        .body(json_body(req, entity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ http::{ header::IF_NONE_MATCH, StatusCode }, test::TestRequest };
+
+    #[test]
+    fn answers_not_modified_for_current_version() {
+        let etag = entity_tag(1, 100);
+        let req = TestRequest::get().insert_header((IF_NONE_MATCH, "\"1-100\"")).to_http_request();
+        let res = entity_response(&req, &"body", etag.clone());
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let req = TestRequest::get().insert_header((IF_NONE_MATCH, "\"1-99\"")).to_http_request();
+        let res = entity_response(&req, &"body", etag);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(ETAG).unwrap(), "\"1-100\"");
+    }
+}
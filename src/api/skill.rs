@@ -1,78 +1,151 @@
 use actix_web::http::header::ContentType;
 use actix_web::{
-    delete, get, post, put,
-    web, HttpResponse, Responder,
+    delete, get, patch, post, put,
+    web, HttpRequest, HttpResponse, Responder,
 };
-use crate::db::{ execute, Pool, Query, QueryResult, };
-use crate::model::skill::{ SkillFields, SkillList };
-use crate::model::task::{TaskFields, TaskList};
-use crate::{AppError, IdType};
+use crate::api::body::Body;
+use crate::etag::if_match;
+use crate::model::page::{ Page, PageParams };
+use crate::model::user::AuthUser;
+use crate::model::skill::{ Skill, SkillFields, SkillFilter, SkillPatch, SKILL_SORTABLE };
+use crate::model::task::{ Task, TaskFields };
+use crate::problem::Problem;
+use crate::progression::LevelCurve;
+use crate::repo::{ SkillRepo, TaskRepo };
+use crate::validation::Validate;
+use crate::IdType;
 
+#[utoipa::path(
+    tag = "skills",
+    params(PageParams, SkillFilter),
+    responses(
+        (status = 200, body = Page<Skill>),
+    ),
+)]
 #[get("/skills")]
-pub async fn get_skills(db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let query_result = execute(&db, Query::GetSkillList).await?;
-    match query_result {
-        QueryResult::SkillList(skill_list) => Ok(SkillList(skill_list)),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn get_skills(page: web::Query<PageParams>, filter: web::Query<SkillFilter>, repo: web::Data<dyn SkillRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let page = page.to_request(SKILL_SORTABLE)?;
+    Ok(repo.list_skills(&user, filter.into_inner(), page).await?)
 }
 
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill")),
+    responses(
+        (status = 200, body = Skill),
+        (status = 304, description = "Unchanged since the `If-None-Match` version"),
+        (status = 404, description = "Missing skill", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[get("/skills/{id}")]
-pub async fn get_skill(path: web::Path<IdType>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn get_skill(path: web::Path<IdType>, repo: web::Data<dyn SkillRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query_result = execute(&db, Query::GetSkill(id)).await?;
-    match query_result {
-        QueryResult::Skill(skill) => Ok(skill),
-        _ => Err(AppError::InternalError.into())
-    }
+    Ok(repo.get_skill(&user, id).await?)
 }
 
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill")),
+    responses(
+        (status = 200, body = Vec<Task>),
+    ),
+)]
 #[get("/skills/{id}/tasks")]
-pub async fn get_skill_tasks(path: web::Path<IdType>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn get_skill_tasks(path: web::Path<IdType>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query = Query::GetSkillTaskList(id);
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::TaskList(task_list) => Ok(TaskList(task_list)),
-        _ => Err(AppError::InternalError.into())
-    }
+    Ok(repo.skill_tasks(&user, id).await?)
 }
 
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill")),
+    request_body = TaskFields,
+    responses(
+        (status = 200, body = Task),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing skill", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[post("/skills/{id}/tasks")]
-pub async fn create_skill_task(path: web::Path<IdType>, form: web::Form<TaskFields>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn create_skill_task(path: web::Path<IdType>, body: Body<TaskFields>, repo: web::Data<dyn TaskRepo>, curve: web::Data<LevelCurve>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query = Query::CreateSkillTask(id, form.into_inner());
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Task(task) => Ok(task),
-        _ =>  Err(AppError::InternalError.into())
-    }
+    let fields = body.into_inner();
+    fields.validate()?;
+    Ok(repo.create_task(&user, id, fields, **curve).await?)
 }
 
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    request_body = SkillFields,
+    responses(
+        (status = 200, body = Skill),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing skill", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[put("/skills/{id}")]
-pub async fn update_skill(path: web::Path<IdType>, form: web::Form<SkillFields>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn update_skill(req: HttpRequest, path: web::Path<IdType>, body: Body<SkillFields>, repo: web::Data<dyn SkillRepo>, curve: web::Data<LevelCurve>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query = Query::UpdateSkill(id, form.into_inner());
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Skill(skill) => Ok(skill),
-        _ => Err(AppError::InternalError.into())
-    }
+    let fields = body.into_inner();
+    fields.validate()?;
+    Ok(repo.update_skill(&user, id, fields.into(), **curve, if_match(&req)).await?)
 }
 
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    request_body = SkillPatch,
+    responses(
+        (status = 200, body = Skill),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing skill", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[patch("/skills/{id}")]
+pub async fn patch_skill(req: HttpRequest, path: web::Path<IdType>, body: Body<SkillPatch>, repo: web::Data<dyn SkillRepo>, curve: web::Data<LevelCurve>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id = path.into_inner();
+    let patch = body.into_inner();
+    patch.validate()?;
+    Ok(repo.update_skill(&user, id, patch, **curve, if_match(&req)).await?)
+}
+
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    responses(
+        (status = 200, description = "Moved to the trash with its tasks", body = String, content_type = "text/plain"),
+        (status = 404, description = "Missing skill", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[delete("/skills/{id}")]
-pub async fn delete_skill(path: web::Path<IdType>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn delete_skill(req: HttpRequest, path: web::Path<IdType>, repo: web::Data<dyn SkillRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let id = path.into_inner();
+    repo.delete_skill(&user, id, if_match(&req)).await?;
+    let msg = format!("Skill with id {} is moved to the trash", id);
+    let res = HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(msg);
+    Ok(res)
+}
+
+#[utoipa::path(
+    tag = "skills",
+    params(("id" = u64, Path, description = "Id of the skill")),
+    responses(
+        (status = 200, body = Skill),
+        (status = 404, description = "Not in the trash", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Its character is in the trash", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[post("/skills/{id}/restore")]
+pub async fn restore_skill(path: web::Path<IdType>, repo: web::Data<dyn SkillRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query = Query::DeleteSkill(id);
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Success => {
-            let msg = format!("Skill with id {} is deleted", id);
-            let res = HttpResponse::Ok()
-                .content_type(ContentType::plaintext())
-                .body(msg);
-            Ok(res)
-        }
-        _ => Err(AppError::InternalError.into())
-    }
+    Ok(repo.restore_skill(&user, id).await?)
 }
@@ -1,55 +1,131 @@
 use actix_web::{
-    delete, get, http::header::ContentType, put, web, HttpResponse, Responder
+    delete, get, http::header::ContentType, patch, post, put, web, HttpRequest, HttpResponse, Responder
 };
-use crate::{ db::{ execute, Pool, Query, QueryResult }, model::task::TaskFields, AppError, IdType };
-use crate::model::task::{ TaskList };
+use crate::{ api::body::Body, etag::if_match, model::task::TaskFields, repo::TaskRepo, IdType };
+use crate::model::page::{ Page, PageParams };
+use crate::model::user::AuthUser;
+use crate::model::task::{ Task, TaskFilter, TaskHistory, TaskPatch, TASK_SORTABLE };
+use crate::problem::Problem;
+use crate::progression::LevelCurve;
+use crate::validation::Validate;
 
 
+#[utoipa::path(
+    tag = "tasks",
+    params(PageParams, TaskFilter),
+    responses(
+        (status = 200, body = Page<Task>),
+    ),
+)]
 #[get("/tasks")]
-pub async fn get_tasks(db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
-    let query = Query::GetTaskList;
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::TaskList(task_list) => Ok(TaskList(task_list)),
-        _ => Err(AppError::InternalError.into())
-    }
+pub async fn get_tasks(page: web::Query<PageParams>, filter: web::Query<TaskFilter>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let page = page.to_request(TASK_SORTABLE)?;
+    let filter = filter.into_inner();
+    filter.validate()?;
+    Ok(repo.list_tasks(&user, filter, page).await?)
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    params(("id" = u64, Path, description = "Id of the task")),
+    responses(
+        (status = 200, body = Task),
+        (status = 304, description = "Unchanged since the `If-None-Match` version"),
+        (status = 404, description = "Missing task", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[get("/tasks/{id}")]
-pub async fn get_task(path: web::Path<IdType>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn get_task(path: web::Path<IdType>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let id = path.into_inner();
-    let query = Query::GetTask(id);
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Task(task) => Ok(task),
-        _ => Err(AppError::InternalError.into())
-    }
+    Ok(repo.get_task(&user, id).await?)
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    params(("id" = u64, Path, description = "Id of the task"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    request_body = TaskFields,
+    responses(
+        (status = 200, body = Task),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing task", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[put("/tasks/{id}")]
-pub async fn update_task(path: web::Path<IdType>, form: web::Form<TaskFields>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn update_task(req: HttpRequest, path: web::Path<IdType>, body: Body<TaskFields>, repo: web::Data<dyn TaskRepo>, curve: web::Data<LevelCurve>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let task_id = path.into_inner();
-    let query = Query::UpdateTask(task_id, form.into_inner());
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Task(task) => Ok(task),
-        _ => Err(AppError::InternalError.into())
-    }
+    let fields = body.into_inner();
+    fields.validate()?;
+    Ok(repo.update_task(&user, task_id, fields.into(), **curve, if_match(&req)).await?)
 }
 
+#[utoipa::path(
+    tag = "tasks",
+    params(("id" = u64, Path, description = "Id of the task"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    request_body = TaskPatch,
+    responses(
+        (status = 200, body = Task),
+        (status = 400, description = "Broken field rules", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Missing task", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[patch("/tasks/{id}")]
+pub async fn patch_task(req: HttpRequest, path: web::Path<IdType>, body: Body<TaskPatch>, repo: web::Data<dyn TaskRepo>, curve: web::Data<LevelCurve>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let task_id = path.into_inner();
+    let patch = body.into_inner();
+    patch.validate()?;
+    Ok(repo.update_task(&user, task_id, patch, **curve, if_match(&req)).await?)
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    params(("id" = u64, Path, description = "Id of the task"),
+        ("If-Match" = Option<String>, Header, description = "Only change it while it still has this ETag")),
+    responses(
+        (status = 200, description = "Moved to the trash", body = String, content_type = "text/plain"),
+        (status = 404, description = "Missing task", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the `If-Match` version", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
 #[delete("/tasks/{id}")]
-pub async fn delete_task(path: web::Path<IdType>, db: web::Data<Pool>) -> Result<impl Responder, actix_web::Error> {
+pub async fn delete_task(req: HttpRequest, path: web::Path<IdType>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let task_id = path.into_inner();
+    repo.delete_task(&user, task_id, if_match(&req)).await?;
+    let msg = format!("Task with id {} is moved to the trash", task_id);
+    let res = HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(msg);
+    Ok(res)
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    params(("id" = u64, Path, description = "Id of the task")),
+    responses(
+        (status = 200, body = Task),
+        (status = 404, description = "Not in the trash", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Its skill is in the trash", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[post("/tasks/{id}/restore")]
+pub async fn restore_task(path: web::Path<IdType>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    let task_id = path.into_inner();
+    Ok(repo.restore_task(&user, task_id).await?)
+}
+
+#[utoipa::path(
+    tag = "tasks",
+    params(("id" = u64, Path, description = "Id of the task")),
+    responses(
+        (status = 200, body = TaskHistory),
+        (status = 404, description = "Missing task", body = Problem, content_type = "application/problem+json"),
+    ),
+)]
+#[get("/tasks/{id}/history")]
+pub async fn get_task_history(path: web::Path<IdType>, repo: web::Data<dyn TaskRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
     let task_id = path.into_inner();
-    let query = Query::DeleteTask(task_id);
-    let query_result = execute(&db, query).await?;
-    match query_result {
-        QueryResult::Success => {
-            let msg = format!("Task with id {} is deleted", task_id);
-            let res = HttpResponse::Ok()
-                .content_type(ContentType::plaintext())
-                .body(msg);
-            Ok(res)
-        },
-        _ => Err(AppError::InternalError.into())
-    }
+    Ok(repo.task_history(&user, task_id).await?)
 }
@@ -0,0 +1,554 @@
+use std::sync::Arc;
+use actix_web::{
+    http::{ header::{ AUTHORIZATION, ETAG, IF_MATCH }, Method, StatusCode },
+    test::{ call_service, init_service, read_body, TestRequest },
+    web, App,
+};
+use serde_json::{ json, Value };
+use crate::api::{ self, body };
+use crate::config::ApiConfig;
+use crate::db::{ test_pool, test_user, user::test_session };
+use crate::progression::LevelCurve;
+use crate::repo::{ self, Repo, SqliteRepo };
+
+/// Every test below runs once for each storage backend of the build, in a module named after
+/// the backend
+macro_rules! backend_tests {
+    ($($test:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[actix_web::test]
+                async fn $test() {
+                    super::$test(super::TestApi::sqlite()).await
+                }
+            )*
+        }
+
+        #[cfg(feature = "postgres")]
+        mod postgres {
+            $(
+                #[actix_web::test]
+                async fn $test() {
+                    super::$test(super::TestApi::postgres()).await
+                }
+            )*
+        }
+    };
+}
+
+backend_tests!(
+    registers_logs_in_and_out,
+    routes_need_a_session,
+    character_routes,
+    character_children_routes,
+    skill_routes,
+    hand_set_levels_keep_growing,
+    task_routes,
+    missing_entities_are_not_found,
+    invalid_input_is_rejected,
+    reset_deletes_the_users_characters,
+    trash_and_restore,
+    character_activity,
+    recurring_tasks,
+    character_includes,
+    v1_responses_keep_their_bytes,
+    versions_share_the_routes,
+    hello_and_echo,
+);
+
+/// The api over a database of its own, with a logged in user
+struct TestApi {
+    repo: Arc<dyn Repo>,
+    token: String,
+}
+
+/// Status, ETag and body of a response, the body as JSON when it is JSON and as sent in `text`
+struct Response {
+    status: StatusCode,
+    etag: Option<String>,
+    body: Value,
+    text: String,
+}
+
+impl TestApi {
+    /// On an in-memory SQLite database
+    fn sqlite() -> Self {
+        let pool = test_pool();
+        let token = {
+            let conn = pool.get().unwrap();
+            let user_id = test_user(&conn, "hero");
+            test_session(&conn, user_id)
+        };
+        TestApi { repo: Arc::new(SqliteRepo::new(pool)), token }
+    }
+
+    /// On a schema of its own in the throwaway PostgreSQL database
+    #[cfg(feature = "postgres")]
+    fn postgres() -> Self {
+        let repo = repo::postgres::test_repo();
+        let (_, token) = repo::postgres::test_session(&repo, "hero");
+        TestApi { repo: Arc::new(repo), token }
+    }
+
+    /// Send `req` as the logged in user
+    async fn call(&self, req: TestRequest) -> Response {
+        self.call_as(req.insert_header((AUTHORIZATION, format!("Bearer {}", self.token)))).await
+    }
+
+    async fn call_as(&self, req: TestRequest) -> Response {
+        let app = init_service(
+            App::new()
+                .configure(repo::configure(self.repo.clone()))
+                .app_data(web::Data::new(LevelCurve::default()))
+                .app_data(body::json_config())
+                .app_data(body::form_config())
+                .configure(api::config(&ApiConfig::default()))
+        ).await;
+        let res = call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let etag = res.headers().get(ETAG).map(|etag| etag.to_str().unwrap().to_string());
+        let bytes = read_body(res).await;
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        let body = serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(text.clone()));
+        Response { status, etag, body, text }
+    }
+
+    async fn send(&self, method: Method, uri: &str, body: Option<Value>) -> Response {
+        let req = TestRequest::default().method(method).uri(uri);
+        match body {
+            Some(body) => self.call(req.set_json(body)).await,
+            None => self.call(req).await,
+        }
+    }
+
+    async fn create_character(&self) -> u64 {
+        let res = self.send(Method::POST, "/api/characters", Some(json!({ "name": "hero", "avatar": "", "notes": "", "quote": "" }))).await;
+        assert_eq!(res.status, StatusCode::OK);
+        res.body["id"].as_u64().unwrap()
+    }
+
+    async fn create_skill(&self, character_id: u64) -> u64 {
+        let uri = format!("/api/characters/{}/skills", character_id);
+        let res = self.send(Method::POST, &uri, Some(json!({ "name": "guitar", "progress": 0, "level": 0 }))).await;
+        assert_eq!(res.status, StatusCode::OK);
+        res.body["id"].as_u64().unwrap()
+    }
+
+    async fn create_task(&self, skill_id: u64, completed: u8) -> u64 {
+        let uri = format!("/api/skills/{}/tasks", skill_id);
+        let res = self.send(Method::POST, &uri, Some(json!({ "name": "scales", "description": "", "completed": completed }))).await;
+        assert_eq!(res.status, StatusCode::OK);
+        res.body["id"].as_u64().unwrap()
+    }
+}
+
+/// Names of the fields a validation error complains about
+fn invalid_fields(res: &Response) -> Vec<&str> {
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    assert_eq!(res.body["type"], "validation_failed");
+    res.body["fields"].as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect()
+}
+
+async fn registers_logs_in_and_out(api: TestApi) {
+    let credentials = json!({ "username": "alice", "password": "correct horse" });
+
+    let res = api.call_as(TestRequest::post().uri("/api/users").set_json(&credentials)).await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(res.body["username"], "alice");
+    let res = api.call_as(TestRequest::post().uri("/api/users").set_json(&credentials)).await;
+    assert_eq!((res.status, &res.body["type"]), (StatusCode::CONFLICT, &json!("conflict")));
+    let res = api.call_as(TestRequest::post().uri("/api/users").set_json(json!({ "username": "a", "password": "short" }))).await;
+    assert_eq!(invalid_fields(&res), vec!["username", "password"]);
+
+    let res = api.call_as(TestRequest::post().uri("/api/sessions").set_json(json!({ "username": "alice", "password": "wrong horse" }))).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    let res = api.call_as(TestRequest::post().uri("/api/sessions").set_json(&credentials)).await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let token = res.body["token"].as_str().unwrap().to_string();
+
+    let bearer = format!("Bearer {}", token);
+    let res = api.call_as(TestRequest::get().uri("/api/characters").insert_header((AUTHORIZATION, bearer.as_str()))).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let res = api.call_as(TestRequest::delete().uri("/api/sessions").insert_header((AUTHORIZATION, bearer.as_str()))).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    let res = api.call_as(TestRequest::get().uri("/api/characters").insert_header((AUTHORIZATION, bearer.as_str()))).await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+async fn routes_need_a_session(api: TestApi) {
+    for (method, uri) in [(Method::GET, "/api/characters"), (Method::GET, "/api/skills/1"), (Method::DELETE, "/api/tasks/1"), (Method::POST, "/api/reset_db"), (Method::GET, "/api/trash")] {
+        let res = api.call_as(TestRequest::default().method(method).uri(uri)).await;
+        assert_eq!((res.status, &res.body["type"]), (StatusCode::UNAUTHORIZED, &json!("unauthorized")), "{}", uri);
+    }
+}
+
+async fn character_routes(api: TestApi) {
+    let id = api.create_character().await;
+    let uri = format!("/api/characters/{}", id);
+
+    let res = api.send(Method::GET, &uri, None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["fields"]["name"], "hero");
+    assert_eq!(res.etag.unwrap(), format!("\"{}-{}\"", id, res.body["updated_at"]));
+
+    let res = api.send(Method::GET, "/api/characters?sort=-name&limit=10", None).await;
+    assert_eq!(res.body["total"], 1);
+    assert_eq!(res.body["items"][0]["id"], id);
+
+    let res = api.send(Method::PUT, &uri, Some(json!({ "name": "heroine", "avatar": "", "notes": "", "quote": "" }))).await;
+    assert_eq!(res.body["fields"]["name"], "heroine");
+
+    let stale = format!("\"{}-0\"", id);
+    let req = TestRequest::patch().uri(&uri).insert_header((IF_MATCH, stale.as_str())).set_json(json!({ "quote": "onwards" }));
+    assert_eq!(api.call(req).await.status, StatusCode::PRECONDITION_FAILED);
+    let res = api.send(Method::PATCH, &uri, Some(json!({ "quote": "onwards" }))).await;
+    assert_eq!(res.body["fields"]["name"], "heroine");
+    assert_eq!(res.body["fields"]["quote"], "onwards");
+
+    let res = api.send(Method::DELETE, &uri, None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(api.send(Method::GET, &uri, None).await.status, StatusCode::NOT_FOUND);
+}
+
+async fn character_children_routes(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    api.create_task(skill_id, 0).await;
+
+    let res = api.send(Method::GET, &format!("/api/characters/{}/skills", character_id), None).await;
+    assert_eq!(res.body.as_array().unwrap().len(), 1);
+    let res = api.send(Method::GET, &format!("/api/characters/{}/tasks", character_id), None).await;
+    assert_eq!(res.body.as_array().unwrap().len(), 1);
+}
+
+async fn skill_routes(api: TestApi) {
+    let character_id = api.create_character().await;
+    let id = api.create_skill(character_id).await;
+    let uri = format!("/api/skills/{}", id);
+
+    let res = api.send(Method::GET, &uri, None).await;
+    assert_eq!(res.body["character_id"], character_id);
+    let res = api.send(Method::GET, &format!("/api/skills?character_id={}", character_id), None).await;
+    assert_eq!(res.body["total"], 1);
+
+    let res = api.send(Method::PUT, &uri, Some(json!({ "name": "piano", "progress": 0, "level": 0 }))).await;
+    assert_eq!(res.body["fields"]["name"], "piano");
+    let res = api.send(Method::PATCH, &uri, Some(json!({ "name": "drums" }))).await;
+    assert_eq!(res.body["fields"]["name"], "drums");
+
+    // completing a task awards XP
+    let task_id = api.create_task(id, 1).await;
+    let res = api.send(Method::GET, &format!("{}/tasks", uri), None).await;
+    assert_eq!(res.body[0]["id"], task_id);
+    let res = api.send(Method::GET, &uri, None).await;
+    assert_eq!(res.body["xp"], LevelCurve::default().task_xp);
+
+    assert_eq!(api.send(Method::DELETE, &uri, None).await.status, StatusCode::OK);
+    assert_eq!(api.send(Method::GET, &uri, None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.send(Method::GET, &format!("/api/tasks/{}", task_id), None).await.status, StatusCode::NOT_FOUND);
+}
+
+async fn hand_set_levels_keep_growing(api: TestApi) {
+    let curve = LevelCurve::default();
+    let character_id = api.create_character().await;
+    let res = api.send(Method::POST, &format!("/api/characters/{}/skills", character_id), Some(json!({ "name": "guitar", "progress": 50, "level": 2 }))).await;
+    assert_eq!(res.body["xp"], curve.xp_for(2, 50));
+    let uri = format!("/api/skills/{}", res.body["id"]);
+
+    let res = api.send(Method::PATCH, &uri, Some(json!({ "level": 5 }))).await;
+    assert_eq!(res.body["xp"], curve.xp_for(5, 50));
+    assert_eq!((&res.body["fields"]["level"], &res.body["fields"]["progress"]), (&json!(5), &json!(50)));
+
+    // a completed task adds to the level set by hand instead of starting over from 0 XP
+    let skill_id = res.body["id"].as_u64().unwrap();
+    api.create_task(skill_id, 1).await;
+    let res = api.send(Method::GET, &uri, None).await;
+    let xp = curve.xp_for(5, 50) + curve.task_xp;
+    assert_eq!(res.body["xp"], xp);
+    let (level, progress) = curve.level_for(xp);
+    assert_eq!((level, progress), (5, 51));
+    assert_eq!((&res.body["fields"]["level"], &res.body["fields"]["progress"]), (&json!(level), &json!(progress)));
+
+    // renaming keeps the XP
+    let res = api.send(Method::PUT, &uri, Some(json!({ "name": "bass", "progress": progress, "level": level }))).await;
+    assert_eq!(res.body["xp"], xp);
+}
+
+async fn task_routes(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let id = api.create_task(skill_id, 0).await;
+    let uri = format!("/api/tasks/{}", id);
+
+    let res = api.send(Method::GET, &uri, None).await;
+    assert_eq!(res.body["skill_id"], skill_id);
+    let res = api.send(Method::GET, "/api/tasks?completed=0", None).await;
+    assert_eq!(res.body["total"], 1);
+    let res = api.send(Method::GET, "/api/tasks?completed=1", None).await;
+    assert_eq!(res.body["total"], 0);
+
+    let res = api.send(Method::PUT, &uri, Some(json!({ "name": "arpeggios", "description": "", "completed": 1 }))).await;
+    assert_eq!(res.body["fields"]["name"], "arpeggios");
+    let res = api.send(Method::PATCH, &uri, Some(json!({ "completed": 0 }))).await;
+    assert_eq!(res.body["fields"]["completed"], 0);
+    assert_eq!(api.send(Method::GET, &format!("/api/skills/{}", skill_id), None).await.body["xp"], 0);
+
+    assert_eq!(api.send(Method::DELETE, &uri, None).await.status, StatusCode::OK);
+    assert_eq!(api.send(Method::GET, &uri, None).await.status, StatusCode::NOT_FOUND);
+}
+
+async fn missing_entities_are_not_found(api: TestApi) {
+    let character = json!({ "name": "hero", "avatar": "", "notes": "", "quote": "" });
+    let skill = json!({ "name": "guitar", "progress": 0, "level": 0 });
+    let task = json!({ "name": "scales", "description": "", "completed": 0 });
+    let requests = [
+        (Method::GET, "/api/characters/42", None),
+        (Method::PUT, "/api/characters/42", Some(character)),
+        (Method::PATCH, "/api/characters/42", Some(json!({}))),
+        (Method::DELETE, "/api/characters/42", None),
+        (Method::GET, "/api/characters/42/skills", None),
+        (Method::POST, "/api/characters/42/skills", Some(skill.clone())),
+        (Method::GET, "/api/characters/42/tasks", None),
+        (Method::GET, "/api/characters/42/activity", None),
+        (Method::GET, "/api/skills/42", None),
+        (Method::PUT, "/api/skills/42", Some(skill)),
+        (Method::PATCH, "/api/skills/42", Some(json!({}))),
+        (Method::DELETE, "/api/skills/42", None),
+        (Method::GET, "/api/skills/42/tasks", None),
+        (Method::POST, "/api/skills/42/tasks", Some(task.clone())),
+        (Method::GET, "/api/tasks/42", None),
+        (Method::PUT, "/api/tasks/42", Some(task)),
+        (Method::PATCH, "/api/tasks/42", Some(json!({}))),
+        (Method::DELETE, "/api/tasks/42", None),
+        (Method::POST, "/api/characters/42/restore", None),
+        (Method::POST, "/api/skills/42/restore", None),
+        (Method::POST, "/api/tasks/42/restore", None),
+        (Method::GET, "/api/tasks/42/history", None),
+    ];
+    for (method, uri, body) in requests {
+        let res = api.send(method.clone(), uri, body).await;
+        assert_eq!((res.status, &res.body["type"]), (StatusCode::NOT_FOUND, &json!("not_found")), "{} {}", method, uri);
+    }
+}
+
+async fn invalid_input_is_rejected(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let task_id = api.create_task(skill_id, 0).await;
+
+    let res = api.send(Method::POST, "/api/characters", Some(json!({ "name": "", "avatar": "", "notes": "", "quote": "" }))).await;
+    assert_eq!(invalid_fields(&res), vec!["name"]);
+    let res = api.send(Method::PATCH, &format!("/api/characters/{}", character_id), Some(json!({ "name": "" }))).await;
+    assert_eq!(invalid_fields(&res), vec!["name"]);
+    let res = api.send(Method::GET, "/api/characters/not-an-id/skills", None).await;
+    assert_eq!(invalid_fields(&res), vec!["id"]);
+    let res = api.send(Method::POST, &format!("/api/characters/{}/skills", character_id), Some(json!({ "name": "guitar", "progress": 101, "level": 0 }))).await;
+    assert_eq!(invalid_fields(&res), vec!["progress"]);
+    let res = api.send(Method::PUT, &format!("/api/skills/{}", skill_id), Some(json!({ "name": "", "progress": 0, "level": 0 }))).await;
+    assert_eq!(invalid_fields(&res), vec!["name"]);
+    let res = api.send(Method::POST, &format!("/api/skills/{}/tasks", skill_id), Some(json!({ "name": "scales", "description": "", "completed": 2 }))).await;
+    assert_eq!(invalid_fields(&res), vec!["completed"]);
+    let res = api.send(Method::PATCH, &format!("/api/tasks/{}", task_id), Some(json!({ "completed": 2 }))).await;
+    assert_eq!(invalid_fields(&res), vec!["completed"]);
+    let res = api.send(Method::GET, "/api/tasks?limit=0&sort=owner", None).await;
+    assert_eq!(invalid_fields(&res), vec!["limit", "sort"]);
+    let res = api.send(Method::GET, "/api/skills?cursor=nope", None).await;
+    assert_eq!(invalid_fields(&res), vec!["cursor"]);
+
+    let req = TestRequest::post().uri("/api/characters").insert_header(("Content-Type", "application/json")).set_payload("{ not json");
+    let res = api.call(req).await;
+    assert_eq!((res.status, &res.body["type"]), (StatusCode::BAD_REQUEST, &json!("invalid_body")));
+}
+
+async fn reset_deletes_the_users_characters(api: TestApi) {
+    api.create_character().await;
+    let res = api.send(Method::POST, "/api/reset_db", None).await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(api.send(Method::GET, "/api/characters", None).await.body["total"], 0);
+}
+
+async fn trash_and_restore(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let task_id = api.create_task(skill_id, 0).await;
+    let trashed_task_id = api.create_task(skill_id, 0).await;
+    let character_uri = format!("/api/characters/{}", character_id);
+    let skill_uri = format!("/api/skills/{}", skill_id);
+    let task_uri = format!("/api/tasks/{}", trashed_task_id);
+
+    // a task trashed on its own stays there when its character comes back
+    assert_eq!(api.send(Method::DELETE, &task_uri, None).await.status, StatusCode::OK);
+    assert_eq!(api.send(Method::DELETE, &character_uri, None).await.status, StatusCode::OK);
+    for uri in [&character_uri, &skill_uri, &format!("/api/tasks/{}", task_id)] {
+        assert_eq!(api.send(Method::GET, uri, None).await.status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+    assert_eq!(api.send(Method::GET, "/api/characters", None).await.body["total"], 0);
+    assert_eq!(api.send(Method::GET, "/api/tasks?include_trashed=true", None).await.body["total"], 2);
+
+    let res = api.send(Method::GET, "/api/trash", None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["characters"][0]["id"], character_id);
+    assert!(res.body["characters"][0]["deleted_at"].is_u64());
+    assert_eq!((res.body["skills"].as_array().unwrap().len(), res.body["tasks"].as_array().unwrap().len()), (0, 0));
+
+    // children come back with their parent only
+    let res = api.send(Method::POST, &format!("{}/restore", skill_uri), None).await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+    let res = api.send(Method::POST, &format!("{}/restore", character_uri), None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body.get("deleted_at").is_none());
+    let res = api.send(Method::GET, &format!("{}/tasks", character_uri), None).await;
+    assert_eq!(res.body.as_array().unwrap().len(), 1);
+    assert_eq!(res.body[0]["id"], task_id);
+
+    let res = api.send(Method::GET, "/api/trash", None).await;
+    assert_eq!(res.body["tasks"][0]["id"], trashed_task_id);
+    assert_eq!(api.send(Method::POST, &format!("{}/restore", task_uri), None).await.status, StatusCode::OK);
+    // only trashed entities can be restored
+    assert_eq!(api.send(Method::POST, &format!("{}/restore", task_uri), None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.send(Method::GET, &format!("{}/tasks", skill_uri), None).await.body.as_array().unwrap().len(), 2);
+}
+
+async fn character_activity(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let task_id = api.create_task(skill_id, 0).await;
+    let task_uri = format!("/api/tasks/{}", task_id);
+    api.send(Method::PATCH, &task_uri, Some(json!({ "completed": 1 }))).await;
+    // saving without changes is left out
+    api.send(Method::PATCH, &task_uri, Some(json!({ "completed": 1 }))).await;
+    api.send(Method::DELETE, &task_uri, None).await;
+    let uri = format!("/api/characters/{}/activity", character_id);
+
+    let res = api.send(Method::GET, &uri, None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let entries: Vec<(&str, &str)> = res.body["items"].as_array().unwrap().iter()
+        .map(|entry| (entry["entity"].as_str().unwrap(), entry["action"].as_str().unwrap()))
+        .collect();
+    assert_eq!(entries, vec![
+        ("character", "create"), ("skill", "create"), ("task", "create"),
+        ("task", "update"), ("skill", "update"), ("task", "delete"),
+    ]);
+    let completed = &res.body["items"][3];
+    assert_eq!((completed["entity_id"].as_u64(), completed["actor_id"].is_u64()), (Some(task_id), true));
+    assert_eq!(completed["changes"], json!({ "completed": { "before": 0, "after": 1 } }));
+    assert_eq!(res.body["items"][4]["changes"]["xp"]["after"], LevelCurve::default().task_xp);
+
+    let res = api.send(Method::GET, &format!("{}?entity=skill&sort=-created_at&limit=1", uri), None).await;
+    assert_eq!((res.body["total"].as_u64(), res.body["items"][0]["action"].as_str()), (Some(2), Some("update")));
+    let created_at = res.body["items"][0]["created_at"].as_u64().unwrap();
+    let res = api.send(Method::GET, &format!("{}?until={}", uri, created_at + 1), None).await;
+    assert_eq!(res.body["total"], 5);
+    let res = api.send(Method::GET, &format!("{}?since={}&until=0", uri, created_at), None).await;
+    assert_eq!(invalid_fields(&res), vec!["until"]);
+}
+
+async fn recurring_tasks(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let uri = format!("/api/skills/{}/tasks", skill_id);
+    let task = json!({ "name": "scales", "description": "", "completed": 1, "recurrence": { "kind": "rrule", "rule": "FREQ=WEEKLY;BYDAY=MO,XX" } });
+    let res = api.send(Method::POST, &uri, Some(task)).await;
+    assert_eq!(invalid_fields(&res), vec!["recurrence"]);
+
+    let recurrence = json!({ "kind": "weekly", "weekdays": ["mon", "thu"] });
+    let task = json!({ "name": "scales", "description": "", "completed": 1, "recurrence": recurrence });
+    let res = api.send(Method::POST, &uri, Some(task)).await;
+    assert_eq!((res.status, &res.body["fields"]["recurrence"]), (StatusCode::OK, &recurrence));
+    let reopens_at = res.body["reopens_at"].as_u64().unwrap();
+    let task_uri = format!("/api/tasks/{}", res.body["id"]);
+
+    let res = api.send(Method::GET, &format!("{}/history", task_uri), None).await;
+    assert_eq!(res.body["completions"].as_array().unwrap().len(), 1);
+    assert!(res.body["completions"][0]["occurrence"].as_u64().unwrap() < reopens_at);
+
+    assert_eq!(api.repo.reopen_tasks(reopens_at).await.unwrap(), 1);
+    let res = api.send(Method::GET, &task_uri, None).await;
+    assert_eq!((&res.body["fields"]["completed"], res.body.get("reopens_at")), (&json!(0), None));
+    let res = api.send(Method::GET, &format!("/api/skills/{}", skill_id), None).await;
+    assert_eq!(res.body["xp"], LevelCurve::default().task_xp);
+
+    // null turns it back into a one-off task, leaving the field out keeps it
+    let res = api.send(Method::PATCH, &task_uri, Some(json!({ "name": "arpeggios" }))).await;
+    assert_eq!(res.body["fields"]["recurrence"], recurrence);
+    let res = api.send(Method::PATCH, &task_uri, Some(json!({ "recurrence": null, "completed": 1 }))).await;
+    assert_eq!((&res.body["fields"]["recurrence"], res.body.get("reopens_at")), (&Value::Null, None));
+}
+
+async fn character_includes(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let task_id = api.create_task(skill_id, 0).await;
+    let trashed_id = api.create_task(skill_id, 0).await;
+    api.send(Method::DELETE, &format!("/api/tasks/{}", trashed_id), None).await;
+    let empty_id = api.create_skill(api.create_character().await).await;
+    let uri = format!("/api/characters/{}", character_id);
+
+    let res = api.send(Method::GET, &uri, None).await;
+    assert_eq!((res.body["id"].as_u64(), res.body.get("skills")), (Some(character_id), None));
+    let etag = res.etag;
+
+    let res = api.send(Method::GET, &format!("{}?include=skills", uri), None).await;
+    assert_eq!((res.body["skills"][0]["id"].as_u64(), res.body["skills"][0].get("tasks")), (Some(skill_id), None));
+
+    let res = api.send(Method::GET, &format!("{}?include=skills.tasks", uri), None).await;
+    assert_eq!(res.etag, etag);
+    let tasks = res.body["skills"][0]["tasks"].as_array().unwrap();
+    assert_eq!(tasks.iter().map(|task| task["id"].as_u64().unwrap()).collect::<Vec<_>>(), vec![task_id]);
+
+    let res = api.send(Method::GET, "/api/characters?include=skills,skills.tasks", None).await;
+    let skills: Vec<(u64, usize)> = res.body["items"].as_array().unwrap().iter()
+        .flat_map(|character| character["skills"].as_array().unwrap())
+        .map(|skill| (skill["id"].as_u64().unwrap(), skill["tasks"].as_array().unwrap().len()))
+        .collect();
+    assert_eq!(skills, vec![(skill_id, 1), (empty_id, 0)]);
+
+    let res = api.send(Method::GET, &format!("{}?include=tasks", uri), None).await;
+    assert_eq!(invalid_fields(&res), vec!["include"]);
+}
+
+async fn v1_responses_keep_their_bytes(api: TestApi) {
+    let character_id = api.create_character().await;
+    let skill_id = api.create_skill(character_id).await;
+    let task_id = api.create_task(skill_id, 0).await;
+
+    let res = api.send(Method::GET, &format!("/api/v1/characters/{}", character_id), None).await;
+    let (created, updated) = (&res.body["created_at"], &res.body["updated_at"]);
+    let character = format!(r#"{{"id":{},"fields":{{"name":"hero","avatar":"","notes":"","quote":""}},"created_at":{},"updated_at":{}}}"#, character_id, created, updated);
+    assert_eq!(res.text, character);
+
+    let res = api.send(Method::GET, &format!("/api/v1/skills/{}", skill_id), None).await;
+    let (created, updated) = (&res.body["created_at"], &res.body["updated_at"]);
+    let skill = format!(r#"{{"id":{},"fields":{{"name":"guitar","progress":0,"level":0}},"xp":0,"character_id":{},"created_at":{},"updated_at":{}}}"#, skill_id, character_id, created, updated);
+    assert_eq!(res.text, skill);
+
+    let res = api.send(Method::GET, &format!("/api/v1/tasks/{}", task_id), None).await;
+    let (created, updated) = (&res.body["created_at"], &res.body["updated_at"]);
+    let task = format!(r#"{{"id":{},"fields":{{"name":"scales","description":"","completed":0,"recurrence":null}},"skill_id":{},"created_at":{},"updated_at":{}}}"#, task_id, skill_id, created, updated);
+    assert_eq!(res.text, task);
+
+    let res = api.send(Method::GET, "/api/v1/tasks", None).await;
+    assert_eq!(res.text, format!(r#"{{"items":[{}],"total":1,"next_cursor":null}}"#, task));
+}
+
+async fn versions_share_the_routes(api: TestApi) {
+    let character_id = api.create_character().await;
+    let v1 = api.send(Method::GET, &format!("/api/v1/characters/{}", character_id), None).await;
+    let unversioned = api.send(Method::GET, &format!("/api/characters/{}", character_id), None).await;
+    assert_eq!(unversioned.text, v1.text);
+
+    let res = api.send(Method::GET, &format!("/api/v2/characters/{}", character_id), None).await;
+    assert_eq!((&res.body["id"], &res.body["name"], res.body.get("fields")), (&json!(character_id), &json!("hero"), None));
+    assert_eq!(res.etag, v1.etag);
+    let res = api.send(Method::POST, "/api/v2/characters", Some(json!({ "name": "sidekick", "avatar": "", "notes": "", "quote": "" }))).await;
+    assert_eq!(res.body["name"], "sidekick");
+    let res = api.send(Method::GET, "/api/v2/characters?sort=name", None).await;
+    assert_eq!(res.body["items"][1]["name"], "sidekick");
+}
+
+async fn hello_and_echo(api: TestApi) {
+    let res = api.call_as(TestRequest::get().uri("/api/")).await;
+    assert_eq!(res.body, "Hello World!");
+    let res = api.call_as(TestRequest::post().uri("/api/echo").set_payload("ping")).await;
+    assert_eq!(res.body, "ping");
+}
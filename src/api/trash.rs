@@ -0,0 +1,15 @@
+use actix_web::{ get, web, Responder };
+use crate::model::trash::Trash;
+use crate::model::user::AuthUser;
+use crate::repo::TrashRepo;
+
+#[utoipa::path(
+    tag = "trash",
+    responses(
+        (status = 200, body = Trash),
+    ),
+)]
+#[get("/trash")]
+pub async fn get_trash(repo: web::Data<dyn TrashRepo>, user: AuthUser) -> Result<impl Responder, actix_web::Error> {
+    Ok(repo.list_trash(&user).await?)
+}
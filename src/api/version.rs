@@ -0,0 +1,93 @@
+use actix_web::HttpRequest;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Versions of the API, each one mounted under `/api/<name>`. The paths right under `/api`,
+/// from before there were versions, are v1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiVersion {
+    V1,
+    /// Characters, skills and tasks with their `fields` next to their id
+    V2,
+}
+
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    /// Version of the scope `req` came in through
+    pub fn of(req: &HttpRequest) -> ApiVersion {
+        req.app_data::<ApiVersion>().copied().unwrap_or(ApiVersion::V1)
+    }
+
+    /// `value` as JSON in the shape of this version
+    pub fn to_json<T: Serialize>(self, value: &T) -> String {
+        match self {
+            ApiVersion::V1 => serde_json::to_string(value).unwrap(),
+            ApiVersion::V2 => {
+                let mut value = serde_json::to_value(value).unwrap();
+                flatten_fields(&mut value);
+                value.to_string()
+            },
+        }
+    }
+}
+
+/// JSON body of `value` in the shape of the version `req` asked for
+pub(super) fn json_body<T: Serialize>(req: &HttpRequest, value: &T) -> String {
+    ApiVersion::of(req).to_json(value)
+}
+
+/// Move every nested `fields` object up into the object holding it
+fn flatten_fields(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::Object(fields)) = map.remove("fields") {
+                map.extend(fields);
+            }
+            map.values_mut().for_each(flatten_fields);
+        },
+        Value::Array(items) => items.iter_mut().for_each(flatten_fields),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ test::{ call_service, init_service, TestRequest }, App };
+    use serde_json::json;
+    use crate::config::{ ApiConfig, DeprecationConfig };
+
+    #[test]
+    fn v2_flattens_fields() {
+        let page = json!({ "items": [{ "id": 1, "fields": { "name": "hero" }, "skills": [{ "id": 2, "fields": { "level": 3 } }] }], "total": 1 });
+        assert_eq!(ApiVersion::V1.to_json(&page), page.to_string());
+        let flat: Value = serde_json::from_str(&ApiVersion::V2.to_json(&page)).unwrap();
+        assert_eq!(flat, json!({ "items": [{ "id": 1, "name": "hero", "skills": [{ "id": 2, "level": 3 }] }], "total": 1 }));
+    }
+
+    #[actix_web::test]
+    async fn deprecated_versions_say_so() {
+        let mut config = ApiConfig::default();
+        let deprecation = DeprecationConfig { since: "2026-11-01".to_string(), sunset: "2027-05-01".to_string() };
+        config.deprecated.insert("unversioned".to_string(), deprecation);
+        let app = init_service(App::new().configure(crate::api::config(&config))).await;
+
+        let res = call_service(&app, TestRequest::get().uri("/api/").to_request()).await;
+        assert_eq!(res.headers().get("Deprecation").unwrap(), "@1793491200");
+        assert_eq!(res.headers().get("Sunset").unwrap(), "Sat, 01 May 2027 00:00:00 GMT");
+
+        for uri in ["/api/v1/", "/api/v2/"] {
+            let res = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert!(res.status().is_success(), "{}", uri);
+            assert!(res.headers().get("Deprecation").is_none(), "{}", uri);
+        }
+    }
+}
@@ -1,391 +1,472 @@
-use std::{thread::sleep, time::Duration};
-use actix_web::web;
-use serde::Serialize;
+use std::{ thread::sleep, time::{ Duration, Instant } };
+use rusqlite::{ ErrorCode, TransactionBehavior };
+use actix_web::{ http::header::IfMatch, web };
 use r2d2_sqlite::SqliteConnectionManager;
-use crate::model::character::{Character, CharacterFields, CharacterList};
-use crate::model::skill::{Skill, SkillFields, SkillList};
-use crate::model::task::{Task, TaskFields, TaskList};
-use crate::{ AppError, IdType};
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+use crate::{ AppError, IdType, TimeType };
+use crate::etag::{ entity_tag, if_match_passes };
+use crate::config::DatabaseConfig;
 
+pub mod activity;
 pub mod character;
 pub mod skill;
 pub mod task;
+pub mod migration;
+pub mod page;
+pub mod patch;
+pub mod trash;
+pub mod user;
+
+use user::AuthError;
+
+/// Shared connections, each query gets `query_timeout` to finish, waiting for a connection
+/// and for locks included
+#[derive(Clone)]
+pub struct Pool {
+    connections: r2d2::Pool<SqliteConnectionManager>,
+    query_timeout: Duration,
+}
+
+impl Pool {
+    /// Open the database file, creating it when missing. Fails when the first connection
+    /// cannot be opened or set up.
+    pub fn open(config: &DatabaseConfig) -> Result<Pool, r2d2::Error> {
+        let busy_timeout = Duration::from_millis(config.busy_timeout_ms);
+        let manager = SqliteConnectionManager::file(&config.path)
+            .with_init(move |conn| init_connection(conn, busy_timeout));
+        let query_timeout = Duration::from_millis(config.query_timeout_ms);
+        let connections = r2d2::Pool::builder()
+            .max_size(config.pool_size)
+            .connection_timeout(query_timeout)
+            .build(manager)?;
+        Ok(Pool { connections, query_timeout })
+    }
 
-use character::{get_character_list, get_character, create_character, update_character, delete_character};
-use skill::{get_skill_list, get_skill, create_skill, update_skill, delete_skill};
-use task::{get_task_list, get_task, create_task, update_task, delete_task};
-
-pub type Pool = r2d2::Pool<SqliteConnectionManager>;
-pub type Connection = r2d2::PooledConnection<SqliteConnectionManager>;
-
-pub enum Query {
-    GetCharacterList,
-    GetCharacter(IdType),
-    GetCharacterSkillList(IdType),
-    CreateCharacterSkill(IdType, SkillFields),   // IdType: character_id
-    GetCharacterTaskList(IdType),
-    CreateCharacter(CharacterFields),
-    UpdateCharacter(IdType, CharacterFields),
-    DeleteCharacter(IdType),
-
-    GetSkillList,
-    GetSkill(IdType),
-    GetSkillTaskList(IdType),
-    CreateSkillTask(IdType, TaskFields),     // IdType: skill_id
-    UpdateSkill(IdType, SkillFields),
-    DeleteSkill(IdType),
-
-    GetTaskList,
-    GetTask(IdType),
-    UpdateTask(IdType, TaskFields),
-    DeleteTask(IdType),
-
-    ResetDB,
+    pub fn get(&self) -> Result<r2d2::PooledConnection<SqliteConnectionManager>, r2d2::Error> {
+        self.connections.get()
+    }
 }
 
-#[derive(Serialize)]
-pub enum QueryResult {
-    CharacterList(Vec<Character>),
-    Character(Character),
-    SkillList(Vec<Skill>),
-    Skill(Skill),
-    TaskList(Vec<Task>),
-    Task(Task),
+/// Settings every connection gets once, when the pool opens it
+fn init_connection(conn: &mut Connection, busy_timeout: Duration) -> Result<(), rusqlite::Error> {
+    // WAL lets readers go on while someone writes, NORMAL is durable enough with it and
+    // saves an fsync per commit
+    conn.execute_batch("
+        PRAGMA foreign_keys = ON;
+        PRAGMA journal_mode = WAL;
+        PRAGMA synchronous = NORMAL;
+    ")?;
+    conn.busy_timeout(busy_timeout)
+}
 
-    Success,
+/// Progress handler checks the deadline every this many virtual machine instructions
+const DEADLINE_CHECK_INTERVAL: i32 = 1000;
+/// First pause before retrying a query that found the database busy, doubled on every retry
+pub const FIRST_BUSY_BACKOFF: Duration = Duration::from_millis(5);
+pub const MAX_BUSY_BACKOFF: Duration = Duration::from_millis(100);
+/// The db modules work on plain connections, so they run the same on a transaction
+pub type Connection = rusqlite::Connection;
+
+/// Entities a transaction can be scoped to, named after their table
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Entity {
+    Character,
+    Skill,
+    Task,
 }
 
-impl From::<CharacterList> for QueryResult {
-    fn from(list: CharacterList) -> Self {
-        QueryResult::CharacterList(list.0)
+impl Entity {
+    pub fn table(self) -> &'static str {
+        match self {
+            Entity::Character => "character",
+            Entity::Skill => "skill",
+            Entity::Task => "task",
+        }
     }
-}
 
-impl From::<Character> for QueryResult {
-    fn from(character: Character) -> Self {
-        QueryResult::Character(character)
+    pub fn from_table(table: &str) -> Option<Entity> {
+        [Entity::Character, Entity::Skill, Entity::Task].into_iter()
+            .find(|entity| entity.table() == table)
     }
-}
 
-impl From::<SkillList> for QueryResult {
-    fn from(list: SkillList) -> Self {
-        QueryResult::SkillList(list.0)
+    /// Entity this one is created under
+    pub fn parent(self) -> Option<Entity> {
+        match self {
+            Entity::Character => None,
+            Entity::Skill => Some(Entity::Character),
+            Entity::Task => Some(Entity::Skill),
+        }
     }
-}
 
-impl From::<Skill> for QueryResult {
-    fn from(skill: Skill) -> Self {
-        QueryResult::Skill(skill)
+    /// Condition that keeps the owner query to entities in the trash, or to ones out of it
+    pub fn trash_filter(self, trashed: bool) -> String {
+        format!(" AND {}.deleted_at IS {}NULL", self.table(), if trashed { "NOT " } else { "" })
     }
-}
 
-impl From::<TaskList> for QueryResult {
-    fn from(list: TaskList) -> Self {
-        QueryResult::TaskList(list.0)
+    /// Selects the owner of the entity with id `?1`
+    fn owner_sql(self) -> &'static str {
+        match self {
+            Entity::Character => "SELECT owner_id FROM character WHERE id = ?1",
+            Entity::Skill => "SELECT character.owner_id FROM skill
+                JOIN character ON character.id = skill.character_id WHERE skill.id = ?1",
+            Entity::Task => "SELECT character.owner_id FROM task
+                JOIN skill ON skill.id = task.skill_id
+                JOIN character ON character.id = skill.character_id WHERE task.id = ?1",
+        }
     }
 }
 
-impl From::<Task> for QueryResult {
-    fn from(task: Task) -> Self {
-        QueryResult::Task(task)
+/// What a transaction works on, checked inside it before `f` runs
+pub struct Scope {
+    pub write: bool,
+    pub owned: Option<Owned>,
+    pub if_match: Option<IfMatch>,
+}
+
+/// Entity that has to belong to `owner`, and be in the trash when `trashed` or out of it
+/// otherwise
+pub struct Owned {
+    pub owner: IdType,
+    pub entity: Entity,
+    pub id: IdType,
+    pub trashed: bool,
+}
+
+impl Scope {
+    pub fn read() -> Self {
+        Scope { write: false, owned: None, if_match: None }
+    }
+
+    /// Writers take the lock up front instead of failing on a read-to-write upgrade
+    pub fn write() -> Self {
+        Scope { write: true, owned: None, if_match: None }
+    }
+
+    /// Entity the transaction reads, changes or creates something under. Other users'
+    /// entities and trashed ones look the same as missing ones. Lists and new characters are
+    /// scoped in their own SQL instead.
+    pub fn owned_by(mut self, owner: IdType, entity: Entity, id: IdType) -> Self {
+        self.owned = Some(Owned { owner, entity, id, trashed: false });
+        self
+    }
+
+    /// Like `owned_by`, for an entity in the trash
+    pub fn trashed_owned_by(mut self, owner: IdType, entity: Entity, id: IdType) -> Self {
+        self.owned = Some(Owned { owner, entity, id, trashed: true });
+        self
+    }
+
+    /// The owned entity has to still match `if_match`, otherwise the transaction fails
+    /// with `AppError::PreconditionFailed`
+    pub fn if_match(mut self, if_match: Option<IfMatch>) -> Self {
+        self.if_match = if_match;
+        self
     }
 }
 
-pub async fn execute(pool: &Pool, query: Query) -> Result<QueryResult, AppError> {
+/// Run `f` in a single transaction on a pooled connection, see `with_connection` for the
+/// time budget and retries.
+pub async fn transaction<R: Send + 'static>(
+    pool: &Pool,
+    scope: Scope,
+    f: impl Fn(&Connection) -> Result<R, AppError> + Send + 'static,
+) -> Result<R, AppError> {
+    with_connection(pool, move |conn| run_in_transaction(conn, &scope, &f)).await
+}
+
+/// Run `f` with a pooled connection on the blocking thread pool, within the query timeout.
+/// While the database is busy `f` is run again after a growing pause. Running out of time
+/// while waiting for a connection or a lock fails with `AppError::DatabaseBusy`, while
+/// running statements with `AppError::QueryTimeout`.
+async fn with_connection<R: Send + 'static>(
+    pool: &Pool,
+    f: impl Fn(&mut Connection) -> Result<R, AppError> + Send + 'static,
+) -> Result<R, AppError> {
+    let deadline = Instant::now() + pool.query_timeout;
     let pool = pool.clone();
 
-    let conn = web::block(move || pool.get())
+    let mut conn = web::block(move || pool.connections.get_timeout(pool.query_timeout))
         .await
         .map_err(|_| AppError::InternalError)? // blocking error
-        .map_err(|e| AppError::DBError {
-            error_msg: format!("error getting db connection after initialization, {}", e.to_string())
+        .map_err(|e| {
+            log::warn!("no db connection within the query timeout, {}", e);
+            AppError::DatabaseBusy
         })?;
 
-    conn.execute("PRAGMA foreign_keys = ON;", ()).expect("cannot set pragma foreign_keys to ON");
-
-    web::block(move || -> Result<QueryResult, AppError> {
-        // simulates expensive query
-        sleep(Duration::from_secs(1));
-
-        match query {
-            Query::GetCharacterList => {
-                let character_list = get_character_list(&conn)
-                    .map_err(|e| {
-                        match e {
-                            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                            _ => AppError::DBError {
-                                error_msg: format!("in get_character_list, {}", e.to_string())
-                            }
-                        }
-                    })?;
-                Ok(QueryResult::from(character_list))
-            },
-            Query::GetCharacter(id) => {
-                let character = get_character(&conn, id)
-                    .map_err(|e| {
-                        match e {
-                            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                            _ => AppError::DBError {
-                                error_msg: format!("in get_character, {}", e.to_string())
-                            }
-                        }
-                    })?;
-                Ok(QueryResult::from(character))
-            },
-            Query::GetCharacterSkillList(id) => {
-                let character = get_character(&conn, id)
-                    .map_err(|e| {
-                        match e {
-                            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                            _ => AppError::DBError {
-                                error_msg: format!("in get_character_skill_list, get_character, {}", e.to_string())
-                            }
-                        }
-                    })?;
-                let skill_list = get_skill_list(&conn, Some(character.id))
-                    .map_err(|e| AppError::DBError {
-                        error_msg: format!("in get_character_skill_list get_skill_list, {}", e.to_string())
-                    })?;
-                Ok(QueryResult::from(skill_list))
-            },
-            Query::GetCharacterTaskList(id) => {
-                let character = get_character(&conn, id)
-                    .map_err(|e| {
-                        match e {
-                            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                            _ => AppError::DBError {
-                                error_msg: format!("in get_character_task_list, get_character, {}", e.to_string())
-                            }
-                        }
-                    })?;
-                let skill_ids = get_skill_list(&conn, Some(character.id))
-                    .map_err(|e| AppError::DBError {
-                        error_msg: format!("in get_character_task_list, get_skill_list, {}", e.to_string())
-                    })?
-                    .0
-                    .into_iter().map(|skill| skill.id)
-                    .collect::<Vec<IdType>>();
-                let mut tasks = Vec::<Task>::new();
-                for &skill_id in skill_ids.iter() {
-                    let mut skill_task_list = get_task_list(&conn, Some(skill_id))
-                        .map_err(|e| AppError::DBError {
-                            error_msg: format!("in get_character_task_list, get_task_list, {}", e.to_string())
-                        })?;
-                    tasks.append(&mut skill_task_list.0);
-                }
-
-                let task_list = TaskList(tasks);
-                Ok(QueryResult::from(task_list))
-            },
-            Query::CreateCharacter(fields) => {
-                create_character(&conn, fields).map_err(|e| AppError::DBError {
-                    error_msg: format!("in create_character, {}", e.to_string())
-                })?;
-                let id = conn.last_insert_rowid() as IdType;
-                let created_character = get_character(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in create_character, get_character, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(created_character))
-            },
-            Query::UpdateCharacter(id, fields) => {
-                update_character(&conn, id, fields).map_err(|e| AppError::DBError {
-                    error_msg: format!("in update_character, {}", e.to_string())
-                })?;
-                let updated_character = get_character(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in update_character, get_character, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(updated_character))
-            },
-            Query::DeleteCharacter(id) => {
-                delete_character(&conn, id).map_err(|e| {
-                    match e {
-                        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                        _ => AppError::DBError {
-                            error_msg: format!("in delete_character, {}", e.to_string())
-                        }
-                    }
-                }
-                )?;
-                Ok(QueryResult::Success)
-            },
-            Query::GetSkillList => {
-                let skill_list = get_skill_list(&conn, None).map_err(|e| AppError::DBError {
-                    error_msg:  format!("in get_skill_list, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(skill_list))
-            },
-            Query::GetSkill(id) => {
-                let skill = get_skill(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in get_skill, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(skill))
-            },
-            Query::GetSkillTaskList(id) => {
-                let skill = get_skill(&conn, id)
-                    .map_err(|e| {
-                        match e {
-                            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                            _ => AppError::DBError {
-                                error_msg: format!("in get_skill_task_list, in get_skill, {}", e.to_string())
-                            }
-                        }
-                    })?;
-
-                let task_list = get_task_list(&conn, Some(skill.id)).map_err(|e| AppError::DBError {
-                    error_msg: format!("in get_skill_task_list, in get_task_list, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(task_list))
-            },
-            Query::CreateCharacterSkill(character_id, fields) => {
-                create_skill(&conn, character_id, fields).map_err(|e| AppError::DBError {
-                    error_msg: format!("in create_skill, {}", e.to_string())
-                })?;
-                let id = conn.last_insert_rowid() as IdType;
-                let created_skill = get_skill(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in create_skill, get_skill, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(created_skill))
-            },
-            Query::UpdateSkill(id, fields) => {
-                update_skill(&conn, id, fields).map_err(|e| {
-                    match e {
-                        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                        _ => AppError::DBError {
-                            error_msg: format!("in update_skill, {}", e.to_string())
-                        }
-                    }
-                })?;
-                let updated_skill = get_skill(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in update_skill, get_skill, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(updated_skill))
-            },
-            Query::DeleteSkill(id) => {
-                delete_skill(&conn, id).map_err(|e| {
-                    match e {
-                        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                        _ => AppError::DBError {
-                            error_msg: format!("in delete_skill, {}", e.to_string())
-                        }
-                    }
-                })?;
-                Ok(QueryResult::Success)
-            },
-            Query::GetTaskList => {
-                let task_list = get_task_list(&conn, None).map_err(|e| AppError::DBError {
-                    error_msg: format!("in get_task_list, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(task_list))
-            },
-            Query::GetTask(id) => {
-                let task = get_task(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in get_task, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(task))
-            },
-            Query::CreateSkillTask(skill_id, fields) => {
-                create_task(&conn, skill_id, fields).map_err(|e| AppError::DBError {
-                    error_msg: format!("in create_task, {}", e.to_string())
-                })?;
-                let id = conn.last_insert_rowid() as IdType;
-                let created_task = get_task(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in create_task, get_task, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(created_task))
-            },
-            Query::UpdateTask(id, fields) => {
-                update_task(&conn, id, fields).map_err(|e| AppError::DBError {
-                    error_msg: format!("in update_task, {}", e.to_string())
-                })?;
-                let updated_task = get_task(&conn, id).map_err(|e| AppError::DBError {
-                    error_msg: format!("in update_task, get_task, {}", e.to_string())
-                })?;
-                Ok(QueryResult::from(updated_task))
-            },
-            Query::DeleteTask(id) => {
-                delete_task(&conn, id).map_err(|e| {
-                    match e {
-                        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
-                        _ => AppError::DBError {
-                            error_msg: format!("in delete_task, {}", e.to_string())
-                        }
-                    }
-                }
-                )?;
-                Ok(QueryResult::Success)
-            },
-            Query::ResetDB => {
-                clear_db(&conn).map_err(|e| AppError::DBError {
-                    error_msg: format!("in reset_db:clear_db, {}", e.to_string())
-                })?;
-                create_db(&conn).map_err(|e| AppError::DBError {
-                    error_msg: format!("in reset_db:create_db, {}", e.to_string())
-                })?;
-
-                Ok(QueryResult::Success)
-            },
-        }
+    web::block(move || {
+        conn.progress_handler(DEADLINE_CHECK_INTERVAL, Some(move || Instant::now() >= deadline));
+
+        let mut backoff = FIRST_BUSY_BACKOFF;
+        let result = loop {
+            match f(&mut conn) {
+                // the failed attempt was rolled back, so it is safe to run again
+                Err(AppError::DatabaseBusy) if Instant::now() + backoff < deadline => {
+                    sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BUSY_BACKOFF);
+                },
+                result => break result,
+            }
+        };
+
+        // the connection goes back to the pool, later queries bring their own deadline
+        conn.progress_handler(0, None::<fn() -> bool>);
+        result
     })
-    .await
-    .map_err(|_| AppError::InternalError)? // blocking error
+        .await
+        .map_err(|_| AppError::InternalError)? // blocking error
 }
 
-/// Delete all entries from the databse.
-fn clear_db(conn: &Connection) -> Result<(), rusqlite::Error> {
-    conn.execute("DELETE FROM task", ())?;
-    conn.execute("DELETE FROM skill", ())?;
-    conn.execute("DELETE FROM character", ())?;
+/// Run `f` in a single transaction. Any error rolls back everything it did, so a failure
+/// halfway through a multi-statement write never leaves partial changes behind.
+fn run_in_transaction<R>(conn: &mut Connection, scope: &Scope, f: impl Fn(&Connection) -> Result<R, AppError>) -> Result<R, AppError> {
+    let behavior = if scope.write {
+        TransactionBehavior::Immediate
+    } else {
+        TransactionBehavior::Deferred
+    };
+    let tx = conn.transaction_with_behavior(behavior).map_err(db_error("begin transaction"))?;
+
+    if let Some(owned @ Owned { entity, id, .. }) = &scope.owned {
+        check_owner(&tx, owned)?;
+
+        // checked in the same transaction, so nobody can change the target in between
+        if let Some(if_match) = &scope.if_match {
+            check_if_match(&tx, *entity, *id, if_match)?;
+        }
+    }
+
+    // dropping `tx` on error rolls it back
+    let result = f(&tx)?;
+
+    tx.commit().map_err(db_error("commit transaction"))?;
+    Ok(result)
+}
+
+fn check_owner(conn: &Connection, owned: &Owned) -> Result<(), AppError> {
+    let sql = format!("{}{}", owned.entity.owner_sql(), owned.entity.trash_filter(owned.trashed));
+    let owner_id: Option<IdType> = conn.query_row(&sql, [owned.id], |row| row.get(0))
+        .map_err(db_error("check_owner"))?;
+    if owner_id == Some(owned.owner) {
+        Ok(())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+fn check_if_match(conn: &Connection, entity: Entity, id: IdType, if_match: &IfMatch) -> Result<(), AppError> {
+    let updated_at = conn.query_row(
+        &format!("SELECT updated_at FROM {} WHERE id = ?1", entity.table()),
+        [id],
+        |row| row.get(0),
+    ).map_err(db_error("check_if_match"))?;
+    if if_match_passes(if_match, &entity_tag(id, updated_at)) {
+        Ok(())
+    } else {
+        Err(AppError::PreconditionFailed)
+    }
+}
+
+/// Fails with `AppError::Conflict` while the parent of `entity` is in the trash, the parent
+/// has to be restored first
+pub fn check_parent_restored(conn: &Connection, entity: Entity, id: IdType) -> Result<(), AppError> {
+    let Some(parent) = entity.parent() else {
+        return Ok(());
+    };
+    let sql = format!(
+        "SELECT deleted_at FROM {parent} WHERE id = (SELECT {parent}_id FROM {child} WHERE id = ?1)",
+        parent = parent.table(),
+        child = entity.table(),
+    );
+    let deleted_at: Option<TimeType> = conn.query_row(&sql, [id], |row| row.get(0))
+        .map_err(db_error("check_parent_restored"))?;
+    match deleted_at {
+        Some(_) => Err(AppError::Conflict { reason: format!("its {} is in the trash", parent.table()) }),
+        None => Ok(()),
+    }
+}
+
+/// Maps errors of the db functions to user facing errors, `context` names the failing step.
+///
+/// Missing rows and broken foreign keys both mean that the requested entity, or the parent
+/// it should be created under, doesn't exist. A lock that was not released in time and a
+/// query interrupted at its deadline are temporary, the client may retry. Anything else is
+/// logged, clients only learn that the database failed.
+pub fn db_error(context: &'static str) -> impl Fn(rusqlite::Error) -> AppError {
+    move |e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound,
+        rusqlite::Error::SqliteFailure(ref error, _)
+            if error.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY => AppError::NotFound,
+        rusqlite::Error::SqliteFailure(ref error, _)
+            if matches!(error.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+            log::warn!("in {}, {}", context, e);
+            AppError::DatabaseBusy
+        },
+        rusqlite::Error::SqliteFailure(ref error, _) if error.code == ErrorCode::OperationInterrupted => {
+            log::warn!("in {}, query timed out", context);
+            AppError::QueryTimeout
+        },
+        _ => {
+            log::error!("in {}, {}", context, e);
+            AppError::DBError
+        },
+    }
+}
+
+pub fn auth_error(context: &'static str) -> impl Fn(AuthError) -> AppError {
+    move |e| match e {
+        AuthError::UsernameTaken => AppError::Conflict { reason: "username is already taken".to_string() },
+        AuthError::InvalidCredentials => AppError::Unauthorized,
+        AuthError::Hashing => AppError::InternalError,
+        AuthError::Db(e) => db_error(context)(e),
+    }
+}
+
+/// Delete all characters of `owner`, their skills and tasks go along with them.
+pub fn clear_db(conn: &Connection, owner: IdType) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM character WHERE owner_id = ?1", [owner])?;
 
     Ok(())
 }
 
-pub fn create_db(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
-    // character table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS character (
-            id          INTEGER PRIMARY KEY,
-            name        TEXT,
-            avatar      TEXT,
-            notes       TEXT,
-            quote       TEXT,
-
-            created_at  INTEGER NOT NULL,
-            updated_at  INTEGER NOT NULL
-        )",
-        (),
-    )?;
-
-    // skill table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS skill (
-            id              INTEGER PRIMARY KEY,
-            name            TEXT,
-            progress        INTEGER,
-            level           INTEGER,
-            character_id    INTEGER NOT NULL,
-            created_at      INTEGER NOT NULL,
-            updated_at      INTEGER NOT NULL,
-
-            FOREIGN KEY(character_id) REFERENCES character(id) ON DELETE CASCADE
-        )",
-        (),
-    )?;
-
-    // task table
+/// Pool over a single in-memory database with the latest schema.
+#[cfg(test)]
+pub fn test_pool() -> Pool {
+    let manager = SqliteConnectionManager::memory()
+        .with_init(|conn| init_connection(conn, Duration::ZERO));
+    let connections = r2d2::Pool::builder().max_size(1).build(manager).expect("error creating test pool");
+    migration::migrate(&mut connections.get().unwrap()).expect("cannot migrate test db");
+    Pool { connections, query_timeout: Duration::from_secs(5) }
+}
+
+/// User without a usable password, skips the slow password hashing in tests.
+#[cfg(test)]
+pub fn test_user(conn: &Connection, username: &str) -> IdType {
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS task (
-            id                      INTEGER PRIMARY KEY,
-            name                    TEXT,
-            description             TEXT,
-            completed               INTEGER NOT NULL CHECK (completed IN (0, 1)),
-            skill_id                INTEGER NOT NULL,
-            created_at              INTEGER NOT NULL,
-            updated_at              INTEGER NOT NULL,
-
-            FOREIGN KEY(skill_id)   REFERENCES skill(id) ON DELETE CASCADE
-        )",
-        (),
-    )?;
+        "INSERT INTO user (username, password_hash, created_at, updated_at) VALUES (?1, '', 0, 0)",
+        [username],
+    ).expect("cannot create test user");
+    conn.last_insert_rowid() as IdType
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn checks_owner_and_if_match_first() {
+        let mut conn = test_pool().get().unwrap();
+        let owner = test_user(&conn, "hero");
+        let other = test_user(&conn, "villain");
+        conn.execute("INSERT INTO character (name, avatar, notes, quote, owner_id, created_at, updated_at) VALUES ('hero', '', '', '', ?1, 0, 7)", [owner]).unwrap();
+        let id = conn.last_insert_rowid() as IdType;
+
+        let scope = Scope::read().owned_by(other, Entity::Character, id);
+        assert!(matches!(run_in_transaction(&mut conn, &scope, |_| Ok(())), Err(AppError::NotFound)));
+        let scope = Scope::read().owned_by(owner, Entity::Character, 42);
+        assert!(matches!(run_in_transaction(&mut conn, &scope, |_| Ok(())), Err(AppError::NotFound)));
+
+        let stale = Some(IfMatch::Items(vec![entity_tag(id, 6)]));
+        let scope = Scope::write().owned_by(owner, Entity::Character, id).if_match(stale);
+        assert!(matches!(run_in_transaction(&mut conn, &scope, |_| Ok(())), Err(AppError::PreconditionFailed)));
+        let current = Some(IfMatch::Items(vec![entity_tag(id, 7)]));
+        let scope = Scope::write().owned_by(owner, Entity::Character, id).if_match(current);
+        assert!(run_in_transaction(&mut conn, &scope, |_| Ok(())).is_ok());
+    }
+
+    #[test]
+    fn errors_roll_back() {
+        let mut conn = test_pool().get().unwrap();
+        let owner = test_user(&conn, "hero");
+        let result: Result<(), AppError> = run_in_transaction(&mut conn, &Scope::write(), |tx| {
+            tx.execute("INSERT INTO character (name, avatar, notes, quote, owner_id, created_at, updated_at) VALUES ('hero', '', '', '', ?1, 0, 0)", [owner])
+                .map_err(db_error("insert"))?;
+            Err(AppError::InternalError)
+        });
+        assert!(result.is_err());
+        assert_eq!(count(&conn, "character"), 0);
+    }
+
+    #[actix_web::test]
+    async fn slow_queries_time_out() {
+        let pool = test_pool();
+        let pool = Pool { connections: pool.connections, query_timeout: Duration::from_millis(50) };
+        let result = with_connection(&pool, |conn| {
+            conn.query_row(
+                "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) SELECT COUNT(*) FROM n",
+                [],
+                |row| row.get::<_, i64>(0),
+            ).map_err(db_error("count forever"))
+        }).await;
+        assert!(matches!(result, Err(AppError::QueryTimeout)));
+
+        // the deadline does not stick to the connection
+        let pool = Pool { connections: pool.connections, query_timeout: Duration::from_secs(5) };
+        assert!(with_connection(&pool, |conn| Ok(count(conn, "task"))).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn busy_pool_is_unavailable() {
+        let pool = test_pool();
+        let pool = Pool { connections: pool.connections, query_timeout: Duration::from_millis(50) };
+        // the only connection is taken
+        let _conn = pool.get().unwrap();
+        let result = with_connection(&pool, |conn| Ok(count(conn, "task"))).await;
+        assert!(matches!(result, Err(AppError::DatabaseBusy)));
+    }
+
+    fn file_config(name: &str) -> DatabaseConfig {
+        let path = std::env::temp_dir().join(format!("game-of-life-{}-{}.db", name, std::process::id()));
+        DatabaseConfig { path, url: None, query_timeout_ms: 2000, busy_timeout_ms: 10, pool_size: 2 }
+    }
+
+    fn remove_db(config: &DatabaseConfig) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", config.path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn connections_are_set_up_once_opened() {
+        let config = file_config("pragmas");
+        let pool = Pool::open(&config).unwrap();
+        let conn = pool.get().unwrap();
+        let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, rusqlite::types::Value>(0)).unwrap();
+        assert_eq!(pragma("journal_mode"), rusqlite::types::Value::Text("wal".to_string()));
+        assert_eq!(pragma("foreign_keys"), rusqlite::types::Value::Integer(1));
+        assert_eq!(pragma("synchronous"), rusqlite::types::Value::Integer(1));
+        assert_eq!(pragma("busy_timeout"), rusqlite::types::Value::Integer(10));
+        drop(conn);
+        remove_db(&config);
+    }
+
+    #[actix_web::test]
+    async fn busy_database_is_retried() {
+        let config = file_config("busy");
+        let pool = Pool::open(&config).unwrap();
+        migration::migrate(&mut pool.get().unwrap()).unwrap();
+        let owner = test_user(&pool.get().unwrap(), "hero");
+
+        // another writer holds the lock for longer than the busy timeout
+        let mut locker = pool.get().unwrap();
+        let release = std::thread::spawn(move || {
+            let tx = locker.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            tx.commit().unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(20));
+
+        let result = transaction(&pool, Scope::write(), move |tx| {
+            tx.execute("INSERT INTO character (name, avatar, notes, quote, owner_id, created_at, updated_at) VALUES ('hero', '', '', '', ?1, 0, 0)", [owner])
+                .map_err(db_error("insert"))
+        }).await;
+        assert!(matches!(result, Ok(1)));
+        release.join().unwrap();
+        remove_db(&config);
+    }
 }
@@ -0,0 +1,53 @@
+use rusqlite::{ params, types::Type, Row };
+use crate::{
+    IdType, now,
+    db::{ Connection, Entity },
+    db::page::{ get_page, Conditions },
+    model::activity::{ Action, Activity, ActivityFilter, NewActivity },
+    model::page::{ Page, PageRequest },
+};
+
+/// Write down what `actor` did, an update that changed nothing is left out
+pub fn record(conn: &Connection, actor: IdType, activity: NewActivity) -> Result<(), rusqlite::Error> {
+    if activity.action == Action::Update && activity.changes.is_empty() {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO activity (actor_id, character_id, entity, entity_id, action, changes, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            actor, activity.character_id, activity.entity.table(), activity.entity_id,
+            activity.action.as_str(), serde_json::Value::Object(activity.changes).to_string(), now(),
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn get_activity_page(conn: &Connection, character_id: IdType, filter: &ActivityFilter, page: &PageRequest) -> Result<Page<Activity>, rusqlite::Error> {
+    let mut conditions = Conditions::default();
+    conditions.add("character_id = ?", character_id);
+    if let Some(entity) = filter.entity {
+        conditions.add("entity = ?", entity.table());
+    }
+    if let Some(since) = filter.since {
+        conditions.add("created_at >= ?", since);
+    }
+    if let Some(until) = filter.until {
+        conditions.add("created_at < ?", until);
+    }
+    get_page(conn, "activity", "id, actor_id, character_id, entity, entity_id, action, changes, created_at", &conditions, page, to_activity)
+}
+
+fn to_activity(row: &Row) -> Result<Activity, rusqlite::Error> {
+    let invalid = |index: usize| rusqlite::Error::InvalidColumnType(index, "activity".to_string(), Type::Text);
+    let changes: String = row.get(6)?;
+    Ok(Activity {
+        id: row.get(0)?,
+        actor_id: row.get(1)?,
+        character_id: row.get(2)?,
+        entity: Entity::from_table(&row.get::<_, String>(3)?).ok_or_else(|| invalid(3))?,
+        entity_id: row.get(4)?,
+        action: Action::parse(&row.get::<_, String>(5)?).ok_or_else(|| invalid(5))?,
+        changes: serde_json::from_str(&changes).map_err(|_| invalid(6))?,
+        created_at: row.get(7)?,
+    })
+}
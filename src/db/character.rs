@@ -1,76 +1,72 @@
-use actix_web::{
-    body::BoxBody, http::header::ContentType, HttpResponse,
-    Responder, Result
-};
 use rusqlite::{ params, Row };
 use crate::{
     IdType, now, TimeType,
-    db::Connection,
+    db::{ Connection, Entity },
+    db::activity::record,
+    db::page::{ get_page, Conditions },
+    db::patch::Assignments,
+    model::activity::{ Action, NewActivity },
+    db::skill::get_characters_skill_list,
+    db::task::get_characters_task_list,
     model::character::{
-        CharacterFields, Character, CharacterList,
+        CharacterFields, Character, CharacterFilter, CharacterPatch, CharacterTree, Include,
     },
+    model::page::{ Page, PageRequest },
 };
 
-impl Responder for Character {
-    type Body = BoxBody;
-
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
-        let body = serde_json::to_string(&self).unwrap();
-
-        HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(body)
+pub fn get_character_list(conn: &Connection, owner: IdType, filter: &CharacterFilter, page: &PageRequest) -> Result<Page<Character>, rusqlite::Error> {
+    let mut conditions = Conditions::default();
+    conditions.add("owner_id = ?", owner);
+    if let Some(updated_since) = filter.updated_since {
+        conditions.add("updated_at >= ?", updated_since);
     }
-}
-
-impl Responder for CharacterList {
-    type Body = BoxBody;
-
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
-        let body = serde_json::to_string(&self.0).unwrap();
-
-        HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(body)
+    if !filter.include_trashed {
+        conditions.require("deleted_at IS NULL");
     }
-}
-
-pub fn get_character_list(conn: &Connection) -> Result<CharacterList, rusqlite::Error> {
-    let mut stmt = conn.prepare(
-        "SELECT id, name, avatar, notes, quote, created_at, updated_at FROM character"
-    )?;
-    let characters = stmt
-        .query_map([], to_character)
-        .and_then(Iterator::collect)?;
-    Ok(CharacterList(characters))
+    get_page(conn, "character", "id, name, avatar, notes, quote, created_at, updated_at, deleted_at", &conditions, page, to_character)
 }
 
 pub fn get_character(conn: &Connection, id: IdType) -> Result<Character, rusqlite::Error> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, avatar, notes, quote, created_at, updated_at FROM character WHERE id = ?1"
+        "SELECT id, name, avatar, notes, quote, created_at, updated_at, deleted_at FROM character WHERE id = ?1"
     )?;
     let character = stmt.query_row(params![id], to_character)?;
     Ok(character)
 }
 
-pub fn create_character(conn: &Connection, fields: CharacterFields) -> Result<(), rusqlite::Error> {
+/// Nest the children `include` asks for under `characters`, with one query per kind of child
+pub fn get_character_trees(conn: &Connection, characters: Vec<Character>, include: Include) -> Result<Vec<CharacterTree>, rusqlite::Error> {
+    let ids: Vec<IdType> = characters.iter().map(|character| character.id).collect();
+    let skills = if include.skills { Some(get_characters_skill_list(conn, &ids)?.0) } else { None };
+    let tasks = if include.tasks { Some(get_characters_task_list(conn, &ids)?.0) } else { None };
+    Ok(CharacterTree::build(characters, skills, tasks))
+}
+
+pub fn create_character(conn: &Connection, owner: IdType, fields: CharacterFields) -> Result<IdType, rusqlite::Error> {
     let timestamp = now();
-    let num_rows_inserted = conn.execute(
-        "INSERT INTO character (name, avatar, notes, quote, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![fields.name, fields.avatar, fields.notes, fields.quote, timestamp, timestamp],
+    conn.execute(
+        "INSERT INTO character (name, avatar, notes, quote, owner_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![fields.name, fields.avatar, fields.notes, fields.quote, owner, timestamp, timestamp],
     )?;
-    assert_eq!(num_rows_inserted, 1);
-    Ok(())
+    let id = conn.last_insert_rowid() as IdType;
+    record_change(conn, owner, id, Action::Create, None)?;
+    Ok(id)
 }
 
-pub fn update_character(conn: &Connection, id: IdType, fields: CharacterFields) -> Result<(), rusqlite::Error> {
+pub fn update_character(conn: &Connection, actor: IdType, id: IdType, patch: CharacterPatch) -> Result<(), rusqlite::Error> {
     let timestamp = now();
-    let num_rows_updated = conn.execute(
-        "UPDATE character SET name = ?1, avatar = ?2, notes = ?3, quote = ?4, updated_at = ?5 WHERE id = ?6",
-        params![fields.name, fields.avatar, fields.notes, fields.quote, timestamp, id],
-    )?;
-    assert_eq!(num_rows_updated, 1);
-    Ok(())
+    let before = get_character(conn, id)?;
+    let mut assignments = Assignments::default();
+    assignments.set("name", patch.name);
+    assignments.set("avatar", patch.avatar);
+    assignments.set("notes", patch.notes);
+    assignments.set("quote", patch.quote);
+    assignments.set("updated_at", Some(timestamp));
+    let num_rows_updated = assignments.execute(conn, "character", id)?;
+    if num_rows_updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    record_change(conn, actor, id, Action::Update, Some(&before))
 }
 
 pub fn touch(conn: &Connection, id: IdType, timestamp: TimeType) -> Result<(), rusqlite::Error> {
@@ -78,19 +74,79 @@ pub fn touch(conn: &Connection, id: IdType, timestamp: TimeType) -> Result<(), r
         "UPDATE character SET updated_at = ?1 WHERE id = ?2",
         params![timestamp, id]
     )?;
-    assert_eq!(num_rows_updated, 1);
+    if num_rows_updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
     Ok(())
 }
 
-pub fn delete_character(conn: &Connection, id: IdType) -> Result<(), rusqlite::Error> {
+/// Move the character to the trash, with its skills and tasks that are not in there yet. They
+/// all get the same `deleted_at`, restoring the character brings back just those.
+pub fn delete_character(conn: &Connection, actor: IdType, id: IdType) -> Result<(), rusqlite::Error> {
+    let timestamp = now();
+    let before = get_character(conn, id)?;
     let num_rows_deleted = conn.execute(
-        "DELETE FROM character WHERE id = ?1",
-        params![id]
+        "UPDATE character SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![timestamp, id]
     )?;
     if num_rows_deleted == 0 {
         return Err(rusqlite::Error::QueryReturnedNoRows);
     }
-    Ok(())
+    conn.execute(
+        "UPDATE skill SET deleted_at = ?1 WHERE character_id = ?2 AND deleted_at IS NULL",
+        params![timestamp, id]
+    )?;
+    conn.execute(
+        "UPDATE task SET deleted_at = ?1
+        WHERE skill_id IN (SELECT id FROM skill WHERE character_id = ?2) AND deleted_at IS NULL",
+        params![timestamp, id]
+    )?;
+    record_change(conn, actor, id, Action::Delete, Some(&before))
+}
+
+/// Bring the character back from the trash, with the skills and tasks trashed along with it
+pub fn restore_character(conn: &Connection, actor: IdType, id: IdType) -> Result<(), rusqlite::Error> {
+    let before = get_character(conn, id)?;
+    let deleted_at: TimeType = conn.query_row(
+        "SELECT deleted_at FROM character WHERE id = ?1 AND deleted_at IS NOT NULL",
+        params![id],
+        |row| row.get(0)
+    )?;
+    conn.execute(
+        "UPDATE task SET deleted_at = NULL
+        WHERE skill_id IN (SELECT id FROM skill WHERE character_id = ?1) AND deleted_at = ?2",
+        params![id, deleted_at]
+    )?;
+    conn.execute(
+        "UPDATE skill SET deleted_at = NULL WHERE character_id = ?1 AND deleted_at = ?2",
+        params![id, deleted_at]
+    )?;
+    conn.execute(
+        "UPDATE character SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+        params![now(), id]
+    )?;
+    record_change(conn, actor, id, Action::Restore, Some(&before))
+}
+
+/// Characters of `owner` in the trash, most recently trashed first
+pub fn get_trashed_characters(conn: &Connection, owner: IdType) -> Result<Vec<Character>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, avatar, notes, quote, created_at, updated_at, deleted_at FROM character
+        WHERE owner_id = ?1 AND deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC"
+    )?;
+    let characters = stmt.query_map(params![owner], to_character).and_then(Iterator::collect)?;
+    Ok(characters)
+}
+
+/// Permanently delete the characters trashed before `before`, their skills and tasks cascade
+pub fn purge_characters(conn: &Connection, before: TimeType) -> Result<usize, rusqlite::Error> {
+    conn.execute("DELETE FROM character WHERE deleted_at < ?1", params![before])
+}
+
+/// Record what `actor` did to the character, `before` is how it was before the change
+fn record_change(conn: &Connection, actor: IdType, id: IdType, action: Action, before: Option<&Character>) -> Result<(), rusqlite::Error> {
+    let after = get_character(conn, id)?;
+    record(conn, actor, NewActivity::new(Entity::Character, id, id, action).diff(before, &after))
 }
 
 fn to_character(row: &Row) -> Result<Character, rusqlite::Error> {
@@ -99,5 +155,6 @@ fn to_character(row: &Row) -> Result<Character, rusqlite::Error> {
         fields: CharacterFields { name: row.get(1)?, avatar: row.get(2)?, notes: row.get(3)?, quote: row.get(4)? },
         created_at: row.get(5)?,
         updated_at: row.get(6)?,
+        deleted_at: row.get(7)?,
     })
 }
@@ -0,0 +1,372 @@
+use derive_more::derive::Display;
+use rusqlite::Connection;
+
+/// Schema version the database file is at, stored in sqlite's `user_version` pragma.
+pub type SchemaVersion = usize;
+
+/// Forward migrations, in order. Migration `i` brings the schema from version `i` to `i + 1`,
+/// so the latest schema version is `MIGRATIONS.len()`. Never edit an applied migration,
+/// append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema, `IF NOT EXISTS` keeps databases created before versioning intact
+    "CREATE TABLE IF NOT EXISTS character (
+        id          INTEGER PRIMARY KEY,
+        name        TEXT,
+        avatar      TEXT,
+        notes       TEXT,
+        quote       TEXT,
+
+        created_at  INTEGER NOT NULL,
+        updated_at  INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS skill (
+        id              INTEGER PRIMARY KEY,
+        name            TEXT,
+        progress        INTEGER,
+        level           INTEGER,
+        character_id    INTEGER NOT NULL,
+        created_at      INTEGER NOT NULL,
+        updated_at      INTEGER NOT NULL,
+
+        FOREIGN KEY(character_id) REFERENCES character(id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS task (
+        id                      INTEGER PRIMARY KEY,
+        name                    TEXT,
+        description             TEXT,
+        completed               INTEGER NOT NULL CHECK (completed IN (0, 1)),
+        skill_id                INTEGER NOT NULL,
+        created_at              INTEGER NOT NULL,
+        updated_at              INTEGER NOT NULL,
+
+        FOREIGN KEY(skill_id)   REFERENCES skill(id) ON DELETE CASCADE
+    );",
+    // 2: skill experience points collected from completed tasks
+    "ALTER TABLE skill ADD COLUMN xp INTEGER NOT NULL DEFAULT 0;",
+    // 3: user accounts owning characters, characters from before stay without owner until the
+    // first user registers and adopts them
+    "CREATE TABLE user (
+        id              INTEGER PRIMARY KEY,
+        username        TEXT NOT NULL UNIQUE,
+        password_hash   TEXT NOT NULL,
+        created_at      INTEGER NOT NULL,
+        updated_at      INTEGER NOT NULL
+    );
+
+    CREATE TABLE session (
+        token_hash      TEXT PRIMARY KEY,
+        user_id         INTEGER NOT NULL,
+        created_at      INTEGER NOT NULL,
+        expires_at      INTEGER NOT NULL,
+
+        FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+    );
+
+    ALTER TABLE character ADD COLUMN owner_id INTEGER REFERENCES user(id) ON DELETE CASCADE;
+    CREATE INDEX character_owner_id ON character(owner_id);",
+    // 4: trash, children trashed along with their parent get the parent's deleted_at
+    "ALTER TABLE character ADD COLUMN deleted_at INTEGER;
+    ALTER TABLE skill ADD COLUMN deleted_at INTEGER;
+    ALTER TABLE task ADD COLUMN deleted_at INTEGER;
+    CREATE INDEX character_deleted_at ON character(deleted_at);
+    CREATE INDEX skill_deleted_at ON skill(deleted_at);
+    CREATE INDEX task_deleted_at ON task(deleted_at);",
+    // 5: history of the changes to characters and everything under them
+    "CREATE TABLE activity (
+        id              INTEGER PRIMARY KEY,
+        actor_id        INTEGER NOT NULL,
+        character_id    INTEGER NOT NULL,
+        entity          TEXT NOT NULL CHECK (entity IN ('character', 'skill', 'task')),
+        entity_id       INTEGER NOT NULL,
+        action          TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete', 'restore')),
+        changes         TEXT NOT NULL,
+        created_at      INTEGER NOT NULL,
+
+        FOREIGN KEY(actor_id) REFERENCES user(id) ON DELETE CASCADE,
+        FOREIGN KEY(character_id) REFERENCES character(id) ON DELETE CASCADE
+    );
+    CREATE INDEX activity_actor_id ON activity(actor_id);
+    CREATE INDEX activity_character_id ON activity(character_id, created_at);",
+    // 6: recurring tasks, reopened at `reopens_at`, and the occurrences they were completed for
+    "ALTER TABLE task ADD COLUMN recurrence TEXT;
+    ALTER TABLE task ADD COLUMN reopens_at INTEGER;
+    CREATE INDEX task_reopens_at ON task(reopens_at);
+
+    CREATE TABLE task_completion (
+        task_id         INTEGER NOT NULL,
+        occurrence      INTEGER NOT NULL,
+        completed_at    INTEGER NOT NULL,
+
+        PRIMARY KEY(task_id, occurrence),
+        FOREIGN KEY(task_id) REFERENCES task(id) ON DELETE CASCADE
+    );",
+    // 7: XP the completion of a task gave its skill, taken back when it is un-completed. Tasks
+    // completed before count as having given nothing, un-completing them leaves the skill alone
+    "ALTER TABLE task ADD COLUMN xp_awarded INTEGER NOT NULL DEFAULT 0;",
+];
+
+#[derive(Debug, Display)]
+pub enum MigrationError {
+    #[display("database schema version {found} is newer than the latest supported version {latest}")]
+    NewerSchema { found: SchemaVersion, latest: SchemaVersion },
+    #[display("migration to schema version {version} failed: {error}")]
+    Failed { version: SchemaVersion, error: rusqlite::Error },
+    #[display("cannot read or write schema version: {_0}")]
+    Version(rusqlite::Error),
+}
+
+/// Latest schema version known to this build.
+pub fn latest_version() -> SchemaVersion {
+    MIGRATIONS.len()
+}
+
+/// Schema version recorded in the database, 0 for a fresh or pre-versioning database.
+pub fn current_version(conn: &Connection) -> Result<SchemaVersion, rusqlite::Error> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Bring the database up to the latest schema version.
+pub fn migrate(conn: &mut Connection) -> Result<(), MigrationError> {
+    run(conn, MIGRATIONS, latest_version())
+}
+
+/// Apply pending `migrations` up to and including version `target`. Each migration runs in
+/// its own transaction together with the version bump, so a failed migration leaves the
+/// database at the previous version.
+fn run(conn: &mut Connection, migrations: &[&str], target: SchemaVersion) -> Result<(), MigrationError> {
+    let found = current_version(conn).map_err(MigrationError::Version)?;
+    if found > migrations.len() {
+        return Err(MigrationError::NewerSchema { found, latest: migrations.len() });
+    }
+
+    for (index, sql) in migrations.iter().enumerate().take(target).skip(found) {
+        let version = index + 1;
+        let apply = |conn: &mut Connection| -> Result<(), rusqlite::Error> {
+            let tx = conn.transaction()?;
+            tx.execute_batch(sql)?;
+            tx.pragma_update(None, "user_version", version)?;
+            tx.commit()
+        };
+        apply(conn).map_err(|error| MigrationError::Failed { version, error })?;
+        log::info!("database migrated to schema version {}", version);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::params;
+    use crate::db::skill::backfill_xp;
+    use crate::progression::{ LevelCurve, XpType };
+
+    fn open() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("PRAGMA foreign_keys = ON;", ()).unwrap();
+        conn
+    }
+
+    fn table_exists(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            params![name],
+            |row| row.get::<_, i64>(0),
+        ).unwrap() == 1
+    }
+
+    #[test]
+    fn migrates_empty_database_to_latest() {
+        let mut conn = open();
+        migrate(&mut conn).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        for table in ["character", "skill", "task"] {
+            assert!(table_exists(&conn, table), "missing table {}", table);
+        }
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let mut conn = open();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let mut conn = open();
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+        match migrate(&mut conn) {
+            Err(MigrationError::NewerSchema { found, latest }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(latest, latest_version());
+            },
+            _ => panic!("expected NewerSchema error"),
+        }
+    }
+
+    #[test]
+    fn failed_migration_keeps_previous_version() {
+        let mut conn = open();
+        let migrations = [MIGRATIONS[0], "CREATE TABLE broken (id INTEGER); INSERT INTO missing VALUES (1);"];
+        assert!(matches!(run(&mut conn, &migrations, 2), Err(MigrationError::Failed { version: 2, .. })));
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        assert!(!table_exists(&conn, "broken"));
+    }
+
+    #[test]
+    fn migration_1_keeps_unversioned_data() {
+        // database as created by the old `create_db`, before versioning existed
+        let mut conn = open();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute_batch(
+            "INSERT INTO character (id, name, avatar, notes, quote, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1);
+             INSERT INTO skill (id, name, progress, level, character_id, created_at, updated_at)
+                VALUES (1, 'guitar', 10, 2, 1, 1, 1);
+             INSERT INTO task (id, name, description, completed, skill_id, created_at, updated_at)
+                VALUES (1, 'scales', '', 1, 1, 1, 1);"
+        ).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+
+        run(&mut conn, MIGRATIONS, 1).unwrap();
+
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        let name: String = conn.query_row("SELECT name FROM task WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(name, "scales");
+    }
+
+    #[test]
+    fn migration_2_adds_skill_xp() {
+        let mut conn = open();
+        run(&mut conn, MIGRATIONS, 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO character (id, name, avatar, notes, quote, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1);
+             INSERT INTO skill (id, name, progress, level, character_id, created_at, updated_at)
+                VALUES (1, 'guitar', 10, 2, 1, 1, 1);"
+        ).unwrap();
+
+        run(&mut conn, MIGRATIONS, 2).unwrap();
+        // the XP is filled in once the repository is opened with a curve
+        let curve = LevelCurve::default();
+        assert_eq!(backfill_xp(&conn, &curve).unwrap(), 1);
+
+        assert_eq!(current_version(&conn).unwrap(), 2);
+        let (xp, level): (XpType, i64) = conn.query_row(
+            "SELECT xp, level FROM skill WHERE id = 1", [], |row| Ok((row.get(0)?, row.get(1)?))
+        ).unwrap();
+        assert_eq!((xp, level), (curve.xp_for(2, 10), 2));
+        assert_eq!(backfill_xp(&conn, &curve).unwrap(), 0);
+    }
+
+    #[test]
+    fn migration_3_adds_users_and_character_owner() {
+        let mut conn = open();
+        run(&mut conn, MIGRATIONS, 2).unwrap();
+        conn.execute_batch(
+            "INSERT INTO character (id, name, avatar, notes, quote, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1);"
+        ).unwrap();
+
+        run(&mut conn, MIGRATIONS, 3).unwrap();
+
+        assert!(table_exists(&conn, "user"));
+        assert!(table_exists(&conn, "session"));
+        let owner_id: Option<i64> = conn.query_row("SELECT owner_id FROM character WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(owner_id, None);
+
+        // deleting a user takes their characters along
+        conn.execute_batch(
+            "INSERT INTO user (id, username, password_hash, created_at, updated_at) VALUES (1, 'hero', 'x', 1, 1);
+             UPDATE character SET owner_id = 1 WHERE id = 1;
+             DELETE FROM user WHERE id = 1;"
+        ).unwrap();
+        let characters: i64 = conn.query_row("SELECT COUNT(*) FROM character", [], |row| row.get(0)).unwrap();
+        assert_eq!(characters, 0);
+    }
+
+    #[test]
+    fn migration_4_adds_deleted_at() {
+        let mut conn = open();
+        run(&mut conn, MIGRATIONS, 3).unwrap();
+        conn.execute_batch(
+            "INSERT INTO character (id, name, avatar, notes, quote, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1);"
+        ).unwrap();
+
+        run(&mut conn, MIGRATIONS, 4).unwrap();
+
+        let deleted_at: Option<i64> = conn.query_row("SELECT deleted_at FROM character WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(deleted_at, None);
+    }
+
+    #[test]
+    fn migration_5_adds_activity_going_with_its_character() {
+        let mut conn = open();
+        run(&mut conn, MIGRATIONS, 5).unwrap();
+        conn.execute_batch(
+            "INSERT INTO user (id, username, password_hash, created_at, updated_at) VALUES (1, 'hero', 'x', 1, 1);
+             INSERT INTO character (id, name, avatar, notes, quote, owner_id, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1, 1);
+             INSERT INTO activity (actor_id, character_id, entity, entity_id, action, changes, created_at)
+                VALUES (1, 1, 'character', 1, 'create', '{}', 1);"
+        ).unwrap();
+        assert!(conn.execute(
+            "INSERT INTO activity (actor_id, character_id, entity, entity_id, action, changes, created_at)
+                VALUES (1, 1, 'user', 1, 'create', '{}', 1)", []
+        ).is_err());
+
+        conn.execute("DELETE FROM character WHERE id = 1", []).unwrap();
+        let entries: i64 = conn.query_row("SELECT COUNT(*) FROM activity", [], |row| row.get(0)).unwrap();
+        assert_eq!(entries, 0);
+    }
+
+    #[test]
+    fn migration_6_keeps_tasks_one_off() {
+        let mut conn = open();
+        run(&mut conn, MIGRATIONS, 5).unwrap();
+        conn.execute_batch(
+            "INSERT INTO character (id, name, avatar, notes, quote, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1);
+             INSERT INTO skill (id, name, progress, level, character_id, created_at, updated_at)
+                VALUES (1, 'guitar', 0, 0, 1, 1, 1);
+             INSERT INTO task (id, name, description, completed, skill_id, created_at, updated_at)
+                VALUES (1, 'scales', '', 1, 1, 1, 1);"
+        ).unwrap();
+
+        run(&mut conn, MIGRATIONS, 6).unwrap();
+
+        let (recurrence, reopens_at): (Option<String>, Option<i64>) = conn.query_row(
+            "SELECT recurrence, reopens_at FROM task WHERE id = 1", [], |row| Ok((row.get(0)?, row.get(1)?))
+        ).unwrap();
+        assert_eq!((recurrence, reopens_at), (None, None));
+
+        conn.execute("INSERT INTO task_completion (task_id, occurrence, completed_at) VALUES (1, 0, 1)", []).unwrap();
+        conn.execute("DELETE FROM task WHERE id = 1", []).unwrap();
+        let completions: i64 = conn.query_row("SELECT COUNT(*) FROM task_completion", [], |row| row.get(0)).unwrap();
+        assert_eq!(completions, 0);
+    }
+
+    #[test]
+    fn migration_7_counts_earlier_completions_as_giving_nothing() {
+        let mut conn = open();
+        run(&mut conn, MIGRATIONS, 6).unwrap();
+        conn.execute_batch(
+            "INSERT INTO character (id, name, avatar, notes, quote, created_at, updated_at)
+                VALUES (1, 'hero', '', '', '', 1, 1);
+             INSERT INTO skill (id, name, progress, level, character_id, created_at, updated_at)
+                VALUES (1, 'guitar', 0, 0, 1, 1, 1);
+             INSERT INTO task (id, name, description, completed, skill_id, created_at, updated_at)
+                VALUES (1, 'scales', '', 1, 1, 1, 1);"
+        ).unwrap();
+
+        run(&mut conn, MIGRATIONS, 7).unwrap();
+
+        let xp_awarded: i64 = conn.query_row("SELECT xp_awarded FROM task WHERE id = 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(xp_awarded, 0);
+    }
+}
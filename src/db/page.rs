@@ -0,0 +1,70 @@
+use rusqlite::{ params_from_iter, types::ToSql, Row };
+use crate::{
+    db::Connection,
+    model::page::{ Page, PageRequest },
+};
+
+/// Filters of a list query, joined with `AND`. Each condition uses one `?` placeholder, or
+/// none when added with `require`.
+#[derive(Default)]
+pub struct Conditions {
+    clauses: Vec<&'static str>,
+    params: Vec<Box<dyn ToSql>>,
+}
+
+impl Conditions {
+    pub fn add(&mut self, clause: &'static str, param: impl ToSql + 'static) {
+        self.clauses.push(clause);
+        self.params.push(Box::new(param));
+    }
+
+    /// Condition without a placeholder
+    pub fn require(&mut self, clause: &'static str) {
+        self.clauses.push(clause);
+    }
+
+    fn where_clause(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.clauses.join(" AND "))
+        }
+    }
+}
+
+/// Count the rows of `table` matching `conditions` and select one page of them. `columns` is
+/// the select list that `to_item` reads. Ties in the sort column are broken by id so pages
+/// never overlap.
+pub fn get_page<T>(
+    conn: &Connection,
+    table: &str,
+    columns: &str,
+    conditions: &Conditions,
+    page: &PageRequest,
+    to_item: fn(&Row) -> Result<T, rusqlite::Error>,
+) -> Result<Page<T>, rusqlite::Error> {
+    let where_clause = conditions.where_clause();
+
+    let total: u64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM {}{}", table, where_clause),
+        params_from_iter(conditions.params.iter()),
+        |row| row.get(0),
+    )?;
+
+    let direction = if page.descending { "DESC" } else { "ASC" };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM {}{} ORDER BY {} {}, id {} LIMIT ? OFFSET ?",
+        columns, table, where_clause, page.sort, direction, direction
+    ))?;
+    let mut params: Vec<&dyn ToSql> = conditions.params.iter().map(|param| param.as_ref()).collect();
+    params.push(&page.limit);
+    params.push(&page.offset);
+    let items: Vec<T> = stmt
+        .query_map(params_from_iter(params), to_item)
+        .and_then(Iterator::collect)?;
+
+    let next_offset = page.offset + items.len() as u64;
+    let next_cursor = (next_offset < total).then(|| next_offset.to_string());
+
+    Ok(Page { items, total, next_cursor })
+}
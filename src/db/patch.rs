@@ -0,0 +1,33 @@
+use rusqlite::{ params_from_iter, types::ToSql };
+use crate::{ IdType, db::Connection };
+
+/// SET clause of an UPDATE, built from the fields a patch supplies
+#[derive(Default)]
+pub struct Assignments {
+    columns: Vec<&'static str>,
+    params: Vec<Box<dyn ToSql>>,
+}
+
+impl Assignments {
+    /// Set `column` to `value` if it was supplied, skip it otherwise
+    pub fn set(&mut self, column: &'static str, value: Option<impl ToSql + 'static>) {
+        if let Some(value) = value {
+            self.columns.push(column);
+            self.params.push(Box::new(value));
+        }
+    }
+
+    /// Run the UPDATE on the row with `id`, returns the number of updated rows
+    pub fn execute(self, conn: &Connection, table: &str, id: IdType) -> Result<usize, rusqlite::Error> {
+        let set_clause = self.columns.iter()
+            .map(|column| format!("{} = ?", column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut params: Vec<&dyn ToSql> = self.params.iter().map(|param| param.as_ref()).collect();
+        params.push(&id);
+        conn.execute(
+            &format!("UPDATE {} SET {} WHERE id = ?", table, set_clause),
+            params_from_iter(params),
+        )
+    }
+}
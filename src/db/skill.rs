@@ -1,46 +1,24 @@
-use actix_web::{
-    body::BoxBody, http::header::ContentType, HttpResponse,
-    Responder, Result
-};
-use rusqlite::{ params, Row };
+use rusqlite::{ params, params_from_iter, Row };
 use crate::{
     IdType, now, TimeType,
-    db::Connection,
+    db::{ Connection, Entity },
+    db::activity::record,
     db::character::touch as touch_character,
+    db::page::{ get_page, Conditions },
+    db::patch::Assignments,
+    model::activity::{ Action, NewActivity },
     model::skill::{
-        SkillFields, Skill, SkillList,
+        SkillFields, Skill, SkillFilter, SkillList, SkillPatch,
     },
+    model::page::{ Page, PageRequest },
+    progression::LevelCurve,
 };
 
-impl Responder for Skill {
-    type Body = BoxBody;
-
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
-        let body = serde_json::to_string(&self).unwrap();
-
-        HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(body)
-    }
-}
-
-impl Responder for SkillList {
-    type Body = BoxBody;
-
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
-        let body = serde_json::to_string(&self.0).unwrap();
-
-        HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(body)
-    }
-}
-
 pub fn get_skill_list(conn: &Connection, character_id: Option<IdType>) -> Result<SkillList, rusqlite::Error> {
     match character_id {
         Some(character_id) => {
             let mut stmt = conn.prepare(
-                "SELECT id, name, progress, level, character_id, created_at, updated_at FROM skill WHERE character_id = ?1",
+                "SELECT id, name, progress, level, xp, character_id, created_at, updated_at, deleted_at FROM skill WHERE character_id = ?1 AND deleted_at IS NULL",
             )?;
             let skills = stmt
                 .query_map(params![character_id], to_skill)
@@ -49,7 +27,7 @@ pub fn get_skill_list(conn: &Connection, character_id: Option<IdType>) -> Result
         },
         None => {
             let mut stmt = conn.prepare(
-                "SELECT id, name, progress, level, character_id, created_at, updated_at FROM skill",
+                "SELECT id, name, progress, level, xp, character_id, created_at, updated_at, deleted_at FROM skill WHERE deleted_at IS NULL",
             )?;
             let skills = stmt
                 .query_map(params![], to_skill)
@@ -59,92 +37,208 @@ pub fn get_skill_list(conn: &Connection, character_id: Option<IdType>) -> Result
     }
 }
 
+/// Skills of every character in `character_ids`, grouped by character
+pub fn get_characters_skill_list(conn: &Connection, character_ids: &[IdType]) -> Result<SkillList, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, progress, level, xp, character_id, created_at, updated_at, deleted_at FROM skill
+        WHERE character_id IN ({}) AND deleted_at IS NULL ORDER BY character_id, id",
+        vec!["?"; character_ids.len()].join(", "),
+    ))?;
+    let skills = stmt
+        .query_map(params_from_iter(character_ids), to_skill)
+        .and_then(Iterator::collect)?;
+    Ok(SkillList(skills))
+}
+
+pub fn get_skill_page(conn: &Connection, owner: IdType, filter: &SkillFilter, page: &PageRequest) -> Result<Page<Skill>, rusqlite::Error> {
+    let mut conditions = Conditions::default();
+    conditions.add("character_id IN (SELECT id FROM character WHERE owner_id = ?)", owner);
+    if let Some(character_id) = filter.character_id {
+        conditions.add("character_id = ?", character_id);
+    }
+    if let Some(updated_since) = filter.updated_since {
+        conditions.add("updated_at >= ?", updated_since);
+    }
+    if !filter.include_trashed {
+        conditions.require("deleted_at IS NULL");
+    }
+    get_page(conn, "skill", "id, name, progress, level, xp, character_id, created_at, updated_at, deleted_at", &conditions, page, to_skill)
+}
+
 pub fn get_skill(conn: &Connection, id: IdType) -> Result<Skill, rusqlite::Error> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, progress, level, character_id, created_at, updated_at FROM skill WHERE id = ?1",
+        "SELECT id, name, progress, level, xp, character_id, created_at, updated_at, deleted_at FROM skill WHERE id = ?1",
     )?;
     let skill = stmt.query_row(params![id], to_skill)?;
     Ok(skill)
 }
 
-pub fn create_skill(conn: &Connection, character_id: IdType, fields: SkillFields) -> Result<(), rusqlite::Error> {
+/// Create a skill, the level and progress in `fields` start it off with the XP they take on `curve`
+pub fn create_skill(conn: &Connection, actor: IdType, character_id: IdType, fields: SkillFields, curve: &LevelCurve) -> Result<IdType, rusqlite::Error> {
     let timestamp = now();
-    let num_rows_inserted = conn.execute(
-        "INSERT INTO skill (name, progress, level, character_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![fields.name, fields.progress, fields.level, character_id, timestamp, timestamp]
+    let xp = curve.xp_for(fields.level, fields.progress);
+    let (level, progress) = curve.level_for(xp);
+    conn.execute(
+        "INSERT INTO skill (name, progress, level, xp, character_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![fields.name, progress, level, xp, character_id, timestamp, timestamp]
     )?;
-    assert_eq!(num_rows_inserted, 1);
+    let id = conn.last_insert_rowid() as IdType;
+    record_change(conn, actor, id, Action::Create, None)?;
 
     // update parent's updated_at attribute
     touch_character(conn, character_id, timestamp)?;
 
-    Ok(())
+    Ok(id)
 }
 
-pub fn update_skill(conn: &Connection, id: IdType, fields: SkillFields) -> Result<(), rusqlite::Error> {
+/// Update a skill. A level or progress set by hand replaces its XP with the XP they take on
+/// `curve`, so tasks completed later build on them.
+pub fn update_skill(conn: &Connection, actor: IdType, id: IdType, patch: SkillPatch, curve: &LevelCurve) -> Result<(), rusqlite::Error> {
     let timestamp = now();
-    let num_rows_updated = conn.execute(
-        "UPDATE skill SET name = ?1, progress = ?2, level = ?3, updated_at = ?4 WHERE id = ?5",
-        params![fields.name, fields.progress, fields.level, timestamp, id],
-    )?;
+    let before = get_skill(conn, id)?;
+    let mut assignments = Assignments::default();
+    assignments.set("name", patch.name);
+    let level = patch.level.unwrap_or(before.fields.level);
+    let progress = patch.progress.unwrap_or(before.fields.progress);
+    if (level, progress) != (before.fields.level, before.fields.progress) {
+        let xp = curve.xp_for(level, progress);
+        let (level, progress) = curve.level_for(xp);
+        assignments.set("xp", Some(xp));
+        assignments.set("level", Some(level));
+        assignments.set("progress", Some(progress));
+    }
+    assignments.set("updated_at", Some(timestamp));
+    let num_rows_updated = assignments.execute(conn, "skill", id)?;
 
     if num_rows_updated == 0 {
         return Err(rusqlite::Error::QueryReturnedNoRows);
     }
-    assert_eq!(num_rows_updated, 1);
+    record_change(conn, actor, id, Action::Update, Some(&before))?;
 
     // update parent's updated_at attribute
-    let mut stmt = conn.prepare(
-        "SELECT character_id FROM skill WHERE id = ?1",
-    )?;
-    let character_id = stmt.query_row(params![id], to_id)?;
-    touch_character(&conn, character_id, timestamp)?;
+    touch_character(conn, before.character_id, timestamp)?;
 
     Ok(())
 }
 
-pub fn delete_skill(conn: &Connection, id: IdType) -> Result<(), rusqlite::Error> {
+/// Move the skill to the trash, with its tasks that are not in there yet
+pub fn delete_skill(conn: &Connection, actor: IdType, id: IdType) -> Result<(), rusqlite::Error> {
     let timestamp = now();
-
-    // get parent ids before deleting
-    let mut stmt = conn.prepare(
-        "SELECT character_id FROM skill WHERE id = ?1",
-    )?;
-    let character_id = stmt.query_row(params![id], to_id)?;
+    let before = get_skill(conn, id)?;
 
     let num_rows_deleted = conn.execute(
-        "DELETE FROM skill WHERE id = ?1",
-        params![id]
+        "UPDATE skill SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![timestamp, id]
     )?;
     if num_rows_deleted == 0 {
         return Err(rusqlite::Error::QueryReturnedNoRows);
     }
-    assert_eq!(num_rows_deleted, 1);
+    conn.execute(
+        "UPDATE task SET deleted_at = ?1 WHERE skill_id = ?2 AND deleted_at IS NULL",
+        params![timestamp, id]
+    )?;
+    record_change(conn, actor, id, Action::Delete, Some(&before))?;
+
+    touch_character(conn, before.character_id, timestamp)?;
+
+    Ok(())
+}
+
+/// Bring the skill back from the trash, with the tasks trashed along with it
+pub fn restore_skill(conn: &Connection, actor: IdType, id: IdType) -> Result<(), rusqlite::Error> {
+    let timestamp = now();
+    let before = get_skill(conn, id)?;
+    let Some(deleted_at) = before.deleted_at else {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    };
+    conn.execute(
+        "UPDATE task SET deleted_at = NULL WHERE skill_id = ?1 AND deleted_at = ?2",
+        params![id, deleted_at]
+    )?;
+    conn.execute(
+        "UPDATE skill SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+        params![timestamp, id]
+    )?;
+    record_change(conn, actor, id, Action::Restore, Some(&before))?;
 
-    touch_character(&conn, character_id, timestamp)?;
+    touch_character(conn, before.character_id, timestamp)?;
 
     Ok(())
 }
 
+/// Skills of `owner` trashed on their own, most recently trashed first
+pub fn get_trashed_skills(conn: &Connection, owner: IdType) -> Result<Vec<Skill>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT skill.id, skill.name, progress, level, xp, character_id, skill.created_at, skill.updated_at, skill.deleted_at
+        FROM skill JOIN character ON character.id = skill.character_id
+        WHERE character.owner_id = ?1 AND skill.deleted_at IS NOT NULL AND character.deleted_at IS NULL
+        ORDER BY skill.deleted_at DESC, skill.id DESC"
+    )?;
+    let skills = stmt.query_map(params![owner], to_skill).and_then(Iterator::collect)?;
+    Ok(skills)
+}
+
+/// Permanently delete the skills trashed before `before`, their tasks cascade
+pub fn purge_skills(conn: &Connection, before: TimeType) -> Result<usize, rusqlite::Error> {
+    conn.execute("DELETE FROM skill WHERE deleted_at < ?1", params![before])
+}
+
 pub fn touch(conn: &Connection, id: IdType, timestamp: TimeType) -> Result<(), rusqlite::Error> {
     let num_rows_updated = conn.execute(
         "UPDATE skill SET updated_at = ?1 WHERE id = ?2",
         params![timestamp, id]
     )?;
-    assert_eq!(num_rows_updated, 1);
+    if num_rows_updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
     Ok(())
 }
 
+/// Give the skill `delta` XP (take it away when negative) and recompute its level and
+/// progress from `curve`. Also updates `updated_at`, so callers don't need to `touch` it.
+/// The new XP is recorded as a change `actor` made to the skill.
+pub fn add_xp(conn: &Connection, actor: IdType, id: IdType, delta: i64, curve: &LevelCurve, timestamp: TimeType) -> Result<(), rusqlite::Error> {
+    let before = get_skill(conn, id)?;
+    let xp = before.xp.saturating_add_signed(delta);
+    let (level, progress) = curve.level_for(xp);
+
+    let num_rows_updated = conn.execute(
+        "UPDATE skill SET xp = ?1, level = ?2, progress = ?3, updated_at = ?4 WHERE id = ?5",
+        params![xp, level, progress, timestamp, id]
+    )?;
+    if num_rows_updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    record_change(conn, actor, id, Action::Update, Some(&before))
+}
+
+/// Give the skills from before XP was tracked, those with a level or progress but no XP, the
+/// XP their level and progress take on `curve`, so completing a task builds on them. Returns
+/// how many skills were filled in.
+pub fn backfill_xp(conn: &Connection, curve: &LevelCurve) -> Result<usize, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT id, level, progress FROM skill WHERE xp = 0 AND (level > 0 OR progress > 0)")?;
+    let skills: Vec<(IdType, u8, u8)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, level, progress) in &skills {
+        conn.execute("UPDATE skill SET xp = ?1 WHERE id = ?2", params![curve.xp_for(*level, *progress), id])?;
+    }
+    Ok(skills.len())
+}
+
+/// Record what `actor` did to the skill, `before` is how it was before the change
+fn record_change(conn: &Connection, actor: IdType, id: IdType, action: Action, before: Option<&Skill>) -> Result<(), rusqlite::Error> {
+    let after = get_skill(conn, id)?;
+    record(conn, actor, NewActivity::new(Entity::Skill, id, after.character_id, action).diff(before, &after))
+}
+
 fn to_skill(row: &Row) -> Result<Skill, rusqlite::Error> {
     Ok(Skill {
         id: row.get(0)?,
         fields: SkillFields { name: row.get(1)?, progress: row.get(2)?, level: row.get(3)? },
-        character_id: row.get(4)?,
-        created_at: row.get(5)?,
-        updated_at: row.get(6)?,
+        xp: row.get(4)?,
+        character_id: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+        deleted_at: row.get(8)?,
     })
 }
-
-fn to_id(row: &Row) -> Result<IdType, rusqlite::Error> {
-    Ok(row.get(0)?)
-}
@@ -1,52 +1,35 @@
-use actix_web::{body::BoxBody, http::header::ContentType, HttpResponse, Responder, Result};
-use rusqlite::{params, Row};
+use rusqlite::{params, params_from_iter, types::Type, Row};
 use crate::{
-    IdType, now,
-    db::Connection,
+    IdType, now, TimeType,
+    db::{ Connection, Entity },
+    db::activity::record,
     db::character::touch as touch_character,
-    db::skill::touch as touch_skill,
+    db::skill::{ add_xp, touch as touch_skill },
+    db::page::{ get_page, Conditions },
+    db::patch::Assignments,
+    model::activity::{ Action, NewActivity },
     model::task::{
-        TaskFields, Task, TaskList,
+        TaskCompletion, TaskFields, Task, TaskFilter, TaskHistory, TaskList, TaskPatch,
     },
+    model::page::{ Page, PageRequest },
+    progression::{ LevelCurve, XpType },
+    recurrence::Recurrence,
 };
 
-impl Responder for Task {
-    type Body = BoxBody;
-
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
-        let body = serde_json::to_string(&self).unwrap();
-
-        HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(body)
-    }
-}
-
-impl Responder for TaskList {
-    type Body = BoxBody;
-
-    fn respond_to(self, _req: &actix_web::HttpRequest) -> HttpResponse<Self::Body> {
-        let body = serde_json::to_string(&self.0).unwrap();
-
-        HttpResponse::Ok()
-            .content_type(ContentType::json())
-            .body(body)
-    }
-}
-
+const COLUMNS: &str = "id, name, description, completed, skill_id, created_at, updated_at, deleted_at, recurrence, reopens_at";
 
 pub fn get_task_list(conn: &Connection, skill_id: Option<IdType>) -> Result<TaskList, rusqlite::Error> {
     match skill_id {
         Some(skill_id) => {
             let mut stmt = conn.prepare(
-                "SELECT id, name, description, completed, skill_id, created_at, updated_at FROM task WHERE skill_id = ?1"
+                &format!("SELECT {} FROM task WHERE skill_id = ?1 AND deleted_at IS NULL", COLUMNS)
             )?;
             let tasks = stmt.query_map(params![skill_id], to_task).and_then(Iterator::collect)?;
             Ok(TaskList(tasks))
         },
         None => {
             let mut stmt = conn.prepare(
-                "SELECT id, name, description, completed, skill_id, created_at, updated_at FROM task"
+                &format!("SELECT {} FROM task WHERE deleted_at IS NULL", COLUMNS)
             )?;
             let tasks = stmt.query_map([], to_task).and_then(Iterator::collect)?;
             Ok(TaskList(tasks))
@@ -54,48 +37,103 @@ pub fn get_task_list(conn: &Connection, skill_id: Option<IdType>) -> Result<Task
     }
 }
 
+/// Tasks of every skill of the characters in `character_ids`, grouped by character and skill
+pub fn get_characters_task_list(conn: &Connection, character_ids: &[IdType]) -> Result<TaskList, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT task.id, task.name, description, completed, skill_id, task.created_at, task.updated_at, task.deleted_at, recurrence, reopens_at
+        FROM task JOIN skill ON skill.id = task.skill_id
+        WHERE skill.character_id IN ({}) AND task.deleted_at IS NULL ORDER BY skill.character_id, task.skill_id, task.id",
+        vec!["?"; character_ids.len()].join(", "),
+    ))?;
+    let tasks = stmt.query_map(params_from_iter(character_ids), to_task).and_then(Iterator::collect)?;
+    Ok(TaskList(tasks))
+}
+
+pub fn get_task_page(conn: &Connection, owner: IdType, filter: &TaskFilter, page: &PageRequest) -> Result<Page<Task>, rusqlite::Error> {
+    let mut conditions = Conditions::default();
+    conditions.add(
+        "skill_id IN (SELECT skill.id FROM skill JOIN character ON character.id = skill.character_id WHERE character.owner_id = ?)",
+        owner,
+    );
+    if let Some(completed) = filter.completed {
+        conditions.add("completed = ?", completed);
+    }
+    if let Some(skill_id) = filter.skill_id {
+        conditions.add("skill_id = ?", skill_id);
+    }
+    if let Some(character_id) = filter.character_id {
+        conditions.add("skill_id IN (SELECT id FROM skill WHERE character_id = ?)", character_id);
+    }
+    if let Some(updated_since) = filter.updated_since {
+        conditions.add("updated_at >= ?", updated_since);
+    }
+    if !filter.include_trashed {
+        conditions.require("deleted_at IS NULL");
+    }
+    get_page(conn, "task", COLUMNS, &conditions, page, to_task)
+}
+
 pub fn get_task(conn: &Connection, id: IdType) -> Result<Task, rusqlite::Error> {
     let mut stmt = conn.prepare(
-        "SELECT id, name, description, completed, skill_id, created_at, updated_at FROM task WHERE id = ?1"
+        &format!("SELECT {} FROM task WHERE id = ?1", COLUMNS)
     )?;
     let task = stmt.query_row(params![id], to_task)?;
     Ok(task)
 }
 
-pub fn create_task(conn: &Connection, skill_id: IdType, fields: TaskFields) -> Result<(), rusqlite::Error> {
+pub fn create_task(conn: &Connection, actor: IdType, skill_id: IdType, fields: TaskFields, curve: &LevelCurve) -> Result<IdType, rusqlite::Error> {
     let timestamp = now();
-    let num_rows_inserted = conn.execute(
-        "INSERT INTO task (name, description, completed, skill_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![fields.name, fields.description, fields.completed, skill_id, timestamp, timestamp]
+    // a task created as completed counts, so un-completing it later takes back what it gave
+    let xp_delta = curve.task_xp_delta(false, fields.completed == 1, 0);
+    conn.execute(
+        "INSERT INTO task (name, description, completed, recurrence, xp_awarded, skill_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![fields.name, fields.description, fields.completed, fields.recurrence.as_ref().map(to_json), xp_delta, skill_id, timestamp, timestamp]
     )?;
-    assert_eq!(num_rows_inserted, 1);
+    let id = conn.last_insert_rowid() as IdType;
+    track_occurrence(conn, id, false, timestamp)?;
+    record_change(conn, actor, id, Action::Create, None)?;
+
+    // add_xp also updates the skill's updated_at
+    add_xp(conn, actor, skill_id, xp_delta, curve, timestamp)?;
 
     // update parents' updated_at attributes
-    touch_skill(conn, skill_id, timestamp)?;
     let mut stmt = conn.prepare(
         "SELECT character_id FROM skill WHERE id = ?1"
     )?;
     let character_id = stmt.query_row(params![skill_id], to_id)?;
     touch_character(conn, character_id, timestamp)?;
 
-    Ok(())
+    Ok(id)
 }
 
-pub fn update_task(conn: &Connection, id: IdType, fields: TaskFields) -> Result<(), rusqlite::Error> {
+pub fn update_task(conn: &Connection, actor: IdType, id: IdType, patch: TaskPatch, curve: &LevelCurve) -> Result<(), rusqlite::Error> {
     let timestamp = now();
-    let num_rows_updated = conn.execute(
-        "UPDATE task SET name = ?1, description = ?2, completed = ?3, updated_at = ?4 WHERE id = ?5",
-        params![fields.name, fields.description, fields.completed, timestamp, id]
-    )?;
-    assert_eq!(num_rows_updated, 1);
 
-    // update parents' updated_at attributes
+    // previous completed flag decides whether the skill gains or loses XP
+    let before = get_task(conn, id)?;
+    let skill_id = before.skill_id;
+    let was_completed = before.fields.completed == 1;
 
-    let mut stmt = conn.prepare(
-        "SELECT skill_id FROM task WHERE id = ?1"
-    )?;
-    let skill_id = stmt.query_row(params![id], to_id)?;
-    touch_skill(conn, skill_id, timestamp)?;
+    let is_completed = patch.completed.map_or(was_completed, |completed| completed == 1);
+    let awarded: XpType = conn.query_row("SELECT xp_awarded FROM task WHERE id = ?1", params![id], |row| row.get(0))?;
+    let xp_delta = curve.task_xp_delta(was_completed, is_completed, awarded);
+
+    let mut assignments = Assignments::default();
+    assignments.set("name", patch.name);
+    assignments.set("description", patch.description);
+    assignments.set("completed", patch.completed);
+    assignments.set("recurrence", patch.recurrence.map(|recurrence| recurrence.as_ref().map(to_json)));
+    assignments.set("xp_awarded", (xp_delta != 0).then(|| awarded.saturating_add_signed(xp_delta)));
+    assignments.set("updated_at", Some(timestamp));
+    let num_rows_updated = assignments.execute(conn, "task", id)?;
+    if num_rows_updated == 0 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    track_occurrence(conn, id, was_completed, timestamp)?;
+    record_change(conn, actor, id, Action::Update, Some(&before))?;
+
+    // update parents' updated_at attributes, add_xp takes care of the skill's
+    add_xp(conn, actor, skill_id, xp_delta, curve, timestamp)?;
 
     let mut stmt = conn.prepare(
         "SELECT character_id FROM skill WHERE id = ?1"
@@ -106,15 +144,13 @@ pub fn update_task(conn: &Connection, id: IdType, fields: TaskFields) -> Result<
     Ok(())
 }
 
-pub fn delete_task(conn: &Connection, id: IdType) -> Result<(), rusqlite::Error> {
+/// Move the task to the trash
+pub fn delete_task(conn: &Connection, actor: IdType, id: IdType) -> Result<(), rusqlite::Error> {
     let timestamp = now();
 
     // get parents' ids before deleting for updating their updated_at attrs
-
-    let mut stmt = conn.prepare(
-        "SELECT skill_id FROM task WHERE id = ?1"
-    )?;
-    let skill_id = stmt.query_row(params![id], to_id)?;
+    let before = get_task(conn, id)?;
+    let skill_id = before.skill_id;
 
     let mut stmt = conn.prepare(
         "SELECT character_id FROM skill WHERE id = ?1"
@@ -122,14 +158,36 @@ pub fn delete_task(conn: &Connection, id: IdType) -> Result<(), rusqlite::Error>
     let character_id = stmt.query_row(params![skill_id], to_id)?;
 
     let num_rows_deleted = conn.execute(
-        "DELETE FROM task WHERE id = ?1",
-        params![id]
+        "UPDATE task SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![timestamp, id]
     )?;
 
     if num_rows_deleted == 0 {
         return Err(rusqlite::Error::QueryReturnedNoRows);
     }
-    assert_eq!(num_rows_deleted, 1);
+    record_change(conn, actor, id, Action::Delete, Some(&before))?;
+
+    touch_skill(conn, skill_id, timestamp)?;
+    touch_character(conn, character_id, timestamp)?;
+
+    Ok(())
+}
+
+/// Bring the task back from the trash
+pub fn restore_task(conn: &Connection, actor: IdType, id: IdType) -> Result<(), rusqlite::Error> {
+    let timestamp = now();
+    let before = get_task(conn, id)?;
+    if before.deleted_at.is_none() {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    let skill_id = before.skill_id;
+    let character_id = conn.query_row("SELECT character_id FROM skill WHERE id = ?1", params![skill_id], to_id)?;
+
+    conn.execute(
+        "UPDATE task SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+        params![timestamp, id]
+    )?;
+    record_change(conn, actor, id, Action::Restore, Some(&before))?;
 
     touch_skill(conn, skill_id, timestamp)?;
     touch_character(conn, character_id, timestamp)?;
@@ -137,16 +195,335 @@ pub fn delete_task(conn: &Connection, id: IdType) -> Result<(), rusqlite::Error>
     Ok(())
 }
 
+/// Tasks of `owner` trashed on their own, most recently trashed first
+pub fn get_trashed_tasks(conn: &Connection, owner: IdType) -> Result<Vec<Task>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT task.id, task.name, description, completed, skill_id, task.created_at, task.updated_at, task.deleted_at, recurrence, reopens_at
+        FROM task
+        JOIN skill ON skill.id = task.skill_id
+        JOIN character ON character.id = skill.character_id
+        WHERE character.owner_id = ?1 AND task.deleted_at IS NOT NULL AND skill.deleted_at IS NULL
+        ORDER BY task.deleted_at DESC, task.id DESC"
+    )?;
+    let tasks = stmt.query_map(params![owner], to_task).and_then(Iterator::collect)?;
+    Ok(tasks)
+}
+
+/// Permanently delete the tasks trashed before `before`
+pub fn purge_tasks(conn: &Connection, before: TimeType) -> Result<usize, rusqlite::Error> {
+    conn.execute("DELETE FROM task WHERE deleted_at < ?1", params![before])
+}
+
+/// Completed occurrences of the task and its streaks as of `at`
+pub fn get_task_history(conn: &Connection, id: IdType, at: TimeType) -> Result<TaskHistory, rusqlite::Error> {
+    let task = get_task(conn, id)?;
+    let mut stmt = conn.prepare(
+        "SELECT occurrence, completed_at FROM task_completion WHERE task_id = ?1 ORDER BY occurrence DESC"
+    )?;
+    let completions = stmt.query_map(params![id], to_completion).and_then(Iterator::collect)?;
+    Ok(TaskHistory::new(&task, completions, at))
+}
+
+/// Mark the completed recurring tasks whose next occurrence has started by `at` not completed,
+/// returns how many. Their completions stay in the history and their XP with the skill. The
+/// schedule reopens them rather than a user, so it isn't in the activity log.
+pub fn reopen_tasks(conn: &Connection, at: TimeType) -> Result<usize, rusqlite::Error> {
+    let timestamp = now();
+    let mut stmt = conn.prepare(
+        "SELECT task.id, task.skill_id, skill.character_id FROM task JOIN skill ON skill.id = task.skill_id
+        WHERE task.reopens_at <= ?1 AND task.deleted_at IS NULL"
+    )?;
+    let due: Vec<(IdType, IdType, IdType)> = stmt.query_map(params![at], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .and_then(Iterator::collect)?;
+    for (id, skill_id, character_id) in &due {
+        conn.execute(
+            "UPDATE task SET completed = 0, reopens_at = NULL, xp_awarded = 0, updated_at = ?1 WHERE id = ?2",
+            params![timestamp, id]
+        )?;
+        touch_skill(conn, *skill_id, timestamp)?;
+        touch_character(conn, *character_id, timestamp)?;
+    }
+    Ok(due.len())
+}
+
+/// Keep the completion history and `reopens_at` of the task in step with its completed flag
+/// and recurrence, after it was created or changed at `timestamp`. A task that stays completed
+/// reopens after the occurrence it was completed for, however late it is edited.
+fn track_occurrence(conn: &Connection, id: IdType, was_completed: bool, timestamp: TimeType) -> Result<(), rusqlite::Error> {
+    let task = get_task(conn, id)?;
+    let is_completed = task.fields.completed == 1;
+    if was_completed && !is_completed {
+        conn.execute(
+            "DELETE FROM task_completion WHERE task_id = ?1 AND occurrence = (SELECT MAX(occurrence) FROM task_completion WHERE task_id = ?1)",
+            params![id]
+        )?;
+    }
+
+    let schedule = task.fields.recurrence.as_ref().and_then(|recurrence| recurrence.schedule().ok());
+    let reopens_at = match schedule {
+        Some(schedule) if is_completed && !was_completed => {
+            let occurrence = schedule.completed_occurrence(task.created_at, timestamp);
+            conn.execute(
+                "INSERT OR IGNORE INTO task_completion (task_id, occurrence, completed_at) VALUES (?1, ?2, ?3)",
+                params![id, occurrence, timestamp]
+            )?;
+            Some(schedule.next(task.created_at, occurrence))
+        },
+        Some(schedule) if is_completed => {
+            // none recorded when it was completed before it got a recurrence
+            let completed: Option<TimeType> = conn.query_row(
+                "SELECT MAX(occurrence) FROM task_completion WHERE task_id = ?1",
+                params![id],
+                |row| row.get(0)
+            )?;
+            let occurrence = completed.unwrap_or_else(|| schedule.completed_occurrence(task.created_at, timestamp));
+            Some(schedule.next(task.created_at, occurrence))
+        },
+        _ => None,
+    };
+    conn.execute("UPDATE task SET reopens_at = ?1 WHERE id = ?2", params![reopens_at, id])?;
+    Ok(())
+}
+
+/// Record what `actor` did to the task, `before` is how it was before the change
+fn record_change(conn: &Connection, actor: IdType, id: IdType, action: Action, before: Option<&Task>) -> Result<(), rusqlite::Error> {
+    let after = get_task(conn, id)?;
+    let character_id = conn.query_row("SELECT character_id FROM skill WHERE id = ?1", params![after.skill_id], to_id)?;
+    record(conn, actor, NewActivity::new(Entity::Task, id, character_id, action).diff(before, &after))
+}
+
 fn to_id(row: &Row) -> Result<IdType, rusqlite::Error> {
-    Ok(row.get(0)?)
+    row.get(0)
+}
+
+fn to_json(recurrence: &Recurrence) -> String {
+    serde_json::to_string(recurrence).unwrap()
 }
 
 fn to_task(row: &Row) -> Result<Task, rusqlite::Error> {
+    let recurrence = row.get::<_, Option<String>>(8)?
+        .map(|recurrence| serde_json::from_str(&recurrence))
+        .transpose()
+        .map_err(|_| rusqlite::Error::InvalidColumnType(8, "recurrence".to_string(), Type::Text))?;
     Ok(Task {
         id: row.get(0)?,
-        fields: TaskFields { name: row.get(1)?, description: row.get(2)?, completed: row.get(3)? },
+        fields: TaskFields { name: row.get(1)?, description: row.get(2)?, completed: row.get(3)?, recurrence },
         skill_id: row.get(4)?,
         created_at: row.get(5)?,
         updated_at: row.get(6)?,
+        deleted_at: row.get(7)?,
+        reopens_at: row.get(9)?,
     })
 }
+
+fn to_completion(row: &Row) -> Result<TaskCompletion, rusqlite::Error> {
+    Ok(TaskCompletion { occurrence: row.get(0)?, completed_at: row.get(1)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ test_pool, test_user, character::create_character, skill::{ backfill_xp, create_skill, get_skill } };
+    use crate::model::character::CharacterFields;
+    use crate::model::skill::SkillFields;
+    use crate::recurrence::DAY_MS;
+
+    fn task_fields(completed: u8) -> TaskFields {
+        TaskFields { name: "scales".to_string(), description: String::new(), completed, recurrence: None }
+    }
+
+    /// User owning a character with one skill, returns the user and skill ids
+    fn setup(conn: &Connection) -> (IdType, IdType) {
+        let owner = test_user(conn, "hero");
+        let fields = CharacterFields { name: "hero".to_string(), avatar: String::new(), notes: String::new(), quote: String::new() };
+        let character_id = create_character(conn, owner, fields).unwrap();
+        let skill_id = create_skill(conn, owner, character_id, SkillFields { name: "guitar".to_string(), progress: 0, level: 0 }, &LevelCurve::default()).unwrap();
+        (owner, skill_id)
+    }
+
+    #[test]
+    fn completing_a_task_levels_up_the_skill() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve { task_xp: 60, base_xp: 100, growth_percent: 0 };
+
+        let first = create_task(&conn, owner, skill_id, task_fields(0), &curve).unwrap();
+        let second = create_task(&conn, owner, skill_id, task_fields(0), &curve).unwrap();
+
+        update_task(&conn, owner, first, task_fields(1).into(), &curve).unwrap();
+        let skill = get_skill(&conn, skill_id).unwrap();
+        assert_eq!((skill.xp, skill.fields.level, skill.fields.progress), (60, 0, 60));
+
+        update_task(&conn, owner, second, task_fields(1).into(), &curve).unwrap();
+        let skill = get_skill(&conn, skill_id).unwrap();
+        assert_eq!((skill.xp, skill.fields.level, skill.fields.progress), (120, 1, 20));
+
+        // saving an already completed task again changes nothing
+        update_task(&conn, owner, second, task_fields(1).into(), &curve).unwrap();
+        assert_eq!(get_skill(&conn, skill_id).unwrap().xp, 120);
+    }
+
+    #[test]
+    fn completing_a_task_builds_on_a_skill_from_before_xp() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve { task_xp: 60, base_xp: 100, growth_percent: 0 };
+        // a level 2 skill as migration 2 left it, without XP
+        conn.execute("UPDATE skill SET xp = 0, level = 2, progress = 10 WHERE id = ?1", params![skill_id]).unwrap();
+        backfill_xp(&conn, &curve).unwrap();
+
+        create_task(&conn, owner, skill_id, task_fields(1), &curve).unwrap();
+        let skill = get_skill(&conn, skill_id).unwrap();
+        assert_eq!((skill.xp, skill.fields.level, skill.fields.progress), (270, 2, 70));
+    }
+
+    #[test]
+    fn uncompleting_a_task_reverses_the_gain() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve { task_xp: 100, base_xp: 100, growth_percent: 0 };
+
+        let id = create_task(&conn, owner, skill_id, task_fields(1), &curve).unwrap();
+        assert_eq!(get_skill(&conn, skill_id).unwrap().fields.level, 1);
+
+        update_task(&conn, owner, id, task_fields(0).into(), &curve).unwrap();
+        let skill = get_skill(&conn, skill_id).unwrap();
+        assert_eq!((skill.xp, skill.fields.level, skill.fields.progress), (0, 0, 0));
+    }
+
+    #[test]
+    fn uncompleting_a_task_from_before_xp_keeps_the_skill() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve { task_xp: 100, base_xp: 100, growth_percent: 0 };
+        let earned = create_task(&conn, owner, skill_id, task_fields(1), &curve).unwrap();
+        // completed before XP was tracked, as migration 7 leaves it
+        let before_xp = create_task(&conn, owner, skill_id, task_fields(0), &curve).unwrap();
+        conn.execute("UPDATE task SET completed = 1 WHERE id = ?1", params![before_xp]).unwrap();
+
+        update_task(&conn, owner, before_xp, task_fields(0).into(), &curve).unwrap();
+        let skill = get_skill(&conn, skill_id).unwrap();
+        assert_eq!((skill.xp, skill.fields.level), (100, 1));
+
+        // completing it now gives XP, which un-completing takes back
+        update_task(&conn, owner, before_xp, task_fields(1).into(), &curve).unwrap();
+        assert_eq!(get_skill(&conn, skill_id).unwrap().xp, 200);
+        update_task(&conn, owner, before_xp, task_fields(0).into(), &curve).unwrap();
+        update_task(&conn, owner, earned, task_fields(0).into(), &curve).unwrap();
+        assert_eq!(get_skill(&conn, skill_id).unwrap().xp, 0);
+    }
+
+    #[test]
+    fn task_page_filters_sorts_and_pages() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve::default();
+        for (name, completed) in [("c", 1), ("a", 0), ("d", 1), ("b", 1)] {
+            let fields = TaskFields { name: name.to_string(), description: String::new(), completed, recurrence: None };
+            create_task(&conn, owner, skill_id, fields, &curve).unwrap();
+        }
+
+        let filter = TaskFilter { completed: Some(1), ..Default::default() };
+        let first = PageRequest { limit: 2, offset: 0, sort: "name", descending: false };
+        let page = get_task_page(&conn, owner, &filter, &first).unwrap();
+        let names: Vec<&str> = page.items.iter().map(|task| task.fields.name.as_str()).collect();
+        assert_eq!((names, page.total, page.next_cursor.as_deref()), (vec!["b", "c"], 3, Some("2")));
+
+        let second = PageRequest { offset: 2, ..first };
+        let page = get_task_page(&conn, owner, &filter, &second).unwrap();
+        let names: Vec<&str> = page.items.iter().map(|task| task.fields.name.as_str()).collect();
+        assert_eq!((names, page.next_cursor), (vec!["d"], None));
+
+        let filter = TaskFilter { character_id: Some(skill_id + 1), ..Default::default() };
+        assert_eq!(get_task_page(&conn, owner, &filter, &first).unwrap().total, 0);
+    }
+
+    #[test]
+    fn patch_changes_only_given_fields() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve::default();
+        let fields = TaskFields { name: "scales".to_string(), description: "daily".to_string(), completed: 0, recurrence: None };
+        let id = create_task(&conn, owner, skill_id, fields, &curve).unwrap();
+
+        update_task(&conn, owner, id, TaskPatch { completed: Some(1), ..Default::default() }, &curve).unwrap();
+
+        let task = get_task(&conn, id).unwrap();
+        assert_eq!((task.fields.name.as_str(), task.fields.description.as_str(), task.fields.completed), ("scales", "daily", 1));
+        assert_eq!(get_skill(&conn, skill_id).unwrap().xp, curve.task_xp);
+
+        // a patch without `completed` keeps the XP as is
+        update_task(&conn, owner, id, TaskPatch { name: Some("arpeggios".to_string()), ..Default::default() }, &curve).unwrap();
+        assert_eq!(get_task(&conn, id).unwrap().fields.name, "arpeggios");
+        assert_eq!(get_skill(&conn, skill_id).unwrap().xp, curve.task_xp);
+    }
+
+    #[test]
+    fn recurring_task_reopens_and_keeps_its_history() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve::default();
+        let fields = TaskFields { recurrence: Some(Recurrence::Daily), ..task_fields(0) };
+        let id = create_task(&conn, owner, skill_id, fields, &curve).unwrap();
+        assert_eq!(get_task(&conn, id).unwrap().reopens_at, None);
+
+        update_task(&conn, owner, id, TaskPatch { completed: Some(1), ..Default::default() }, &curve).unwrap();
+        let today = now() / DAY_MS * DAY_MS;
+        assert_eq!(get_task(&conn, id).unwrap().reopens_at, Some(today + DAY_MS));
+
+        assert_eq!(reopen_tasks(&conn, today + DAY_MS - 1).unwrap(), 0);
+        assert_eq!(reopen_tasks(&conn, today + DAY_MS).unwrap(), 1);
+        let task = get_task(&conn, id).unwrap();
+        assert_eq!((task.fields.completed, task.reopens_at), (0, None));
+        // the XP stays, the completion is kept
+        assert_eq!(get_skill(&conn, skill_id).unwrap().xp, curve.task_xp);
+        let history = get_task_history(&conn, id, today + DAY_MS).unwrap();
+        assert_eq!(history.completions, vec![TaskCompletion { occurrence: today, completed_at: history.completions[0].completed_at }]);
+        assert_eq!((history.current_streak, history.longest_streak), (1, 1));
+
+        // a one-off task is not reopened, un-completing takes the completion back
+        update_task(&conn, owner, id, TaskPatch { completed: Some(1), recurrence: Some(None), ..Default::default() }, &curve).unwrap();
+        assert_eq!(get_task(&conn, id).unwrap().reopens_at, None);
+        update_task(&conn, owner, id, TaskPatch { completed: Some(0), ..Default::default() }, &curve).unwrap();
+        assert!(get_task_history(&conn, id, now()).unwrap().completions.is_empty());
+    }
+
+    #[test]
+    fn editing_a_completed_task_keeps_its_reopening() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let curve = LevelCurve::default();
+        let fields = TaskFields { recurrence: Some(Recurrence::Daily), ..task_fields(0) };
+        let id = create_task(&conn, owner, skill_id, fields, &curve).unwrap();
+        update_task(&conn, owner, id, TaskPatch { completed: Some(1), ..Default::default() }, &curve).unwrap();
+
+        // completed yesterday, today's occurrence has started but the task was not reopened yet
+        let today = now() / DAY_MS * DAY_MS;
+        conn.execute("UPDATE task SET created_at = created_at - ?1, reopens_at = ?2 WHERE id = ?3", params![DAY_MS, today, id]).unwrap();
+        conn.execute("UPDATE task_completion SET occurrence = ?1 WHERE task_id = ?2", params![today - DAY_MS, id]).unwrap();
+
+        update_task(&conn, owner, id, TaskPatch { name: Some("arpeggios".to_string()), ..Default::default() }, &curve).unwrap();
+        assert_eq!(get_task(&conn, id).unwrap().reopens_at, Some(today));
+        assert_eq!(reopen_tasks(&conn, now()).unwrap(), 1);
+    }
+
+    #[test]
+    fn streaks_count_consecutive_occurrences() {
+        let conn = test_pool().get().unwrap();
+        let (owner, skill_id) = setup(&conn);
+        let fields = TaskFields { recurrence: Some(Recurrence::Daily), ..task_fields(0) };
+        let id = create_task(&conn, owner, skill_id, fields, &LevelCurve::default()).unwrap();
+        let today = now() / DAY_MS * DAY_MS;
+        conn.execute("UPDATE task SET created_at = ?1 WHERE id = ?2", params![today - 5 * DAY_MS, id]).unwrap();
+        for days_ago in [5, 4, 3, 1] {
+            conn.execute(
+                "INSERT INTO task_completion (task_id, occurrence, completed_at) VALUES (?1, ?2, ?2)",
+                params![id, today - days_ago * DAY_MS],
+            ).unwrap();
+        }
+
+        let history = get_task_history(&conn, id, now()).unwrap();
+        assert_eq!(history.completions.first().map(|completion| completion.occurrence), Some(today - DAY_MS));
+        assert_eq!((history.current_streak, history.longest_streak), (1, 3));
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{
+    IdType, TimeType,
+    db::Connection,
+    db::character::{ get_trashed_characters, purge_characters },
+    db::skill::{ get_trashed_skills, purge_skills },
+    db::task::{ get_trashed_tasks, purge_tasks },
+    model::trash::Trash,
+};
+
+pub fn get_trash(conn: &Connection, owner: IdType) -> Result<Trash, rusqlite::Error> {
+    Ok(Trash {
+        characters: get_trashed_characters(conn, owner)?,
+        skills: get_trashed_skills(conn, owner)?,
+        tasks: get_trashed_tasks(conn, owner)?,
+    })
+}
+
+/// Permanently delete everything trashed before `before`, returns the number of entries
+/// removed, not counting the children removed along with them
+pub fn purge_trash(conn: &Connection, before: TimeType) -> Result<usize, rusqlite::Error> {
+    Ok(purge_characters(conn, before)? + purge_skills(conn, before)? + purge_tasks(conn, before)?)
+}
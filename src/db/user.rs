@@ -0,0 +1,217 @@
+use argon2::{
+    password_hash::{ rand_core::{ OsRng, RngCore }, PasswordHash, PasswordHasher, PasswordVerifier, SaltString },
+    Argon2,
+};
+use rusqlite::{ params, OptionalExtension, Row };
+use sha2::{ Digest, Sha256 };
+use crate::{
+    IdType, now, TimeType,
+    db::Connection,
+    model::user::{ AuthUser, Credentials, Session, User },
+};
+
+/// Ways registering or logging in can fail besides the database itself
+#[derive(Debug)]
+pub enum AuthError {
+    UsernameTaken,
+    InvalidCredentials,
+    Hashing,
+    Db(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for AuthError {
+    fn from(e: rusqlite::Error) -> Self {
+        AuthError::Db(e)
+    }
+}
+
+pub fn get_user(conn: &Connection, id: IdType) -> Result<User, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, username, created_at, updated_at FROM user WHERE id = ?1"
+    )?;
+    stmt.query_row(params![id], to_user)
+}
+
+/// Store a new user with a salted argon2 hash of the password. The first user to register
+/// adopts the characters from before user accounts, which have no owner.
+pub fn create_user(conn: &Connection, credentials: &Credentials) -> Result<IdType, AuthError> {
+    let mut stmt = conn.prepare(
+        "SELECT 1 FROM user WHERE username = ?1"
+    )?;
+    if stmt.exists(params![credentials.username])? {
+        return Err(AuthError::UsernameTaken);
+    }
+
+    let password_hash = hash_password(&credentials.password)?;
+
+    let timestamp = now();
+    conn.execute(
+        "INSERT INTO user (username, password_hash, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)",
+        params![credentials.username, password_hash, timestamp, timestamp],
+    )?;
+    let id = conn.last_insert_rowid() as IdType;
+
+    let users: i64 = conn.query_row("SELECT COUNT(*) FROM user", [], |row| row.get(0))?;
+    if users == 1 {
+        conn.execute("UPDATE character SET owner_id = ?1 WHERE owner_id IS NULL", params![id])?;
+    }
+    Ok(id)
+}
+
+/// Check the credentials and open a session that lasts `ttl` milliseconds
+pub fn create_session(conn: &Connection, credentials: &Credentials, ttl: TimeType) -> Result<Session, AuthError> {
+    let mut stmt = conn.prepare(
+        "SELECT id, password_hash FROM user WHERE username = ?1"
+    )?;
+    let found = stmt.query_row(params![credentials.username], |row| {
+        Ok((row.get::<_, IdType>(0)?, row.get::<_, String>(1)?))
+    }).optional()?;
+    let (user_id, password_hash) = found.ok_or(AuthError::InvalidCredentials)?;
+
+    verify_password(&credentials.password, &password_hash)?;
+
+    let token = new_token();
+    let timestamp = now();
+    let expires_at = timestamp + ttl;
+    conn.execute(
+        "INSERT INTO session (token_hash, user_id, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        params![hash_token(&token), user_id, timestamp, expires_at],
+    )?;
+
+    let user = get_user(conn, user_id)?;
+    Ok(Session { token, user, expires_at })
+}
+
+/// User of an unexpired session, `None` for unknown or expired tokens
+pub fn get_session_user(conn: &Connection, token: &str) -> Result<Option<AuthUser>, rusqlite::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT user.id FROM session JOIN user ON user.id = session.user_id
+            WHERE session.token_hash = ?1 AND session.expires_at > ?2"
+    )?;
+    stmt.query_row(params![hash_token(token), now()], |row| {
+        Ok(AuthUser { id: row.get(0)? })
+    }).optional()
+}
+
+pub fn delete_session(conn: &Connection, token: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM session WHERE token_hash = ?1 OR expires_at <= ?2",
+        params![hash_token(token), now()],
+    )?;
+    Ok(())
+}
+
+/// Salted argon2 hash of `password`
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| AuthError::Hashing)
+}
+
+/// `AuthError::InvalidCredentials` unless `password` is the one `password_hash` was made from
+pub fn verify_password(password: &str, password_hash: &str) -> Result<(), AuthError> {
+    let password_hash = PasswordHash::new(password_hash).map_err(|_| AuthError::Hashing)?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &password_hash)
+        .map_err(|_| AuthError::InvalidCredentials)
+}
+
+/// Random bearer token for a new session
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Tokens are random, so a fast hash is enough to keep them useless if the db leaks
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn to_user(row: &Row) -> Result<User, rusqlite::Error> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        created_at: row.get(2)?,
+        updated_at: row.get(3)?,
+    })
+}
+
+/// Session with a made up token for `user_id`, skips the password check in tests
+#[cfg(test)]
+pub fn test_session(conn: &Connection, user_id: IdType) -> String {
+    let token = format!("test-token-{}", user_id);
+    conn.execute(
+        "INSERT INTO session (token_hash, user_id, created_at, expires_at) VALUES (?1, ?2, 0, ?3)",
+        params![hash_token(&token), user_id, TimeType::MAX >> 1],
+    ).expect("cannot create test session");
+    token
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_pool;
+
+    fn credentials(password: &str) -> Credentials {
+        Credentials { username: "hero".to_string(), password: password.to_string() }
+    }
+
+    #[test]
+    fn login_checks_the_password() {
+        let conn = test_pool().get().unwrap();
+        create_user(&conn, &credentials("correct horse")).unwrap();
+
+        assert!(matches!(create_session(&conn, &credentials("wrong horse"), 1000), Err(AuthError::InvalidCredentials)));
+
+        let session = create_session(&conn, &credentials("correct horse"), 60_000).unwrap();
+        let user = get_session_user(&conn, &session.token).unwrap().unwrap();
+        assert_eq!(user.id, session.user.id);
+
+        delete_session(&conn, &session.token).unwrap();
+        assert!(get_session_user(&conn, &session.token).unwrap().is_none());
+    }
+
+    #[test]
+    fn usernames_are_unique() {
+        let conn = test_pool().get().unwrap();
+        create_user(&conn, &credentials("correct horse")).unwrap();
+        assert!(matches!(create_user(&conn, &credentials("other horse")), Err(AuthError::UsernameTaken)));
+    }
+
+    #[test]
+    fn first_user_adopts_characters_without_owner() {
+        let conn = test_pool().get().unwrap();
+        // as migration 3 leaves the characters from before user accounts
+        conn.execute(
+            "INSERT INTO character (name, avatar, notes, quote, created_at, updated_at) VALUES ('hero', '', '', '', 1, 1)", []
+        ).unwrap();
+        let character_id = conn.last_insert_rowid();
+        let owner_id = |conn: &Connection| -> Option<IdType> {
+            conn.query_row("SELECT owner_id FROM character WHERE id = ?1", params![character_id], |row| row.get(0)).unwrap()
+        };
+
+        let first = create_user(&conn, &credentials("correct horse")).unwrap();
+        assert_eq!(owner_id(&conn), Some(first));
+
+        // later users adopt nothing
+        conn.execute("UPDATE character SET owner_id = NULL WHERE id = ?1", params![character_id]).unwrap();
+        let second = Credentials { username: "sidekick".to_string(), password: "other horse".to_string() };
+        create_user(&conn, &second).unwrap();
+        assert_eq!(owner_id(&conn), None);
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected() {
+        let conn = test_pool().get().unwrap();
+        create_user(&conn, &credentials("correct horse")).unwrap();
+        let session = create_session(&conn, &credentials("correct horse"), 0).unwrap();
+        assert!(get_session_user(&conn, &session.token).unwrap().is_none());
+    }
+}
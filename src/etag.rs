@@ -0,0 +1,38 @@
+use actix_web::{ http::header::{ EntityTag, IfMatch, IF_MATCH }, HttpMessage, HttpRequest };
+use crate::{ IdType, TimeType };
+
+/// Entity tag of one version of an entity, it changes whenever `updated_at` does
+pub fn entity_tag(id: IdType, updated_at: TimeType) -> EntityTag {
+    EntityTag::new_strong(format!("{}-{}", id, updated_at))
+}
+
+/// The request's If-Match precondition, `None` when the header is missing
+pub fn if_match(req: &HttpRequest) -> Option<IfMatch> {
+    if req.headers().contains_key(IF_MATCH) {
+        req.get_header::<IfMatch>()
+    } else {
+        None
+    }
+}
+
+/// Whether a write may go ahead given the client's If-Match and the current entity tag
+pub fn if_match_passes(if_match: &IfMatch, current: &EntityTag) -> bool {
+    match if_match {
+        IfMatch::Any => true,
+        IfMatch::Items(tags) => tags.iter().any(|tag| tag.strong_eq(current)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_match_needs_the_current_version() {
+        let current = entity_tag(1, 100);
+        assert!(if_match_passes(&IfMatch::Any, &current));
+        assert!(if_match_passes(&IfMatch::Items(vec![entity_tag(1, 100)]), &current));
+        assert!(!if_match_passes(&IfMatch::Items(vec![entity_tag(1, 99)]), &current));
+        assert!(!if_match_passes(&IfMatch::Items(vec![EntityTag::new_weak("1-100".to_string())]), &current));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{ future::{ ready, Ready }, rc::Rc, time::Duration };
+use actix_web::{
+    dev::{ Service, ServiceRequest, ServiceResponse, Transform },
+    http::StatusCode,
+    rt::time::sleep,
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use rand::Rng;
+use crate::{ config::FaultConfig, AppError };
+
+/// Middleware that slows down and breaks requests on purpose, so the frontend can be tried
+/// against a slow or flaky backend. Every request waits `delay_ms` plus up to `jitter_ms`,
+/// then fails with `error_status` with a chance of `error_rate` instead of reaching the app.
+#[derive(Clone)]
+pub struct FaultInjection {
+    config: Rc<FaultConfig>,
+}
+
+impl FaultInjection {
+    pub fn new(config: FaultConfig) -> Self {
+        FaultInjection { config: Rc::new(config) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for FaultInjection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = FaultInjectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(FaultInjectionMiddleware { service: Rc::new(service), config: self.config.clone() }))
+    }
+}
+
+pub struct FaultInjectionMiddleware<S> {
+    service: Rc<S>,
+    config: Rc<FaultConfig>,
+}
+
+impl<S, B> Service<ServiceRequest> for FaultInjectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let mut rng = rand::thread_rng();
+        let delay = Duration::from_millis(self.config.delay_ms + rng.gen_range(0..=self.config.jitter_ms));
+        let fail = rng.gen_bool(self.config.error_rate);
+        let status = StatusCode::from_u16(self.config.error_status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+
+        let service = self.service.clone();
+        Box::pin(async move {
+            sleep(delay).await;
+            if fail {
+                return Err(AppError::InjectedFault { status }.into());
+            }
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use actix_web::{ test::{ call_service, init_service, try_call_service, TestRequest }, web, App };
+
+    fn config(delay_ms: u64, error_rate: f64) -> FaultConfig {
+        FaultConfig { delay_ms, jitter_ms: 0, error_rate, error_status: 500 }
+    }
+
+    #[actix_web::test]
+    async fn delays_requests() {
+        let app = init_service(
+            App::new()
+                .wrap(FaultInjection::new(config(50, 0.0)))
+                .route("/", web::get().to(|| async { "slow" }))
+        ).await;
+
+        let start = Instant::now();
+        let res = call_service(&app, TestRequest::get().to_request()).await;
+        assert!(res.status().is_success());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[actix_web::test]
+    async fn fails_with_the_configured_status() {
+        let app = init_service(
+            App::new()
+                .wrap(FaultInjection::new(config(0, 1.0)))
+                .route("/", web::get().to(|| async { "never" }))
+        ).await;
+
+        let err = try_call_service(&app, TestRequest::get().to_request()).await.err().unwrap();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -1,22 +1,33 @@
 use actix_web::{
-    web, error, get, guard, App, HttpResponse, HttpServer, Responder,
-    http::{header::ContentType, StatusCode},
-    middleware::Logger,
+    web, error, get, App, HttpMessage, HttpResponse, HttpServer, Responder,
+    http::{header, StatusCode},
+    middleware::{ Condition, Logger },
 };
 use actix_cors::Cors;
 use env_logger::Env;
 use std::{io, sync::{Arc, Mutex}};
-use rusqlite::Connection;
-use r2d2_sqlite::SqliteConnectionManager;
 use derive_more::derive::Display;
 
 mod model;
 mod db;
+mod repo;
 mod api;
+mod progression;
+mod recurrence;
+mod etag;
+mod config;
+mod proxy;
+mod tls;
+mod fault;
+mod problem;
+mod request_id;
 
 mod util;
 pub use util::{IdType, TimeType, now};
 
+mod validation;
+use validation::FieldError;
+
 // SHARED STATE EXAMPLES //
 
 struct AppState {
@@ -32,36 +43,117 @@ struct AppStateWithCounter {
 // USER FACING ERRORS
 #[derive(Debug, Display)]
 enum AppError {
-    #[display("Validation error on field: {field}")]
-    ValidationError { field: String },
+    #[display("Validation error on fields: {}", FieldError::names(errors))]
+    ValidationError { errors: Vec<FieldError> },
+    #[display("Malformed request body: {reason}")]
+    InvalidBody { reason: String },
+    #[display("Request body is larger than {limit} bytes")]
+    PayloadTooLarge { limit: usize },
+    #[display("Request body must be application/json or application/x-www-form-urlencoded")]
+    UnsupportedMediaType,
+    #[display("Host `{host}` is not allowed")]
+    HostNotAllowed { host: String },
+    #[display("Authentication required, log in and send the token as a bearer token.")]
+    Unauthorized,
     #[display("Not found")]
     NotFound,
-    #[display("Database error has occurred: {error_msg}. Please try again later.")]
-    DBError { error_msg: String },
+    #[display("Conflict: {reason}")]
+    Conflict { reason: String },
+    #[display("The entity has changed since it was read, fetch it again and retry.")]
+    PreconditionFailed,
+    /// The cause only goes to the log, see `db::db_error`
+    #[display("A database error has occurred. Please try again later.")]
+    DBError,
+    #[display("The database is busy. Please try again later.")]
+    DatabaseBusy,
+    #[display("The query took too long. Please try again later.")]
+    QueryTimeout,
+    #[display("Injected fault, fault injection is enabled on this server.")]
+    InjectedFault { status: StatusCode },
     #[display("An internal error has occurred. Please try again later.")]
     InternalError,
+    #[allow(dead_code)]
     #[display("Not implemented yet.")]
     NotImplemented,
 }
 
+impl AppError {
+    /// Stable code of the problem, its `type`, for clients to branch on
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::ValidationError { .. } => "validation_failed",
+            AppError::InvalidBody { .. } => "invalid_body",
+            AppError::PayloadTooLarge { .. } => "payload_too_large",
+            AppError::UnsupportedMediaType => "unsupported_media_type",
+            AppError::HostNotAllowed { .. } => "host_not_allowed",
+            AppError::Unauthorized => "unauthorized",
+            AppError::NotFound => "not_found",
+            AppError::Conflict { .. } => "conflict",
+            AppError::PreconditionFailed => "precondition_failed",
+            AppError::DBError => "database_error",
+            AppError::DatabaseBusy => "database_busy",
+            AppError::QueryTimeout => "query_timeout",
+            AppError::InjectedFault { .. } => "injected_fault",
+            AppError::InternalError => "internal_error",
+            AppError::NotImplemented => "not_implemented",
+        }
+    }
+
+    /// Short summary of the problem, the same for every occurrence of it
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::ValidationError { .. } => "Validation failed",
+            AppError::InvalidBody { .. } => "Malformed request body",
+            AppError::PayloadTooLarge { .. } => "Request body too large",
+            AppError::UnsupportedMediaType => "Unsupported media type",
+            AppError::HostNotAllowed { .. } => "Host not allowed",
+            AppError::Unauthorized => "Authentication required",
+            AppError::NotFound => "Not found",
+            AppError::Conflict { .. } => "Conflict",
+            AppError::PreconditionFailed => "Precondition failed",
+            AppError::DBError => "Database error",
+            AppError::DatabaseBusy => "Database busy",
+            AppError::QueryTimeout => "Query timeout",
+            AppError::InjectedFault { .. } => "Injected fault",
+            AppError::InternalError => "Internal error",
+            AppError::NotImplemented => "Not implemented",
+        }
+    }
+}
+
+// served as application/problem+json, `request_id::RequestIds` adds the request id
 impl error::ResponseError for AppError {
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::plaintext())
-            .body(self.to_string())
+        problem::app_error_response(self, None)
     }
 
     fn status_code(&self) -> actix_web::http::StatusCode {
         match *self {
             AppError::ValidationError { .. } => StatusCode::BAD_REQUEST,
+            AppError::InvalidBody { .. } => StatusCode::BAD_REQUEST,
+            AppError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::HostNotAllowed { .. } => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
             AppError::NotFound => StatusCode::NOT_FOUND,
-            AppError::DBError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            AppError::DBError => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::DatabaseBusy => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::QueryTimeout => StatusCode::GATEWAY_TIMEOUT,
+            AppError::InjectedFault { status } => status,
             AppError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
         }
     }
 }
 
+impl From<FieldError> for AppError {
+    fn from(error: FieldError) -> Self {
+        AppError::ValidationError { errors: vec![error] }
+    }
+}
+
 //----------
 
 
@@ -76,7 +168,7 @@ async fn index(data: web::Data<AppState>, data_with_counter: web::Data<AppStateW
 
 #[get("/info")]
 async fn info() -> impl Responder {
-    format!{"Welcome to the app! Here we will provide useful info for debugging the server."}
+    "Welcome to the app! Here we will provide useful info for debugging the server."
 }
 
 #[actix_web::main]
@@ -86,61 +178,117 @@ async fn main() -> io::Result<()> {
         counter: Mutex::new(0)
     });
 
-    let json_config = Arc::new(web::JsonConfig::default()
-        .limit(4096) // 4kb
-        // create custom error response
-        .error_handler(|err, _req| {
-            error::InternalError::from_response(err, HttpResponse::Conflict().finish())
-                .into()
-        }));
+    // setup logger
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-    // database connection
-    let conn = Connection::open("game_of_life.db").expect("error connecting to database");
-    conn.execute("PRAGMA foreign_keys = ON;", ()).expect("cannot set pragma foreign_keys to ON");
-    db::create_db(&conn).expect("cannot create db");
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(io::Error::other(e.to_string()));
+        }
+    };
 
-    let manager = SqliteConnectionManager::file("game_of_life.db");
+    // repository on a pool of db connections, this will be shared. Opening blocks, and the
+    // schema is brought up to date, refusing to start on a database from a newer build.
+    let database = config.database.clone();
+    let curve = config.progression.curve();
+    let opened = web::block(move || repo::open(&database, &curve))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|repo| repo.map_err(|e| e.to_string()));
+    let repo = match opened {
+        Ok(repo) => repo,
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(io::Error::other(e));
+        }
+    };
+    repo::spawn_trash_purge(repo.clone(), &config.trash);
+    repo::spawn_task_reopening(repo.clone(), &config.recurrence);
 
-    // pool to make db requests, this will be shared
-    let pool = db::Pool::new(manager).expect("error creating connection pool");
+    // loaded before starting, a bad certificate should stop the server right away
+    let cert_reloader = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert), Some(key)) => match tls::CertReloader::load(cert, key) {
+            Ok(reloader) => Some(Arc::new(reloader)),
+            Err(e) => {
+                log::error!("cannot load tls certificate: {}", e);
+                return Err(io::Error::other(e.to_string()));
+            }
+        },
+        _ => None,
+    };
+    let redirect_http = config.tls.redirect_http;
+    let https_port = config.tls.port;
 
-    // setup logger
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
+    if config.fault.enabled() {
+        log::warn!("fault injection is enabled, requests are delayed and fail on purpose");
+    }
+    let fault_enabled = config.fault.enabled();
+    let fault_config = config.fault;
 
-    HttpServer::new(move || {
-        let logger = Logger::new("%a %r %s Req: Content-Type=%{Content-Type}i");
+    let app_name = config.app.name.clone();
+    let api_config = config.api.clone();
+    let allowed_origins = config.cors.allowed_origins.clone();
+    let proxy_headers = proxy::ProxyHeaders::new(config.proxy.trusted.clone(), config.server.allowed_hosts.clone());
+
+    let server = HttpServer::new(move || {
+        // %{r}a is the client behind trusted proxies, %a the peer that connected
+        let logger = Logger::new("%{r}a (peer %a) %r %s Req: Content-Type=%{Content-Type}i Id=%{id}xi")
+            .custom_request_replace("id", |req| req.extensions().get::<request_id::RequestId>()
+                .map(|id| id.0.clone())
+                .unwrap_or_default());
         //let logger = Logger::default();
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:4000")
+        let cors = allowed_origins.iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allow_any_method()
             .allow_any_header()
+            // lets the frontend read versions for If-Match, ids to report failed requests and
+            // when the API version it uses goes away
+            .expose_headers(vec![header::ETAG, request_id::X_REQUEST_ID, header::HeaderName::from_static("deprecation"), header::HeaderName::from_static("sunset")])
             .max_age(3600);
 
         App::new()
-            .wrap(logger)
+            // inside cors, so injected errors still reach the browser's scripts
+            .wrap(Condition::new(fault_enabled, fault::FaultInjection::new(fault_config.clone())))
             .wrap(cors)
+            .wrap(Condition::new(redirect_http, tls::RedirectHttps::new(https_port)))
+            .wrap(logger)
+            // everything inside sees the sanitized forwarding headers
+            .wrap(proxy_headers.clone())
+            // outermost, so every error is a problem with the request id
+            .wrap(request_id::RequestIds)
             // prepare shared states
-            .app_data(web::Data::new(pool.clone()))
-            .app_data(json_config.clone())
+            .configure(repo::configure(repo.clone()))
+            .app_data(web::Data::new(curve))
+            // body parsing errors become user facing errors
+            .app_data(api::body::json_config())
+            .app_data(api::body::form_config())
             .app_data(
                 web::Data::new(AppState {
-                    app_name: String::from("Game of Life API")
+                    app_name: app_name.clone()
                 })
             )
             .app_data(counter.clone())
             // configure api
-            .configure(api::config)
+            .configure(api::config(&api_config))
             // configure index and info routes
-            .service(
-                web::scope("")
-                    .guard(guard::Host("localhost").scheme("http"))
-                    .service(index)
-                    .service(info)
-            )
+            .service(index)
+            .service(info)
     })
         //.keep_alive(None)
-        .workers(2)
-        .bind(("0.0.0.0", 3000))?
-        .run()
+        .workers(config.server.workers)
+        .bind((config.server.host.as_str(), config.server.port))?;
+
+    let server = match cert_reloader {
+        Some(reloader) => {
+            #[cfg(unix)]
+            tls::reload_on_sighup(reloader.clone())?;
+            server.bind_rustls_0_23((config.server.host.as_str(), config.tls.port), reloader.server_config())?
+        },
+        None => server,
+    };
+
+    server.run()
         .await
 }
@@ -1,3 +1,7 @@
+pub mod activity;
 pub mod character;
 pub mod skill;
 pub mod task;
+pub mod page;
+pub mod trash;
+pub mod user;
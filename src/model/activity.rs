@@ -0,0 +1,143 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::{ IntoParams, ToSchema };
+use serde_json::{ json, Map, Value };
+use crate::{ IdType, TimeType };
+use crate::db::Entity;
+
+/// What was done to an entity
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    /// Moved to the trash
+    Delete,
+    /// Brought back from the trash
+    Restore,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Restore => "restore",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Action> {
+        [Action::Create, Action::Update, Action::Delete, Action::Restore].into_iter()
+            .find(|action| action.as_str() == name)
+    }
+}
+
+/// One change in the history of a character, to the character itself or to something under it
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Activity {
+    pub id: IdType,
+    /// User who made the change
+    pub actor_id: IdType,
+    pub character_id: IdType,
+    pub entity: Entity,
+    pub entity_id: IdType,
+    pub action: Action,
+    /// Changed fields, each as `{ "before": ..., "after": ... }` with `null` for a side the
+    /// field is missing from
+    pub changes: Value,
+    pub created_at: TimeType,
+}
+
+/// Change about to be recorded
+pub struct NewActivity {
+    pub entity: Entity,
+    pub entity_id: IdType,
+    pub character_id: IdType,
+    pub action: Action,
+    pub changes: Map<String, Value>,
+}
+
+/// Bookkeeping fields left out of the changes
+const UNTRACKED: &[&str] = &["id", "created_at", "updated_at"];
+
+impl NewActivity {
+    pub fn new(entity: Entity, entity_id: IdType, character_id: IdType, action: Action) -> Self {
+        NewActivity { entity, entity_id, character_id, action, changes: Map::new() }
+    }
+
+    /// Keep the fields that differ between the entity `before` and `after` the change, `None`
+    /// standing for an entity that didn't exist yet
+    pub fn diff<T: Serialize>(mut self, before: Option<&T>, after: &T) -> Self {
+        let before = flatten(before);
+        let after = flatten(Some(after));
+        for name in before.keys().chain(after.keys().filter(|name| !before.contains_key(*name))) {
+            let (old, new) = (before.get(name), after.get(name));
+            if old != new {
+                self.changes.insert(name.clone(), json!({ "before": old, "after": new }));
+            }
+        }
+        self
+    }
+}
+
+/// Fields of the serialized entity, with its nested `fields` moved to the top
+fn flatten<T: Serialize>(entity: Option<&T>) -> Map<String, Value> {
+    let mut map = match entity.map(serde_json::to_value) {
+        Some(Ok(Value::Object(map))) => map,
+        _ => Map::new(),
+    };
+    if let Some(Value::Object(fields)) = map.remove("fields") {
+        map.extend(fields);
+    }
+    for name in UNTRACKED {
+        map.remove(*name);
+    }
+    map
+}
+
+/// Columns the activity list can be sorted by, the first one is the default
+pub const ACTIVITY_SORTABLE: &[&str] = &["created_at"];
+
+/// Filters for the activity of a character, taken from the query string
+#[derive(Clone, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ActivityFilter {
+    pub entity: Option<Entity>,
+    /// Changes made at or after this time
+    pub since: Option<TimeType>,
+    /// Changes made before this time
+    pub until: Option<TimeType>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::skill::{ Skill, SkillFields };
+
+    fn skill(name: &str, level: u8, updated_at: TimeType) -> Skill {
+        Skill {
+            id: 1,
+            fields: SkillFields { name: name.to_string(), progress: 0, level },
+            xp: 0,
+            character_id: 1,
+            created_at: 1,
+            updated_at,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn diff_keeps_changed_fields_only() {
+        let activity = NewActivity::new(Entity::Skill, 1, 1, Action::Update)
+            .diff(Some(&skill("guitar", 2, 1)), &skill("guitar", 3, 2));
+        assert_eq!(Value::Object(activity.changes), json!({ "level": { "before": 2, "after": 3 } }));
+    }
+
+    #[test]
+    fn diff_of_a_new_entity_has_every_field() {
+        let activity = NewActivity::new(Entity::Skill, 1, 1, Action::Create).diff(None, &skill("guitar", 0, 1));
+        let names: Vec<&str> = activity.changes.keys().map(String::as_str).collect();
+        assert_eq!(names, vec!["character_id", "level", "name", "progress", "xp"]);
+        assert_eq!(activity.changes["name"], json!({ "before": null, "after": "guitar" }));
+    }
+}
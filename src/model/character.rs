@@ -1,15 +1,23 @@
+use std::collections::HashMap;
 use serde::{ Deserialize, Serialize, };
-use crate::{ IdType, TimeType, };
+use utoipa::{ IntoParams, ToSchema };
+use crate::{ AppError, IdType, TimeType, };
+use crate::model::skill::Skill;
+use crate::model::task::Task;
+use crate::validation::FieldError;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Character {
     pub id: IdType,
     pub fields: CharacterFields,
     pub created_at: TimeType,
     pub updated_at: TimeType,
+    /// When the character was moved to the trash, left out while it is not in there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<TimeType>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct CharacterFields {
     pub name: String,
     pub avatar: String,
@@ -17,4 +25,120 @@ pub struct CharacterFields {
     pub quote: String,
 }
 
-pub struct CharacterList(pub Vec<Character>);
+/// Partial update, only the given fields are changed
+#[derive(Clone, Debug, Deserialize, Default, ToSchema)]
+pub struct CharacterPatch {
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    pub notes: Option<String>,
+    pub quote: Option<String>,
+}
+
+impl From<CharacterFields> for CharacterPatch {
+    fn from(fields: CharacterFields) -> Self {
+        CharacterPatch { name: Some(fields.name), avatar: Some(fields.avatar), notes: Some(fields.notes), quote: Some(fields.quote) }
+    }
+}
+
+/// Columns the character list can be sorted by, the first one is the default
+pub const CHARACTER_SORTABLE: &[&str] = &["created_at", "name", "updated_at"];
+
+/// Filters for the character list, taken from the query string
+#[derive(Clone, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CharacterFilter {
+    pub updated_since: Option<TimeType>,
+    /// List trashed entries too
+    #[serde(default)]
+    pub include_trashed: bool,
+}
+
+/// Children to nest in character responses, as given in the query string, e.g.
+/// `?include=skills,skills.tasks`
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IncludeParams {
+    pub include: Option<String>,
+}
+
+/// Checked includes, `skills.tasks` brings the skills along
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Include {
+    pub skills: bool,
+    pub tasks: bool,
+}
+
+impl IncludeParams {
+    pub fn to_include(&self) -> Result<Include, AppError> {
+        let mut include = Include::default();
+        for name in self.include.iter().flat_map(|include| include.split(',')).filter(|name| !name.is_empty()) {
+            match name {
+                "skills" => include.skills = true,
+                "skills.tasks" => include = Include { skills: true, tasks: true },
+                _ => return Err(FieldError::new("include", "must be a comma separated list of skills, skills.tasks").into()),
+            }
+        }
+        Ok(include)
+    }
+}
+
+/// A character with the children asked for by an `Include`, the ones not asked for are left out
+#[derive(Serialize, ToSchema)]
+pub struct CharacterTree {
+    #[serde(flatten)]
+    pub character: Character,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skills: Option<Vec<SkillTree>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SkillTree {
+    #[serde(flatten)]
+    pub skill: Skill,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tasks: Option<Vec<Task>>,
+}
+
+impl CharacterTree {
+    /// Nest `skills` and `tasks` under the `characters` they belong to, keeping their order.
+    /// `None` leaves them out of the trees.
+    pub fn build(characters: Vec<Character>, skills: Option<Vec<Skill>>, tasks: Option<Vec<Task>>) -> Vec<CharacterTree> {
+        let with_tasks = tasks.is_some();
+        let mut tasks_by_skill: HashMap<IdType, Vec<Task>> = HashMap::new();
+        for task in tasks.into_iter().flatten() {
+            tasks_by_skill.entry(task.skill_id).or_default().push(task);
+        }
+
+        let with_skills = skills.is_some();
+        let mut skills_by_character: HashMap<IdType, Vec<SkillTree>> = HashMap::new();
+        for skill in skills.into_iter().flatten() {
+            let tasks = with_tasks.then(|| tasks_by_skill.remove(&skill.id).unwrap_or_default());
+            skills_by_character.entry(skill.character_id).or_default().push(SkillTree { skill, tasks });
+        }
+
+        characters.into_iter()
+            .map(|character| CharacterTree {
+                skills: with_skills.then(|| skills_by_character.remove(&character.id).unwrap_or_default()),
+                character,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn include(value: &str) -> Result<Include, AppError> {
+        IncludeParams { include: Some(value.to_string()) }.to_include()
+    }
+
+    #[test]
+    fn parses_includes() {
+        assert_eq!(IncludeParams::default().to_include().unwrap(), Include::default());
+        assert_eq!(include("skills").unwrap(), Include { skills: true, tasks: false });
+        assert_eq!(include("skills.tasks").unwrap(), Include { skills: true, tasks: true });
+        assert_eq!(include("skills,skills.tasks").unwrap(), Include { skills: true, tasks: true });
+        assert!(matches!(include("skills,tasks"), Err(AppError::ValidationError { .. })));
+    }
+}
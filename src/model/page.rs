@@ -0,0 +1,111 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::{ IntoParams, ToSchema };
+use crate::AppError;
+use crate::validation::FieldError;
+
+pub const DEFAULT_LIMIT: u64 = 50;
+pub const MAX_LIMIT: u64 = 100;
+
+/// Paging and sorting as given in the query string, e.g. `?limit=20&cursor=40&sort=-updated_at`.
+/// A leading `-` on `sort` sorts descending.
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageParams {
+    pub limit: Option<u64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+}
+
+/// Checked paging and sorting, ready to be turned into SQL
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageRequest {
+    pub limit: u64,
+    pub offset: u64,
+    /// One of the sortable columns of the listed entity
+    pub sort: &'static str,
+    pub descending: bool,
+}
+
+/// One page of a list with the total number of matching entries. `next_cursor` is passed
+/// back as `cursor` to get the following page, it is `None` on the last page.
+#[derive(Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub next_cursor: Option<String>,
+}
+
+impl PageParams {
+    /// Check the params against the columns the entity can be sorted by, the first one is
+    /// the default.
+    pub fn to_request(&self, sortable: &[&'static str]) -> Result<PageRequest, AppError> {
+        let mut errors = Vec::new();
+
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            errors.push(FieldError::new("limit", format!("must be between 1 and {}", MAX_LIMIT)));
+        }
+
+        let offset = match &self.cursor {
+            None => 0,
+            Some(cursor) => cursor.parse().unwrap_or_else(|_| {
+                errors.push(FieldError::new("cursor", "must be a cursor returned by a previous page"));
+                0
+            }),
+        };
+
+        let (sort, descending) = match self.sort.as_deref() {
+            None => (sortable[0], false),
+            Some(sort) => {
+                let (name, descending) = match sort.strip_prefix('-') {
+                    Some(name) => (name, true),
+                    None => (sort, false),
+                };
+                match sortable.iter().find(|&&column| column == name) {
+                    Some(column) => (*column, descending),
+                    None => {
+                        errors.push(FieldError::new("sort", format!("must be one of {}, optionally prefixed with -", sortable.join(", "))));
+                        (sortable[0], false)
+                    },
+                }
+            },
+        };
+
+        if errors.is_empty() {
+            Ok(PageRequest { limit, offset, sort, descending })
+        } else {
+            Err(AppError::ValidationError { errors })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SORTABLE: &[&str] = &["created_at", "name"];
+
+    #[test]
+    fn defaults() {
+        let request = PageParams::default().to_request(SORTABLE).unwrap();
+        assert_eq!(request, PageRequest { limit: DEFAULT_LIMIT, offset: 0, sort: "created_at", descending: false });
+    }
+
+    #[test]
+    fn parses_cursor_and_descending_sort() {
+        let params = PageParams { limit: Some(10), cursor: Some("30".to_string()), sort: Some("-name".to_string()) };
+        let request = params.to_request(SORTABLE).unwrap();
+        assert_eq!(request, PageRequest { limit: 10, offset: 30, sort: "name", descending: true });
+    }
+
+    #[test]
+    fn reports_every_bad_param() {
+        let params = PageParams { limit: Some(0), cursor: Some("abc".to_string()), sort: Some("level".to_string()) };
+        match params.to_request(SORTABLE) {
+            Err(AppError::ValidationError { errors }) => {
+                assert_eq!(FieldError::names(&errors), "limit, cursor, sort");
+            },
+            _ => panic!("expected validation error"),
+        }
+    }
+}
@@ -1,20 +1,56 @@
 use serde::{ Serialize, Deserialize };
+use utoipa::{ IntoParams, ToSchema };
 use crate::{ IdType, TimeType, };
+use crate::progression::XpType;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct SkillFields {
     pub name: String,
+    /// Percent of the way to the next level. Setting it or `level` sets `xp` to match.
     pub progress: u8,
     pub level: u8,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Skill {
     pub id: IdType,
     pub fields: SkillFields,
+    /// Total XP collected from completed tasks, `level` and `progress` follow from it
+    pub xp: XpType,
     pub character_id: IdType,
     pub created_at: TimeType,
     pub updated_at: TimeType,
+    /// When the skill was moved to the trash, left out while it is not in there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<TimeType>,
 }
 
 pub struct SkillList(pub Vec<Skill>);
+
+/// Partial update, only the given fields are changed
+#[derive(Clone, Debug, Deserialize, Default, ToSchema)]
+pub struct SkillPatch {
+    pub name: Option<String>,
+    pub progress: Option<u8>,
+    pub level: Option<u8>,
+}
+
+impl From<SkillFields> for SkillPatch {
+    fn from(fields: SkillFields) -> Self {
+        SkillPatch { name: Some(fields.name), progress: Some(fields.progress), level: Some(fields.level) }
+    }
+}
+
+/// Columns the skill list can be sorted by, the first one is the default
+pub const SKILL_SORTABLE: &[&str] = &["created_at", "name", "updated_at", "level"];
+
+/// Filters for the skill list, taken from the query string
+#[derive(Clone, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SkillFilter {
+    pub character_id: Option<IdType>,
+    pub updated_since: Option<TimeType>,
+    /// List trashed entries too
+    #[serde(default)]
+    pub include_trashed: bool,
+}
@@ -1,20 +1,102 @@
-use serde::{ Serialize, Deserialize };
+use serde::{ Serialize, Deserialize, Deserializer };
+use utoipa::{ IntoParams, ToSchema };
 use crate::{ IdType, TimeType, };
+use crate::recurrence::Recurrence;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct TaskFields {
     pub name: String,
     pub description: String,
     pub completed: u8,
+    /// Reopens the task once completed, `None` for a one-off task
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Task {
     pub id: IdType,
     pub fields: TaskFields,
     pub skill_id: IdType,
     pub created_at: TimeType,
     pub updated_at: TimeType,
+    /// When the task was moved to the trash, left out while it is not in there
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<TimeType>,
+    /// When a completed recurring task is marked not completed again, left out otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reopens_at: Option<TimeType>,
 }
 
 pub struct TaskList(pub Vec<Task>);
+
+/// Partial update, only the given fields are changed
+#[derive(Clone, Debug, Deserialize, Default, ToSchema)]
+pub struct TaskPatch {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub completed: Option<u8>,
+    /// `null` makes the task a one-off again
+    #[serde(default, deserialize_with = "present")]
+    pub recurrence: Option<Option<Recurrence>>,
+}
+
+impl From<TaskFields> for TaskPatch {
+    fn from(fields: TaskFields) -> Self {
+        TaskPatch {
+            name: Some(fields.name),
+            description: Some(fields.description),
+            completed: Some(fields.completed),
+            recurrence: Some(fields.recurrence),
+        }
+    }
+}
+
+/// Tells a `null` apart from a missing field, which stays `None`
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// A completed occurrence of a recurring task
+#[derive(Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TaskCompletion {
+    /// Start of the occurrence
+    pub occurrence: TimeType,
+    pub completed_at: TimeType,
+}
+
+/// Completed occurrences of a task, latest first, and its streaks of consecutive ones
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TaskHistory {
+    pub completions: Vec<TaskCompletion>,
+    /// Run of completed occurrences up to now, the one going on only breaks it once it is over
+    pub current_streak: u32,
+    pub longest_streak: u32,
+}
+
+/// Columns the task list can be sorted by, the first one is the default
+pub const TASK_SORTABLE: &[&str] = &["created_at", "name", "updated_at"];
+
+/// Filters for the task list, taken from the query string
+#[derive(Clone, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TaskFilter {
+    pub completed: Option<u8>,
+    pub skill_id: Option<IdType>,
+    pub character_id: Option<IdType>,
+    pub updated_since: Option<TimeType>,
+    /// List trashed entries too
+    #[serde(default)]
+    pub include_trashed: bool,
+}
+
+impl TaskHistory {
+    /// History of `task` as of `at`, from its `completions` latest first
+    pub fn new(task: &Task, completions: Vec<TaskCompletion>, at: TimeType) -> Self {
+        let schedule = task.fields.recurrence.as_ref().and_then(|recurrence| recurrence.schedule().ok());
+        let occurrences: Vec<TimeType> = completions.iter().map(|completion| completion.occurrence).collect();
+        let (current_streak, longest_streak) = schedule
+            .map_or((0, 0), |schedule| schedule.streaks(task.created_at, at, &occurrences));
+        TaskHistory { completions, current_streak, longest_streak }
+    }
+}
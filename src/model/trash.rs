@@ -0,0 +1,14 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::model::character::Character;
+use crate::model::skill::Skill;
+use crate::model::task::Task;
+
+/// What a user has in the trash, most recently trashed first. Entries under a trashed parent
+/// are left out, they come back or show up again once the parent is restored.
+#[derive(Serialize, ToSchema)]
+pub struct Trash {
+    pub characters: Vec<Character>,
+    pub skills: Vec<Skill>,
+    pub tasks: Vec<Task>,
+}
@@ -0,0 +1,32 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+use crate::{ IdType, TimeType };
+
+#[derive(Serialize, ToSchema)]
+pub struct User {
+    pub id: IdType,
+    pub username: String,
+    pub created_at: TimeType,
+    pub updated_at: TimeType,
+}
+
+/// Username and password for registering and logging in
+#[derive(Clone, Deserialize, ToSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Bearer token handed out on login, only its hash is stored
+#[derive(Serialize, ToSchema)]
+pub struct Session {
+    pub token: String,
+    pub user: User,
+    pub expires_at: TimeType,
+}
+
+/// The user a request was authenticated as, every character query is scoped to them
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub id: IdType,
+}
@@ -0,0 +1,88 @@
+use actix_web::{ http::{ header, StatusCode }, HttpResponse, ResponseError };
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::{ validation::FieldError, AppError };
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+/// Error body of RFC 7807. `type` is a stable code like `not_found` for clients to branch
+/// on, `detail` is meant for people.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem<'a> {
+    #[serde(rename = "type")]
+    pub code: &'a str,
+    pub title: &'a str,
+    pub status: u16,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<&'a str>,
+    /// Every rule broken by a validation error, so clients can show them next to the fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<&'a [FieldError]>,
+}
+
+impl Problem<'_> {
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR))
+            .content_type(CONTENT_TYPE)
+            .json(self)
+    }
+}
+
+/// Problem response for `error`, with the headers that go along with it
+pub fn app_error_response(error: &AppError, request_id: Option<&str>) -> HttpResponse {
+    let fields = match error {
+        AppError::ValidationError { errors } => Some(errors.as_slice()),
+        _ => None,
+    };
+    let problem = Problem {
+        code: error.code(),
+        title: error.title(),
+        status: error.status_code().as_u16(),
+        detail: error.to_string(),
+        request_id,
+        fields,
+    };
+    let mut res = problem.response();
+    let extra = match error {
+        AppError::Unauthorized => Some((header::WWW_AUTHENTICATE, "Bearer")),
+        AppError::DatabaseBusy => Some((header::RETRY_AFTER, "1")),
+        _ => None,
+    };
+    if let Some((name, value)) = extra {
+        res.headers_mut().insert(name, header::HeaderValue::from_static(value));
+    }
+    res
+}
+
+/// Problem response for any error, those raised by actix itself get a code from their status.
+/// Server errors of unknown origin keep their detail to the log.
+pub fn error_response(error: &actix_web::Error, request_id: Option<&str>) -> HttpResponse {
+    if let Some(error) = error.as_error::<AppError>() {
+        return app_error_response(error, request_id);
+    }
+    let status = error.as_response_error().status_code();
+    let code = match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        status if status.is_client_error() => "client_error",
+        _ => "internal_error",
+    };
+    let detail = if status.is_server_error() {
+        log::error!("request {} failed, {}", request_id.unwrap_or("-"), error);
+        AppError::InternalError.to_string()
+    } else {
+        error.to_string()
+    };
+    Problem {
+        code,
+        title: status.canonical_reason().unwrap_or("Error"),
+        status: status.as_u16(),
+        detail,
+        request_id,
+        fields: None,
+    }.response()
+}
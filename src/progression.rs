@@ -0,0 +1,127 @@
+/// Experience points a skill collects from completed tasks
+pub type XpType = u64;
+
+/// How completed tasks turn into skill levels.
+///
+/// Reaching level 1 takes `base_xp`, every further level takes `growth_percent`
+/// percent more than the level before it.
+#[derive(Clone, Copy, Debug)]
+pub struct LevelCurve {
+    /// XP a skill gains when one of its tasks gets completed
+    pub task_xp: XpType,
+    /// XP needed to go from level 0 to level 1
+    pub base_xp: XpType,
+    /// Extra XP each level needs compared to the previous one, in percent
+    pub growth_percent: XpType,
+}
+
+impl Default for LevelCurve {
+    fn default() -> Self {
+        LevelCurve { task_xp: 10, base_xp: 100, growth_percent: 50 }
+    }
+}
+
+impl LevelCurve {
+    /// XP needed to go from `level` to `level + 1`
+    pub fn xp_to_next(&self, level: u8) -> XpType {
+        let mut needed = self.base_xp.max(1);
+        for _ in 0..level {
+            needed = needed.saturating_mul(100 + self.growth_percent) / 100;
+        }
+        needed.max(1)
+    }
+
+    /// Level and progress towards the next level (as percentage) for a total amount of XP.
+    /// Progress stays at 100 once the maximum level is reached.
+    pub fn level_for(&self, xp: XpType) -> (u8, u8) {
+        let mut level = 0u8;
+        let mut remaining = xp;
+        loop {
+            if level == u8::MAX {
+                return (level, 100);
+            }
+            let needed = self.xp_to_next(level);
+            if remaining < needed {
+                let progress = (remaining.saturating_mul(100) / needed) as u8;
+                return (level, progress);
+            }
+            remaining -= needed;
+            level += 1;
+        }
+    }
+
+    /// Least XP that `level_for` turns into `level` and `progress`, for skills whose level was
+    /// set by hand. Progress that the level cannot show, like 100 below the maximum level,
+    /// stops just short of the next level.
+    pub fn xp_for(&self, level: u8, progress: u8) -> XpType {
+        let below = (0..level).map(|level| self.xp_to_next(level)).fold(0, XpType::saturating_add);
+        if level == u8::MAX {
+            return below;
+        }
+        let needed = self.xp_to_next(level);
+        let into_level = XpType::from(progress).saturating_mul(needed).div_ceil(100).min(needed - 1);
+        below.saturating_add(into_level)
+    }
+
+    /// XP change when a task's completed flag goes from `was_completed` to `is_completed`.
+    /// Un-completing takes back `awarded`, what the task gave when it was completed.
+    pub fn task_xp_delta(&self, was_completed: bool, is_completed: bool, awarded: XpType) -> i64 {
+        match (was_completed, is_completed) {
+            (false, true) => self.task_xp as i64,
+            (true, false) => -(awarded as i64),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_rolls_over() {
+        let curve = LevelCurve { task_xp: 10, base_xp: 100, growth_percent: 50 };
+        assert_eq!(curve.level_for(0), (0, 0));
+        assert_eq!(curve.level_for(50), (0, 50));
+        assert_eq!(curve.level_for(100), (1, 0));
+        // level 1 -> 2 needs 150
+        assert_eq!(curve.level_for(175), (1, 50));
+        assert_eq!(curve.level_for(250), (2, 0));
+    }
+
+    #[test]
+    fn flat_curve() {
+        let curve = LevelCurve { task_xp: 25, base_xp: 100, growth_percent: 0 };
+        assert_eq!(curve.level_for(1025), (10, 25));
+    }
+
+    #[test]
+    fn caps_at_max_level() {
+        let curve = LevelCurve { task_xp: 1, base_xp: 1, growth_percent: 0 };
+        assert_eq!(curve.level_for(XpType::MAX), (u8::MAX, 100));
+    }
+
+    #[test]
+    fn hand_set_levels_round_trip() {
+        let curve = LevelCurve::default();
+        assert_eq!(curve.xp_for(0, 0), 0);
+        assert_eq!(curve.xp_for(2, 0), 250);
+        for level in 0..10 {
+            for progress in 0..100 {
+                assert_eq!(curve.level_for(curve.xp_for(level, progress)), (level, progress));
+            }
+        }
+        assert_eq!(curve.level_for(curve.xp_for(1, 100)), (1, 99));
+    }
+
+    #[test]
+    fn completing_and_uncompleting_cancel_out() {
+        let curve = LevelCurve::default();
+        assert_eq!(curve.task_xp_delta(false, true, 0), 10);
+        assert_eq!(curve.task_xp_delta(true, false, 10), -10);
+        assert_eq!(curve.task_xp_delta(true, true, 10), 0);
+        assert_eq!(curve.task_xp_delta(false, false, 0), 0);
+        // a task completed before XP was tracked gave nothing, so takes nothing back
+        assert_eq!(curve.task_xp_delta(true, false, 0), 0);
+    }
+}
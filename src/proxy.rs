@@ -0,0 +1,331 @@
+use std::{ fmt, future::{ ready, Ready }, net::IpAddr, str::FromStr, sync::Arc };
+use actix_web::{
+    dev::{ Service, ServiceRequest, ServiceResponse, Transform },
+    http::header::{ HeaderMap, HeaderName, HeaderValue, FORWARDED },
+    Error,
+};
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use crate::AppError;
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// An address or a CIDR block, like `10.0.0.0/8`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{}` is not an ip address or CIDR block", s);
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max_prefix).ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Whether `pattern` from the allowed hosts is valid: `*`, a hostname or address,
+/// or `*.example.com` for every subdomain
+pub fn is_host_pattern(pattern: &str) -> bool {
+    let host = pattern.strip_prefix("*.").unwrap_or(pattern);
+    pattern == "*" || host.parse::<IpAddr>().is_ok() || is_hostname(host)
+}
+
+pub fn is_hostname(host: &str) -> bool {
+    !host.is_empty() && host.split('.').all(|label| {
+        !label.is_empty() && label.len() <= 63
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-') && !label.ends_with('-')
+    })
+}
+
+fn host_allowed(allowed_hosts: &[String], host: &str) -> bool {
+    let host = strip_port(host).to_ascii_lowercase();
+    allowed_hosts.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('*') {
+            Some("") => true,
+            Some(suffix) => host.ends_with(suffix),
+            None => host == pattern,
+        }
+    })
+}
+
+/// `host[:port]`, `ip[:port]` or `[ipv6][:port]` without the port and brackets
+fn strip_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.parse::<u16>().is_ok() => name,
+        _ => host,
+    }
+}
+
+/// Rewrite the forwarding headers so the rest of the app, `ConnectionInfo` and the `Logger`
+/// included, can take them at face value. Headers from an untrusted peer are dropped, anyone
+/// could have sent them. Behind trusted proxies they are reduced to a single `X-Forwarded-*`
+/// value each, with the client being the closest hop that is not a trusted proxy.
+fn normalize_forwarded(headers: &mut HeaderMap, peer: Option<IpAddr>, trusted: &[IpRange]) {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+
+    let forwarded = if peer.is_some_and(is_trusted) {
+        Some(Forwarded::from_headers(headers))
+    } else {
+        None
+    };
+
+    for name in [&FORWARDED, &X_FORWARDED_FOR, &X_FORWARDED_HOST, &X_FORWARDED_PROTO] {
+        headers.remove(name);
+    }
+
+    let Some(forwarded) = forwarded else {
+        return;
+    };
+    let client = forwarded.hops.iter().rev()
+        .find(|hop| strip_port(hop).parse().map_or(true, |ip| !is_trusted(ip)))
+        .or(forwarded.hops.first());
+    for (name, value) in [(&X_FORWARDED_FOR, client), (&X_FORWARDED_HOST, forwarded.host.as_ref()), (&X_FORWARDED_PROTO, forwarded.proto.as_ref())] {
+        if let Some(value) = value.and_then(|value| HeaderValue::from_str(value).ok()) {
+            headers.insert(name.clone(), value);
+        }
+    }
+}
+
+/// What the proxies in front of us said about the original request
+#[derive(Debug, Default, PartialEq)]
+struct Forwarded {
+    /// Addresses from the client to the last proxy
+    hops: Vec<String>,
+    host: Option<String>,
+    proto: Option<String>,
+}
+
+impl Forwarded {
+    /// Read from the standard `Forwarded` header, or `X-Forwarded-*` when it is missing
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let values = |name: &HeaderName| -> Vec<String> {
+            headers.get_all(name)
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+
+        let elements = values(&FORWARDED);
+        if elements.is_empty() {
+            return Forwarded {
+                hops: values(&X_FORWARDED_FOR),
+                host: values(&X_FORWARDED_HOST).into_iter().next(),
+                proto: values(&X_FORWARDED_PROTO).into_iter().next(),
+            };
+        }
+
+        let mut forwarded = Forwarded::default();
+        for element in elements {
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim().trim_matches('"').to_string();
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => forwarded.hops.push(value),
+                    "host" => { forwarded.host.get_or_insert(value); },
+                    "proto" => { forwarded.proto.get_or_insert(value); },
+                    _ => {},
+                }
+            }
+        }
+        forwarded
+    }
+}
+
+/// Middleware that trusts forwarding headers only from the configured proxies and
+/// rejects requests for hosts that are not allowed. Wrap it around everything else.
+#[derive(Clone)]
+pub struct ProxyHeaders {
+    trusted_proxies: Arc<Vec<IpRange>>,
+    allowed_hosts: Arc<Vec<String>>,
+}
+
+impl ProxyHeaders {
+    pub fn new(trusted_proxies: Vec<IpRange>, allowed_hosts: Vec<String>) -> Self {
+        ProxyHeaders { trusted_proxies: Arc::new(trusted_proxies), allowed_hosts: Arc::new(allowed_hosts) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ProxyHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ProxyHeadersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ProxyHeadersMiddleware { service, config: self.clone() }))
+    }
+}
+
+pub struct ProxyHeadersMiddleware<S> {
+    service: S,
+    config: ProxyHeaders,
+}
+
+impl<S, B> Service<ServiceRequest> for ProxyHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        normalize_forwarded(req.headers_mut(), peer, &self.config.trusted_proxies);
+
+        let host = req.connection_info().host().to_string();
+        if !host_allowed(&self.config.allowed_hosts, &host) {
+            log::warn!("rejected request for host `{}` from {}", host, req.connection_info().realip_remote_addr().unwrap_or("-"));
+            return Box::pin(ready(Err(AppError::HostNotAllowed { host }.into())));
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ http::StatusCode, test::{ call_and_read_body, init_service, try_call_service, TestRequest }, web, App, HttpRequest };
+
+    fn ranges(ranges: &[&str]) -> Vec<IpRange> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn ip_ranges_match_their_block() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        let range: IpRange = "::1".parse().unwrap();
+        assert!(range.contains("::1".parse().unwrap()));
+        assert!(!range.contains("127.0.0.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("localhost".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn hosts_match_patterns() {
+        let allowed = vec!["localhost".to_string(), "*.example.com".to_string(), "::1".to_string()];
+        assert!(host_allowed(&allowed, "localhost:3000"));
+        assert!(host_allowed(&allowed, "API.example.com"));
+        assert!(host_allowed(&allowed, "[::1]:3000"));
+        assert!(!host_allowed(&allowed, "example.com"));
+        assert!(!host_allowed(&allowed, "evil.test"));
+        assert!(host_allowed(&["*".to_string()], "evil.test"));
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_forward() {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR.clone(), HeaderValue::from_static("1.2.3.4"));
+        headers.insert(FORWARDED, HeaderValue::from_static("for=1.2.3.4;proto=https"));
+        normalize_forwarded(&mut headers, Some("203.0.113.9".parse().unwrap()), &ranges(&["10.0.0.0/8"]));
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn client_is_the_closest_untrusted_hop() {
+        let trusted = ranges(&["10.0.0.0/8"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR.clone(), HeaderValue::from_static("6.6.6.6, 198.51.100.7, 10.0.0.2"));
+        headers.insert(X_FORWARDED_PROTO.clone(), HeaderValue::from_static("https"));
+        normalize_forwarded(&mut headers, Some("10.0.0.1".parse().unwrap()), &trusted);
+        assert_eq!(headers.get(&X_FORWARDED_FOR).unwrap(), "198.51.100.7");
+        assert_eq!(headers.get(&X_FORWARDED_PROTO).unwrap(), "https");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED, HeaderValue::from_static(r#"for="[2001:db8::1]:4711";proto=https;host=game.example, for=10.0.0.2"#));
+        normalize_forwarded(&mut headers, Some("10.0.0.1".parse().unwrap()), &trusted);
+        assert_eq!(headers.get(&X_FORWARDED_FOR).unwrap(), "[2001:db8::1]:4711");
+        assert_eq!(headers.get(&X_FORWARDED_HOST).unwrap(), "game.example");
+        assert!(headers.get(FORWARDED).is_none());
+    }
+
+    #[actix_web::test]
+    async fn rejects_hosts_that_are_not_allowed() {
+        let app = init_service(
+            App::new()
+                .wrap(ProxyHeaders::new(ranges(&["127.0.0.1"]), vec!["game.example".to_string()]))
+                .route("/", web::get().to(|req: HttpRequest| async move {
+                    let info = req.connection_info();
+                    format!("{} {} {}", info.scheme(), info.host(), info.realip_remote_addr().unwrap_or("-"))
+                }))
+        ).await;
+
+        let req = TestRequest::get().insert_header(("Host", "other.example")).to_request();
+        let err = try_call_service(&app, req).await.err().unwrap();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::get()
+            .peer_addr("127.0.0.1:50000".parse().unwrap())
+            .insert_header(("Host", "internal:3000"))
+            .insert_header((FORWARDED, "for=198.51.100.7;proto=https;host=game.example"))
+            .to_request();
+        let body = call_and_read_body(&app, req).await;
+        assert_eq!(body, "https game.example 198.51.100.7");
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{ Serialize, Deserialize };
+use utoipa::ToSchema;
+use crate::TimeType;
+
+pub const DAY_MS: TimeType = 24 * 60 * 60 * 1000;
+
+/// Longest gap between two occurrences a rule may ask for, in days or weeks
+pub const MAX_INTERVAL: u64 = 365;
+
+/// Day of the week, `mon` to `sun` in JSON
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon, Tue, Wed, Thu, Fri, Sat, Sun,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+    /// Weekday of a day counted from 1970-01-01, which was a Thursday
+    fn of_day(day: u64) -> Weekday {
+        Weekday::ALL[((day + 3) % 7) as usize]
+    }
+
+    /// Two letter RRULE name, like `MO`
+    fn from_rrule(name: &str) -> Option<Weekday> {
+        let index = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"].iter().position(|day| *day == name)?;
+        Some(Weekday::ALL[index])
+    }
+}
+
+/// When a recurring task comes back. Occurrences start at midnight UTC, counted from the day
+/// the task was created.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Recurrence {
+    Daily,
+    /// Every week on the given days
+    Weekly { weekdays: Vec<Weekday> },
+    EveryNDays { days: u64 },
+    /// RRULE subset: `FREQ=DAILY` or `FREQ=WEEKLY` with optional `INTERVAL` and `BYDAY`,
+    /// like `FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH`
+    Rrule { rule: String },
+}
+
+impl Recurrence {
+    /// The days this recurrence falls on, or the rule it breaks
+    pub fn schedule(&self) -> Result<Schedule, String> {
+        let schedule = match self {
+            Recurrence::Daily => Schedule { weeks: false, interval: 1, weekdays: Vec::new() },
+            Recurrence::Weekly { weekdays } if weekdays.is_empty() => return Err("weekly needs at least one weekday".to_string()),
+            Recurrence::Weekly { weekdays } => Schedule { weeks: true, interval: 1, weekdays: weekdays.clone() },
+            Recurrence::EveryNDays { days } => Schedule { weeks: false, interval: *days, weekdays: Vec::new() },
+            Recurrence::Rrule { rule } => parse_rrule(rule)?,
+        };
+        if !(1..=MAX_INTERVAL).contains(&schedule.interval) {
+            return Err(format!("interval must be 1 to {}", MAX_INTERVAL));
+        }
+        Ok(schedule)
+    }
+}
+
+/// `Recurrence` reduced to a period of `interval` days or weeks, on `weekdays` for weekly ones
+#[derive(Debug, PartialEq)]
+pub struct Schedule {
+    weeks: bool,
+    interval: u64,
+    /// Empty for the weekday of the first day
+    weekdays: Vec<Weekday>,
+}
+
+impl Schedule {
+    /// Whether an occurrence starts on `day`, both days counted from 1970-01-01
+    fn occurs_on(&self, first_day: u64, day: u64) -> bool {
+        if day < first_day {
+            return false;
+        }
+        if !self.weeks {
+            return (day - first_day).is_multiple_of(self.interval);
+        }
+        // weeks start on monday
+        let weeks_apart = (day + 3) / 7 - (first_day + 3) / 7;
+        let weekday = Weekday::of_day(day);
+        let on_weekday = if self.weekdays.is_empty() { weekday == Weekday::of_day(first_day) } else { self.weekdays.contains(&weekday) };
+        weeks_apart.is_multiple_of(self.interval) && on_weekday
+    }
+
+    /// Longest stretch of days without an occurrence, and then some
+    fn max_gap(&self) -> u64 {
+        if self.weeks { self.interval * 7 + 7 } else { self.interval }
+    }
+
+    /// Start of the occurrence going on at `at`, `None` before the first one. `start` is when
+    /// the recurrence began.
+    pub fn current(&self, start: TimeType, at: TimeType) -> Option<TimeType> {
+        let (first_day, day) = (start / DAY_MS, at / DAY_MS);
+        (day.saturating_sub(self.max_gap())..=day).rev()
+            .find(|day| self.occurs_on(first_day, *day))
+            .map(|day| day * DAY_MS)
+    }
+
+    /// Start of the first occurrence after the day of `at`
+    pub fn next(&self, start: TimeType, at: TimeType) -> TimeType {
+        let (first_day, day) = (start / DAY_MS, (at / DAY_MS).max(start / DAY_MS));
+        (day + 1..=day + self.max_gap() + 1)
+            .find(|day| self.occurs_on(first_day, *day))
+            .unwrap_or(day + 1) * DAY_MS
+    }
+
+    /// Occurrence a completion at `at` counts for, the current one or, before the first one,
+    /// the first one
+    pub fn completed_occurrence(&self, start: TimeType, at: TimeType) -> TimeType {
+        self.current(start, at).unwrap_or_else(|| self.next(start, at))
+    }
+
+    /// Current and longest run of completed occurrences up to `at`, `completed` holds the
+    /// starts of the completed ones. The occurrence going on at `at` only breaks the current
+    /// run once it is over.
+    pub fn streaks(&self, start: TimeType, at: TimeType, completed: &[TimeType]) -> (u32, u32) {
+        let (first_day, last_day) = (start / DAY_MS, at / DAY_MS);
+        let (mut run, mut longest, mut current) = (0, 0, 0);
+        for day in (first_day..=last_day).filter(|day| self.occurs_on(first_day, *day)) {
+            if completed.contains(&(day * DAY_MS)) {
+                run += 1;
+                longest = longest.max(run);
+                current = run;
+            } else {
+                current = if day == last_day { run } else { 0 };
+                run = 0;
+            }
+        }
+        (current, longest)
+    }
+}
+
+fn parse_rrule(rule: &str) -> Result<Schedule, String> {
+    let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+    let mut schedule = Schedule { weeks: false, interval: 1, weekdays: Vec::new() };
+    let mut freq = None;
+    for part in rule.split(';') {
+        let (name, value) = part.split_once('=').ok_or(format!("`{}` is not NAME=VALUE", part))?;
+        match name {
+            "FREQ" => freq = Some(value),
+            "INTERVAL" => schedule.interval = value.parse().map_err(|_| format!("INTERVAL `{}` is not a number", value))?,
+            "BYDAY" => schedule.weekdays = value.split(',')
+                .map(|day| Weekday::from_rrule(day).ok_or(format!("BYDAY `{}` is not a weekday like MO", day)))
+                .collect::<Result<_, _>>()?,
+            _ => return Err(format!("{} is not supported, only FREQ, INTERVAL and BYDAY are", name)),
+        }
+    }
+    match freq {
+        Some("DAILY") if schedule.weekdays.is_empty() => Ok(schedule),
+        Some("DAILY") => Err("BYDAY needs FREQ=WEEKLY".to_string()),
+        Some("WEEKLY") => Ok(Schedule { weeks: true, ..schedule }),
+        Some(freq) => Err(format!("FREQ `{}` is not supported, only DAILY and WEEKLY are", freq)),
+        None => Err("FREQ is missing".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01, a Monday
+    const MONDAY: TimeType = 19723 * DAY_MS;
+
+    fn day(n: u64) -> TimeType {
+        MONDAY + n * DAY_MS
+    }
+
+    #[test]
+    fn daily_and_every_n_days() {
+        let daily = Recurrence::Daily.schedule().unwrap();
+        assert_eq!(daily.current(day(0) + 500, day(3) + 1000), Some(day(3)));
+        assert_eq!(daily.next(day(0) + 500, day(3) + 1000), day(4));
+
+        let every_3 = Recurrence::EveryNDays { days: 3 }.schedule().unwrap();
+        assert_eq!(every_3.current(day(0), day(5)), Some(day(3)));
+        assert_eq!(every_3.next(day(0), day(5)), day(6));
+        assert_eq!(every_3.current(day(1), day(0)), None);
+    }
+
+    #[test]
+    fn weekly_on_weekdays() {
+        let weekly = Recurrence::Weekly { weekdays: vec![Weekday::Wed, Weekday::Fri] }.schedule().unwrap();
+        assert_eq!(weekly.current(day(0), day(1)), None);
+        assert_eq!(weekly.next(day(0), day(1)), day(2));
+        assert_eq!(weekly.current(day(0), day(8)), Some(day(4)));
+        assert_eq!(weekly.next(day(0), day(4)), day(9));
+        // completing before the first one counts for the first one
+        assert_eq!(weekly.completed_occurrence(day(0), day(0)), day(2));
+    }
+
+    #[test]
+    fn parses_the_rrule_subset() {
+        let rule = Recurrence::Rrule { rule: "RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH".to_string() };
+        let schedule = rule.schedule().unwrap();
+        assert_eq!(schedule, Schedule { weeks: true, interval: 2, weekdays: vec![Weekday::Mon, Weekday::Thu] });
+        assert_eq!(schedule.next(day(0), day(3)), day(14));
+
+        for rule in ["FREQ=MONTHLY", "FREQ=DAILY;COUNT=3", "INTERVAL=2", "FREQ=DAILY;BYDAY=MO", "FREQ=DAILY;INTERVAL=0"] {
+            assert!(Recurrence::Rrule { rule: rule.to_string() }.schedule().is_err(), "{} should be rejected", rule);
+        }
+        assert!(Recurrence::Weekly { weekdays: Vec::new() }.schedule().is_err());
+    }
+
+    #[test]
+    fn counts_streaks() {
+        let daily = Recurrence::Daily.schedule().unwrap();
+        let completed = [day(0), day(1), day(2), day(4), day(5)];
+        assert_eq!(daily.streaks(day(0), day(5), &completed), (2, 3));
+        // today's occurrence can still be completed
+        assert_eq!(daily.streaks(day(0), day(6), &completed), (2, 3));
+        assert_eq!(daily.streaks(day(0), day(7), &completed), (0, 3));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{ sync::Arc, time::Duration };
+use actix_web::{ http::header::IfMatch, web };
+use async_trait::async_trait;
+use derive_more::derive::Display;
+use crate::{ AppError, IdType, now, TimeType };
+use crate::config::{ DatabaseConfig, RecurrenceConfig, TrashConfig };
+use crate::model::activity::{ Activity, ActivityFilter };
+use crate::model::character::{ Character, CharacterFields, CharacterFilter, CharacterPatch, CharacterTree, Include };
+use crate::model::page::{ Page, PageRequest };
+use crate::model::skill::{ Skill, SkillFields, SkillFilter, SkillList, SkillPatch };
+use crate::model::task::{ Task, TaskFields, TaskFilter, TaskHistory, TaskList, TaskPatch };
+use crate::model::trash::Trash;
+use crate::model::user::{ AuthUser, Credentials, Session, User };
+use crate::progression::LevelCurve;
+
+pub mod sqlite;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+pub use sqlite::SqliteRepo;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresRepo;
+
+/// Characters of the acting user. Other users' characters look the same as missing ones,
+/// `AppError::NotFound`.
+///
+/// Methods taking an `if_match` only change the entity while it still matches it, otherwise
+/// they fail with `AppError::PreconditionFailed`.
+///
+/// Reads nest the children `include` asks for, fetched with one query per kind of child
+/// however many characters there are.
+#[async_trait(?Send)]
+pub trait CharacterRepo: Send + Sync {
+    async fn list_characters(&self, user: &AuthUser, filter: CharacterFilter, page: PageRequest, include: Include) -> Result<Page<CharacterTree>, AppError>;
+    async fn get_character(&self, user: &AuthUser, id: IdType, include: Include) -> Result<CharacterTree, AppError>;
+    async fn create_character(&self, user: &AuthUser, fields: CharacterFields) -> Result<Character, AppError>;
+    async fn update_character(&self, user: &AuthUser, id: IdType, patch: CharacterPatch, if_match: Option<IfMatch>) -> Result<Character, AppError>;
+    /// Moves it to the trash, with its skills and tasks
+    async fn delete_character(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError>;
+    /// Bring a trashed character back, with the skills and tasks trashed along with it
+    async fn restore_character(&self, user: &AuthUser, id: IdType) -> Result<Character, AppError>;
+    /// Delete every character of `user`, with their skills and tasks
+    async fn delete_all_characters(&self, user: &AuthUser) -> Result<(), AppError>;
+}
+
+/// Skills under the acting user's characters. A level and progress given by the client are
+/// turned into the XP they take on `curve`.
+#[async_trait(?Send)]
+pub trait SkillRepo: Send + Sync {
+    async fn list_skills(&self, user: &AuthUser, filter: SkillFilter, page: PageRequest) -> Result<Page<Skill>, AppError>;
+    async fn character_skills(&self, user: &AuthUser, character_id: IdType) -> Result<SkillList, AppError>;
+    async fn get_skill(&self, user: &AuthUser, id: IdType) -> Result<Skill, AppError>;
+    async fn create_skill(&self, user: &AuthUser, character_id: IdType, fields: SkillFields, curve: LevelCurve) -> Result<Skill, AppError>;
+    async fn update_skill(&self, user: &AuthUser, id: IdType, patch: SkillPatch, curve: LevelCurve, if_match: Option<IfMatch>) -> Result<Skill, AppError>;
+    /// Moves it to the trash, with its tasks
+    async fn delete_skill(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError>;
+    /// Bring a trashed skill back, with the tasks trashed along with it. `AppError::Conflict`
+    /// while its character is in the trash.
+    async fn restore_skill(&self, user: &AuthUser, id: IdType) -> Result<Skill, AppError>;
+}
+
+/// Tasks under the acting user's skills. Completing a task awards its skill XP on `curve`,
+/// marking it not completed again takes the XP back. A completed recurring task is reopened
+/// when its next occurrence starts, and completing it again awards the XP again.
+#[async_trait(?Send)]
+pub trait TaskRepo: Send + Sync {
+    async fn list_tasks(&self, user: &AuthUser, filter: TaskFilter, page: PageRequest) -> Result<Page<Task>, AppError>;
+    async fn skill_tasks(&self, user: &AuthUser, skill_id: IdType) -> Result<TaskList, AppError>;
+    async fn character_tasks(&self, user: &AuthUser, character_id: IdType) -> Result<TaskList, AppError>;
+    async fn get_task(&self, user: &AuthUser, id: IdType) -> Result<Task, AppError>;
+    async fn create_task(&self, user: &AuthUser, skill_id: IdType, fields: TaskFields, curve: LevelCurve) -> Result<Task, AppError>;
+    async fn update_task(&self, user: &AuthUser, id: IdType, patch: TaskPatch, curve: LevelCurve, if_match: Option<IfMatch>) -> Result<Task, AppError>;
+    /// Moves it to the trash
+    async fn delete_task(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError>;
+    /// Bring a trashed task back, `AppError::Conflict` while its skill is in the trash
+    async fn restore_task(&self, user: &AuthUser, id: IdType) -> Result<Task, AppError>;
+    /// Completed occurrences of a recurring task and its streaks, empty for a one-off task
+    async fn task_history(&self, user: &AuthUser, id: IdType) -> Result<TaskHistory, AppError>;
+    /// Reopen every user's completed recurring tasks whose next occurrence has started by
+    /// `at`, returns how many were reopened
+    async fn reopen_tasks(&self, at: TimeType) -> Result<usize, AppError>;
+}
+
+/// History of the changes made to a character and everything under it, written along with
+/// every change
+#[async_trait(?Send)]
+pub trait ActivityRepo: Send + Sync {
+    async fn character_activity(&self, user: &AuthUser, character_id: IdType, filter: ActivityFilter, page: PageRequest) -> Result<Page<Activity>, AppError>;
+}
+
+/// Entities moved to the trash, gone from lists and lookups until restored or purged
+#[async_trait(?Send)]
+pub trait TrashRepo: Send + Sync {
+    async fn list_trash(&self, user: &AuthUser) -> Result<Trash, AppError>;
+    /// Permanently delete every user's entities trashed before `before`, returns how many
+    /// were removed
+    async fn purge_trash(&self, before: TimeType) -> Result<usize, AppError>;
+}
+
+/// Accounts and their sessions, used before anyone is authenticated
+#[async_trait(?Send)]
+pub trait UserRepo: Send + Sync {
+    async fn register(&self, credentials: Credentials) -> Result<User, AppError>;
+    /// New session valid for `ttl` milliseconds, `AppError::Unauthorized` on a wrong password
+    async fn login(&self, credentials: Credentials, ttl: TimeType) -> Result<Session, AppError>;
+    /// User of an unexpired session, `None` for an unknown or expired token
+    async fn authenticate(&self, token: String) -> Result<Option<AuthUser>, AppError>;
+    async fn logout(&self, token: String) -> Result<(), AppError>;
+}
+
+/// Every repository of one storage backend
+pub trait Repo: CharacterRepo + SkillRepo + TaskRepo + ActivityRepo + TrashRepo + UserRepo {}
+
+impl<R: CharacterRepo + SkillRepo + TaskRepo + ActivityRepo + TrashRepo + UserRepo> Repo for R {}
+
+#[derive(Debug, Display)]
+pub enum OpenError {
+    #[display("cannot open db {target}: {error}")]
+    Connect { target: String, error: String },
+    #[display("cannot migrate db: {_0}")]
+    Migrate(String),
+}
+
+/// Open the database `config` points at, the PostgreSQL one when `url` is set and the SQLite
+/// file otherwise, and bring its schema up to date. Skills from before XP was tracked get the
+/// XP of their level on `curve`. Blocks, call it from the blocking thread pool.
+pub fn open(config: &DatabaseConfig, curve: &LevelCurve) -> Result<Arc<dyn Repo>, OpenError> {
+    #[cfg(feature = "postgres")]
+    if config.url.is_some() {
+        return Ok(Arc::new(PostgresRepo::open(config, curve)?));
+    }
+    Ok(Arc::new(SqliteRepo::open(config, curve)?))
+}
+
+/// Hand `repo` to the handlers, each one takes the trait it needs as `web::Data<dyn ...Repo>`
+pub fn configure(repo: Arc<dyn Repo>) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(web::Data::<dyn CharacterRepo>::from(repo.clone() as Arc<dyn CharacterRepo>))
+            .app_data(web::Data::<dyn SkillRepo>::from(repo.clone() as Arc<dyn SkillRepo>))
+            .app_data(web::Data::<dyn TaskRepo>::from(repo.clone() as Arc<dyn TaskRepo>))
+            .app_data(web::Data::<dyn ActivityRepo>::from(repo.clone() as Arc<dyn ActivityRepo>))
+            .app_data(web::Data::<dyn TrashRepo>::from(repo.clone() as Arc<dyn TrashRepo>))
+            .app_data(web::Data::<dyn UserRepo>::from(repo as Arc<dyn UserRepo>));
+    }
+}
+
+/// Permanently delete what has been in the trash for longer than `config.retention_days`, right
+/// away and then every `config.purge_interval_minutes`. Has to be called from within the actix
+/// runtime.
+pub fn spawn_trash_purge(repo: Arc<dyn Repo>, config: &TrashConfig) {
+    if config.retention_days == 0 {
+        return;
+    }
+    let retention_ms = config.retention_days * 24 * 60 * 60 * 1000;
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(config.purge_interval_minutes * 60));
+    actix_web::rt::spawn(async move {
+        loop {
+            interval.tick().await;
+            match repo.purge_trash(now().saturating_sub(retention_ms)).await {
+                Ok(0) => {},
+                Ok(removed) => log::info!("purged {} entries from the trash", removed),
+                Err(e) => log::error!("cannot purge the trash, {}", e),
+            }
+        }
+    });
+}
+
+/// Reopen the completed recurring tasks whose next occurrence has started, right away and then
+/// every `config.check_interval_seconds`. Has to be called from within the actix runtime.
+pub fn spawn_task_reopening(repo: Arc<dyn Repo>, config: &RecurrenceConfig) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(config.check_interval_seconds));
+    actix_web::rt::spawn(async move {
+        loop {
+            interval.tick().await;
+            match repo.reopen_tasks(now()).await {
+                Ok(0) => {},
+                Ok(reopened) => log::info!("reopened {} recurring tasks", reopened),
+                Err(e) => log::error!("cannot reopen recurring tasks, {}", e),
+            }
+        }
+    });
+}
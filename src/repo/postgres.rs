@@ -0,0 +1,567 @@
+use std::{ thread::sleep, time::{ Duration, Instant } };
+use actix_web::{ http::header::IfMatch, web };
+use async_trait::async_trait;
+use postgres::{ error::SqlState, Client, IsolationLevel, NoTls };
+use r2d2_postgres::PostgresConnectionManager;
+use crate::{ AppError, IdType, now, TimeType };
+use crate::config::DatabaseConfig;
+use crate::db::{ Entity, Owned, Scope, FIRST_BUSY_BACKOFF, MAX_BUSY_BACKOFF };
+use crate::etag::{ entity_tag, if_match_passes };
+use crate::model::activity::{ Activity, ActivityFilter };
+use crate::model::character::{ Character, CharacterFields, CharacterFilter, CharacterPatch, CharacterTree, Include };
+use crate::model::page::{ Page, PageRequest };
+use crate::model::skill::{ Skill, SkillFields, SkillFilter, SkillList, SkillPatch };
+use crate::model::task::{ Task, TaskFields, TaskFilter, TaskHistory, TaskList, TaskPatch };
+use crate::model::trash::Trash;
+use crate::model::user::{ AuthUser, Credentials, Session, User };
+use crate::progression::LevelCurve;
+use super::{ ActivityRepo, CharacterRepo, OpenError, SkillRepo, TaskRepo, TrashRepo, UserRepo };
+
+mod activity;
+mod character;
+mod decode;
+pub mod migration;
+mod page;
+mod patch;
+mod skill;
+mod task;
+mod trash;
+mod user;
+
+/// The query modules work inside a transaction, so the checks of a call and its writes see
+/// the same data
+pub type Transaction<'a> = postgres::Transaction<'a>;
+
+type Connections = r2d2::Pool<PostgresConnectionManager<NoTls>>;
+
+/// Repositories on a PostgreSQL database, which several servers can share. Every call runs in
+/// its own transaction on a pooled connection, see `transaction` for the time budget and
+/// retries.
+pub struct PostgresRepo {
+    /// Only `None` while dropping, see `Drop`
+    connections: Option<Connections>,
+    query_timeout: Duration,
+}
+
+/// Sets the lock timeout on every connection once, when the pool opens it
+#[derive(Debug)]
+struct LockTimeout(Duration);
+
+impl r2d2::CustomizeConnection<Client, postgres::Error> for LockTimeout {
+    fn on_acquire(&self, conn: &mut Client) -> Result<(), postgres::Error> {
+        conn.batch_execute(&format!("SET lock_timeout = {}", self.0.as_millis()))
+    }
+}
+
+impl PostgresRepo {
+    /// Connect to the database at `config.url` and migrate it. Refuses a database from a
+    /// newer build. Connections are made without TLS, keep the database on a private network.
+    /// Skills from before XP was tracked get theirs from `curve`.
+    pub fn open(config: &DatabaseConfig, curve: &LevelCurve) -> Result<Self, OpenError> {
+        let url = config.url.as_deref().unwrap_or_default();
+        let pg_config: postgres::Config = url.parse()
+            .map_err(|e: postgres::Error| OpenError::Connect { target: "postgres".to_string(), error: e.to_string() })?;
+        let target = describe(&pg_config);
+        let connect_error = |error: r2d2::Error| OpenError::Connect { target: target.clone(), error: error.to_string() };
+
+        let query_timeout = Duration::from_millis(config.query_timeout_ms);
+        let connections = r2d2::Pool::builder()
+            .max_size(config.pool_size)
+            .connection_timeout(query_timeout)
+            .connection_customizer(Box::new(LockTimeout(Duration::from_millis(config.busy_timeout_ms))))
+            .build(PostgresConnectionManager::new(pg_config, NoTls))
+            .map_err(connect_error)?;
+        let mut conn = connections.get().map_err(connect_error)?;
+        migration::migrate(&mut conn).map_err(|e| OpenError::Migrate(e.to_string()))?;
+        let filled = conn.transaction()
+            .and_then(|mut tx| {
+                let filled = skill::backfill_xp(&mut tx, curve)?;
+                tx.commit()?;
+                Ok(filled)
+            })
+            .map_err(|e| OpenError::Migrate(e.to_string()))?;
+        if filled > 0 {
+            log::info!("filled in the XP of {} skills from before XP was tracked", filled);
+        }
+        drop(conn);
+
+        Ok(PostgresRepo { connections: Some(connections), query_timeout })
+    }
+
+    fn connections(&self) -> &Connections {
+        self.connections.as_ref().expect("connections are only taken while dropping")
+    }
+
+    /// Run `f` in a single transaction on a pooled connection, on the blocking thread pool.
+    /// The owner and `if_match` of `scope` are checked in it first.
+    ///
+    /// Writers run serializable, so a write that raced with another one fails instead of
+    /// acting on stale data. Such a failure, a deadlock or a lock not given within the busy
+    /// timeout rolls the transaction back and `f` is run again after a growing pause, until
+    /// the query timeout. Running out of time while waiting for a connection or a lock fails
+    /// with `AppError::DatabaseBusy`, while running statements with `AppError::QueryTimeout`.
+    async fn transaction<R: Send + 'static>(
+        &self,
+        scope: Scope,
+        f: impl Fn(&mut Transaction) -> Result<R, AppError> + Send + 'static,
+    ) -> Result<R, AppError> {
+        let deadline = Instant::now() + self.query_timeout;
+        let connections = self.connections().clone();
+        let query_timeout = self.query_timeout;
+
+        web::block(move || {
+            let mut conn = connections.get_timeout(query_timeout).map_err(|e| {
+                log::warn!("no db connection within the query timeout, {}", e);
+                AppError::DatabaseBusy
+            })?;
+
+            let mut backoff = FIRST_BUSY_BACKOFF;
+            loop {
+                match run_in_transaction(&mut conn, &scope, deadline, &f) {
+                    // the failed attempt was rolled back, so it is safe to run again
+                    Err(AppError::DatabaseBusy) if Instant::now() + backoff < deadline => {
+                        sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BUSY_BACKOFF);
+                    },
+                    result => break result,
+                }
+            }
+        })
+            .await
+            .map_err(|_| AppError::InternalError)? // blocking error
+    }
+}
+
+/// Closing a connection blocks on the connection's own runtime, which panics on a thread that
+/// drives the server's. The last repo is dropped on one of those, so the connections are
+/// closed on a thread of their own.
+impl Drop for PostgresRepo {
+    fn drop(&mut self) {
+        if let Some(connections) = self.connections.take() {
+            let _ = std::thread::spawn(move || drop(connections)).join();
+        }
+    }
+}
+
+/// Database and hosts of `config`, leaving out the credentials
+fn describe(config: &postgres::Config) -> String {
+    let hosts = config.get_hosts().iter()
+        .map(|host| match host {
+            postgres::config::Host::Tcp(name) => name.clone(),
+            #[cfg(unix)]
+            postgres::config::Host::Unix(path) => path.display().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{} on {}", config.get_dbname().unwrap_or("postgres"), hosts)
+}
+
+fn run_in_transaction<R>(
+    conn: &mut Client,
+    scope: &Scope,
+    deadline: Instant,
+    f: impl Fn(&mut Transaction) -> Result<R, AppError>,
+) -> Result<R, AppError> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(AppError::QueryTimeout);
+    }
+
+    let mut tx = conn.build_transaction()
+        .isolation_level(if scope.write { IsolationLevel::Serializable } else { IsolationLevel::RepeatableRead })
+        .read_only(!scope.write)
+        .start()
+        .map_err(pg_error("begin transaction"))?;
+    // each statement of this attempt gets what is left of the query timeout
+    tx.batch_execute(&format!("SET LOCAL statement_timeout = {}", remaining.as_millis().max(1)))
+        .map_err(pg_error("set statement_timeout"))?;
+
+    if let Some(owned @ Owned { entity, id, .. }) = &scope.owned {
+        check_owner(&mut tx, owned)?;
+
+        // checked in the same transaction, so nobody can change the target in between
+        if let Some(if_match) = &scope.if_match {
+            check_if_match(&mut tx, *entity, *id, if_match)?;
+        }
+    }
+
+    // dropping `tx` on error rolls it back
+    let result = f(&mut tx)?;
+
+    tx.commit().map_err(pg_error("commit transaction"))?;
+    Ok(result)
+}
+
+/// Selects the owner of the entity with id `$1`
+fn owner_sql(entity: Entity) -> &'static str {
+    match entity {
+        Entity::Character => "SELECT owner_id FROM character WHERE id = $1",
+        Entity::Skill => "SELECT character.owner_id FROM skill
+            JOIN character ON character.id = skill.character_id WHERE skill.id = $1",
+        Entity::Task => "SELECT character.owner_id FROM task
+            JOIN skill ON skill.id = task.skill_id
+            JOIN character ON character.id = skill.character_id WHERE task.id = $1",
+    }
+}
+
+fn check_owner(tx: &mut Transaction, owned: &Owned) -> Result<(), AppError> {
+    let sql = format!("{}{}", owner_sql(owned.entity), owned.entity.trash_filter(owned.trashed));
+    let owner_id: Option<i64> = tx.query_opt(&sql, &[&(owned.id as i64)])
+        .map_err(pg_error("check_owner"))?
+        .and_then(|row| row.get(0));
+    if owner_id == Some(owned.owner as i64) {
+        Ok(())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Fails with `AppError::Conflict` while the parent of `entity` is in the trash, the parent
+/// has to be restored first
+fn check_parent_restored(tx: &mut Transaction, entity: Entity, id: IdType) -> Result<(), AppError> {
+    let Some(parent) = entity.parent() else {
+        return Ok(());
+    };
+    let sql = format!(
+        "SELECT deleted_at FROM {parent} WHERE id = (SELECT {parent}_id FROM {child} WHERE id = $1)",
+        parent = parent.table(),
+        child = entity.table(),
+    );
+    let deleted_at: Option<i64> = tx.query_one(&sql, &[&(id as i64)])
+        .map_err(pg_error("check_parent_restored"))?
+        .get(0);
+    match deleted_at {
+        Some(_) => Err(AppError::Conflict { reason: format!("its {} is in the trash", parent.table()) }),
+        None => Ok(()),
+    }
+}
+
+fn check_if_match(tx: &mut Transaction, entity: Entity, id: IdType, if_match: &IfMatch) -> Result<(), AppError> {
+    let updated_at: i64 = tx.query_one(
+        &format!("SELECT updated_at FROM {} WHERE id = $1", entity.table()),
+        &[&(id as i64)],
+    ).map_err(pg_error("check_if_match"))?.get(0);
+    if if_match_passes(if_match, &entity_tag(id, updated_at as TimeType)) {
+        Ok(())
+    } else {
+        Err(AppError::PreconditionFailed)
+    }
+}
+
+/// Maps errors of the query modules to user facing errors, `context` names the failing step.
+///
+/// A broken foreign key means the parent an entity should be created under doesn't exist.
+/// Losing a race to another writer, a deadlock and a lock that was not given in time are
+/// temporary, as is a statement cancelled at its deadline, the client may retry. Anything
+/// else is logged, clients only learn that the database failed.
+pub fn pg_error(context: &'static str) -> impl Fn(postgres::Error) -> AppError {
+    move |e| match e.code() {
+        Some(code) if *code == SqlState::FOREIGN_KEY_VIOLATION => AppError::NotFound,
+        Some(code) if [SqlState::T_R_SERIALIZATION_FAILURE, SqlState::T_R_DEADLOCK_DETECTED, SqlState::LOCK_NOT_AVAILABLE].contains(code) => {
+            log::warn!("in {}, {}", context, e);
+            AppError::DatabaseBusy
+        },
+        Some(code) if *code == SqlState::QUERY_CANCELED => {
+            log::warn!("in {}, query timed out", context);
+            AppError::QueryTimeout
+        },
+        _ => {
+            log::error!("in {}, {}", context, e);
+            AppError::DBError
+        },
+    }
+}
+
+#[async_trait(?Send)]
+impl CharacterRepo for PostgresRepo {
+    async fn list_characters(&self, user: &AuthUser, filter: CharacterFilter, page: PageRequest, include: Include) -> Result<Page<CharacterTree>, AppError> {
+        let owner = user.id;
+        self.transaction(Scope::read(), move |tx| {
+            let page = character::get_character_list(tx, owner, &filter, &page)?;
+            let items = character::get_character_trees(tx, page.items, include)?;
+            Ok(Page { items, total: page.total, next_cursor: page.next_cursor })
+        }).await
+    }
+
+    async fn get_character(&self, user: &AuthUser, id: IdType, include: Include) -> Result<CharacterTree, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Character, id), move |tx| {
+            let character = character::get_character(tx, id)?;
+            let mut trees = character::get_character_trees(tx, vec![character], include)?;
+            Ok(trees.remove(0))
+        }).await
+    }
+
+    async fn create_character(&self, user: &AuthUser, fields: CharacterFields) -> Result<Character, AppError> {
+        let owner = user.id;
+        self.transaction(Scope::write(), move |tx| {
+            let id = character::create_character(tx, owner, fields.clone())?;
+            character::get_character(tx, id)
+        }).await
+    }
+
+    async fn update_character(&self, user: &AuthUser, id: IdType, patch: CharacterPatch, if_match: Option<IfMatch>) -> Result<Character, AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Character, id).if_match(if_match);
+        self.transaction(scope, move |tx| {
+            character::update_character(tx, actor, id, patch.clone())?;
+            character::get_character(tx, id)
+        }).await
+    }
+
+    async fn delete_character(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Character, id).if_match(if_match);
+        self.transaction(scope, move |tx| {
+            character::delete_character(tx, actor, id)
+        }).await
+    }
+
+    async fn restore_character(&self, user: &AuthUser, id: IdType) -> Result<Character, AppError> {
+        let actor = user.id;
+        self.transaction(Scope::write().trashed_owned_by(actor, Entity::Character, id), move |tx| {
+            character::restore_character(tx, actor, id)?;
+            character::get_character(tx, id)
+        }).await
+    }
+
+    async fn delete_all_characters(&self, user: &AuthUser) -> Result<(), AppError> {
+        let owner = user.id;
+        self.transaction(Scope::write(), move |tx| {
+            character::delete_all_characters(tx, owner)
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl SkillRepo for PostgresRepo {
+    async fn list_skills(&self, user: &AuthUser, filter: SkillFilter, page: PageRequest) -> Result<Page<Skill>, AppError> {
+        let owner = user.id;
+        self.transaction(Scope::read(), move |tx| {
+            skill::get_skill_page(tx, owner, &filter, &page)
+        }).await
+    }
+
+    async fn character_skills(&self, user: &AuthUser, character_id: IdType) -> Result<SkillList, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Character, character_id), move |tx| {
+            skill::get_skill_list(tx, character_id)
+        }).await
+    }
+
+    async fn get_skill(&self, user: &AuthUser, id: IdType) -> Result<Skill, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Skill, id), move |tx| {
+            skill::get_skill(tx, id)
+        }).await
+    }
+
+    async fn create_skill(&self, user: &AuthUser, character_id: IdType, fields: SkillFields, curve: LevelCurve) -> Result<Skill, AppError> {
+        let actor = user.id;
+        self.transaction(Scope::write().owned_by(actor, Entity::Character, character_id), move |tx| {
+            let id = skill::create_skill(tx, actor, character_id, fields.clone(), &curve)?;
+            skill::get_skill(tx, id)
+        }).await
+    }
+
+    async fn update_skill(&self, user: &AuthUser, id: IdType, patch: SkillPatch, curve: LevelCurve, if_match: Option<IfMatch>) -> Result<Skill, AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Skill, id).if_match(if_match);
+        self.transaction(scope, move |tx| {
+            skill::update_skill(tx, actor, id, patch.clone(), &curve)?;
+            skill::get_skill(tx, id)
+        }).await
+    }
+
+    async fn delete_skill(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Skill, id).if_match(if_match);
+        self.transaction(scope, move |tx| {
+            skill::delete_skill(tx, actor, id)
+        }).await
+    }
+
+    async fn restore_skill(&self, user: &AuthUser, id: IdType) -> Result<Skill, AppError> {
+        let actor = user.id;
+        self.transaction(Scope::write().trashed_owned_by(actor, Entity::Skill, id), move |tx| {
+            check_parent_restored(tx, Entity::Skill, id)?;
+            skill::restore_skill(tx, actor, id)?;
+            skill::get_skill(tx, id)
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl TaskRepo for PostgresRepo {
+    async fn list_tasks(&self, user: &AuthUser, filter: TaskFilter, page: PageRequest) -> Result<Page<Task>, AppError> {
+        let owner = user.id;
+        self.transaction(Scope::read(), move |tx| {
+            task::get_task_page(tx, owner, &filter, &page)
+        }).await
+    }
+
+    async fn skill_tasks(&self, user: &AuthUser, skill_id: IdType) -> Result<TaskList, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Skill, skill_id), move |tx| {
+            task::get_skill_task_list(tx, skill_id)
+        }).await
+    }
+
+    async fn character_tasks(&self, user: &AuthUser, character_id: IdType) -> Result<TaskList, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Character, character_id), move |tx| {
+            task::get_characters_task_list(tx, &[character_id])
+        }).await
+    }
+
+    async fn get_task(&self, user: &AuthUser, id: IdType) -> Result<Task, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Task, id), move |tx| {
+            task::get_task(tx, id)
+        }).await
+    }
+
+    async fn create_task(&self, user: &AuthUser, skill_id: IdType, fields: TaskFields, curve: LevelCurve) -> Result<Task, AppError> {
+        let actor = user.id;
+        self.transaction(Scope::write().owned_by(actor, Entity::Skill, skill_id), move |tx| {
+            let id = task::create_task(tx, actor, skill_id, fields.clone(), &curve)?;
+            task::get_task(tx, id)
+        }).await
+    }
+
+    async fn update_task(&self, user: &AuthUser, id: IdType, patch: TaskPatch, curve: LevelCurve, if_match: Option<IfMatch>) -> Result<Task, AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Task, id).if_match(if_match);
+        self.transaction(scope, move |tx| {
+            task::update_task(tx, actor, id, patch.clone(), &curve)?;
+            task::get_task(tx, id)
+        }).await
+    }
+
+    async fn delete_task(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Task, id).if_match(if_match);
+        self.transaction(scope, move |tx| {
+            task::delete_task(tx, actor, id)
+        }).await
+    }
+
+    async fn restore_task(&self, user: &AuthUser, id: IdType) -> Result<Task, AppError> {
+        let actor = user.id;
+        self.transaction(Scope::write().trashed_owned_by(actor, Entity::Task, id), move |tx| {
+            check_parent_restored(tx, Entity::Task, id)?;
+            task::restore_task(tx, actor, id)?;
+            task::get_task(tx, id)
+        }).await
+    }
+
+    async fn task_history(&self, user: &AuthUser, id: IdType) -> Result<TaskHistory, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Task, id), move |tx| {
+            task::get_task_history(tx, id, now())
+        }).await
+    }
+
+    async fn reopen_tasks(&self, at: TimeType) -> Result<usize, AppError> {
+        self.transaction(Scope::write(), move |tx| {
+            task::reopen_tasks(tx, at)
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl ActivityRepo for PostgresRepo {
+    async fn character_activity(&self, user: &AuthUser, character_id: IdType, filter: ActivityFilter, page: PageRequest) -> Result<Page<Activity>, AppError> {
+        self.transaction(Scope::read().owned_by(user.id, Entity::Character, character_id), move |tx| {
+            activity::get_activity_page(tx, character_id, &filter, &page)
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl TrashRepo for PostgresRepo {
+    async fn list_trash(&self, user: &AuthUser) -> Result<Trash, AppError> {
+        let owner = user.id;
+        self.transaction(Scope::read(), move |tx| {
+            trash::get_trash(tx, owner)
+        }).await
+    }
+
+    async fn purge_trash(&self, before: TimeType) -> Result<usize, AppError> {
+        self.transaction(Scope::write(), move |tx| {
+            trash::purge_trash(tx, before)
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl UserRepo for PostgresRepo {
+    async fn register(&self, credentials: Credentials) -> Result<User, AppError> {
+        self.transaction(Scope::write(), move |tx| {
+            let id = user::create_user(tx, &credentials)?;
+            user::get_user(tx, id)
+        }).await
+    }
+
+    async fn login(&self, credentials: Credentials, ttl: TimeType) -> Result<Session, AppError> {
+        self.transaction(Scope::write(), move |tx| {
+            user::create_session(tx, &credentials, ttl)
+        }).await
+    }
+
+    async fn authenticate(&self, token: String) -> Result<Option<AuthUser>, AppError> {
+        self.transaction(Scope::read(), move |tx| {
+            user::get_session_user(tx, &token)
+        }).await
+    }
+
+    async fn logout(&self, token: String) -> Result<(), AppError> {
+        self.transaction(Scope::write(), move |tx| {
+            user::delete_session(tx, &token)
+        }).await
+    }
+}
+
+/// Run `f` on a thread of its own. The client blocks on its own runtime, which panics on a
+/// test's runtime thread.
+#[cfg(test)]
+pub fn blocking<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    std::thread::scope(|scope| scope.spawn(f).join().expect("blocking test code panicked"))
+}
+
+/// Repo on a schema of its own, at the latest version, in the throwaway database that
+/// `GOL_TEST_DATABASE_URL` points to. `scripts/test-postgres.sh` starts one and runs the tests.
+#[cfg(test)]
+pub fn test_repo() -> PostgresRepo {
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    static NEXT_SCHEMA: AtomicUsize = AtomicUsize::new(0);
+
+    let url = std::env::var("GOL_TEST_DATABASE_URL")
+        .expect("GOL_TEST_DATABASE_URL must point to a throwaway database, see scripts/test-postgres.sh");
+    let schema = format!("test_{}_{}", std::process::id(), NEXT_SCHEMA.fetch_add(1, Ordering::Relaxed));
+    blocking(move || {
+        let mut config: postgres::Config = url.parse().expect("invalid GOL_TEST_DATABASE_URL");
+        config.connect(NoTls).expect("cannot connect to the test database")
+            .batch_execute(&format!("CREATE SCHEMA {schema}")).expect("cannot create test schema");
+        config.options(&format!("-c search_path={schema}"));
+
+        let connections = r2d2::Pool::builder().max_size(2).build(PostgresConnectionManager::new(config, NoTls))
+            .expect("error creating test pool");
+        migration::migrate(&mut connections.get().unwrap()).expect("cannot migrate test db");
+        PostgresRepo { connections: Some(connections), query_timeout: Duration::from_secs(5) }
+    })
+}
+
+/// User without a usable password, with a session under a made up token. Returns the user's
+/// id and the token.
+#[cfg(test)]
+pub fn test_session(repo: &PostgresRepo, username: &str) -> (IdType, String) {
+    let connections = repo.connections().clone();
+    let username = username.to_string();
+    blocking(move || {
+        let mut conn = connections.get().unwrap();
+        let id: i64 = conn.query_one(
+            r#"INSERT INTO "user" (username, password_hash, created_at, updated_at) VALUES ($1, '', 0, 0) RETURNING id"#,
+            &[&username],
+        ).expect("cannot create test user").get(0);
+        let token = format!("test-token-{}", id);
+        conn.execute(
+            "INSERT INTO session (token_hash, user_id, created_at, expires_at) VALUES ($1, $2, 0, $3)",
+            &[&crate::db::user::hash_token(&token), &id, &((TimeType::MAX >> 1) as i64)],
+        ).expect("cannot create test session");
+        (id as IdType, token)
+    })
+}
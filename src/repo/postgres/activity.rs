@@ -0,0 +1,54 @@
+use postgres::Row;
+use serde_json::Value;
+use crate::{ AppError, IdType, now, TimeType };
+use crate::db::Entity;
+use crate::model::activity::{ Action, Activity, ActivityFilter, NewActivity };
+use crate::model::page::{ Page, PageRequest };
+use super::{ pg_error, Transaction };
+use super::decode::Decoded;
+use super::page::{ get_page, Conditions };
+
+const COLUMNS: &str = "id, actor_id, character_id, entity, entity_id, action, changes, created_at";
+
+/// Write down what `actor` did, an update that changed nothing is left out
+pub fn record(tx: &mut Transaction, actor: IdType, activity: NewActivity) -> Result<(), AppError> {
+    if activity.action == Action::Update && activity.changes.is_empty() {
+        return Ok(());
+    }
+    tx.execute(
+        "INSERT INTO activity (actor_id, character_id, entity, entity_id, action, changes, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        &[
+            &(actor as i64), &(activity.character_id as i64), &activity.entity.table(), &(activity.entity_id as i64),
+            &activity.action.as_str(), &Value::Object(activity.changes).to_string(), &(now() as i64),
+        ],
+    ).map_err(pg_error("record activity"))?;
+    Ok(())
+}
+
+pub fn get_activity_page(tx: &mut Transaction, character_id: IdType, filter: &ActivityFilter, page: &PageRequest) -> Result<Page<Activity>, AppError> {
+    let mut conditions = Conditions::default();
+    conditions.add("character_id = ?", character_id as i64);
+    if let Some(entity) = filter.entity {
+        conditions.add("entity = ?", entity.table());
+    }
+    if let Some(since) = filter.since {
+        conditions.add("created_at >= ?", since as i64);
+    }
+    if let Some(until) = filter.until {
+        conditions.add("created_at < ?", until as i64);
+    }
+    get_page(tx, "activity", COLUMNS, &conditions, page, to_activity).map_err(pg_error("get_activity_page"))
+}
+
+fn to_activity(row: &Row) -> Result<Activity, postgres::Error> {
+    Ok(Activity {
+        id: row.get::<_, i64>(0) as IdType,
+        actor_id: row.get::<_, i64>(1) as IdType,
+        character_id: row.get::<_, i64>(2) as IdType,
+        entity: row.try_get::<_, Decoded<Entity>>(3)?.0,
+        entity_id: row.get::<_, i64>(4) as IdType,
+        action: row.try_get::<_, Decoded<Action>>(5)?.0,
+        changes: row.try_get::<_, Decoded<_>>(6)?.0,
+        created_at: row.get::<_, i64>(7) as TimeType,
+    })
+}
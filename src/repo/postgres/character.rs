@@ -0,0 +1,156 @@
+use postgres::{ types::ToSql, Row };
+use crate::{ AppError, IdType, now, TimeType };
+use crate::db::Entity;
+use crate::model::activity::{ Action, NewActivity };
+use crate::model::character::{ Character, CharacterFields, CharacterFilter, CharacterPatch, CharacterTree, Include };
+use crate::model::page::{ Page, PageRequest };
+use super::{ pg_error, Transaction };
+use super::activity::record;
+use super::page::{ get_page, Conditions };
+use super::patch::Assignments;
+use super::skill::get_characters_skill_list;
+use super::task::get_characters_task_list;
+
+const COLUMNS: &str = "id, name, avatar, notes, quote, created_at, updated_at, deleted_at";
+
+pub fn get_character_list(tx: &mut Transaction, owner: IdType, filter: &CharacterFilter, page: &PageRequest) -> Result<Page<Character>, AppError> {
+    let mut conditions = Conditions::default();
+    conditions.add("owner_id = ?", owner as i64);
+    if let Some(updated_since) = filter.updated_since {
+        conditions.add("updated_at >= ?", updated_since as i64);
+    }
+    if !filter.include_trashed {
+        conditions.require("deleted_at IS NULL");
+    }
+    get_page(tx, "character", COLUMNS, &conditions, page, |row| Ok(to_character(row))).map_err(pg_error("get_character_list"))
+}
+
+pub fn get_character(tx: &mut Transaction, id: IdType) -> Result<Character, AppError> {
+    let row = tx.query_opt(&format!("SELECT {} FROM character WHERE id = $1", COLUMNS), &[&(id as i64)])
+        .map_err(pg_error("get_character"))?
+        .ok_or(AppError::NotFound)?;
+    Ok(to_character(&row))
+}
+
+/// Nest the children `include` asks for under `characters`, with one query per kind of child
+pub fn get_character_trees(tx: &mut Transaction, characters: Vec<Character>, include: Include) -> Result<Vec<CharacterTree>, AppError> {
+    let ids: Vec<IdType> = characters.iter().map(|character| character.id).collect();
+    let skills = if include.skills { Some(get_characters_skill_list(tx, &ids)?.0) } else { None };
+    let tasks = if include.tasks { Some(get_characters_task_list(tx, &ids)?.0) } else { None };
+    Ok(CharacterTree::build(characters, skills, tasks))
+}
+
+pub fn create_character(tx: &mut Transaction, owner: IdType, fields: CharacterFields) -> Result<IdType, AppError> {
+    let timestamp = now() as i64;
+    let row = tx.query_one(
+        "INSERT INTO character (name, avatar, notes, quote, owner_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        &[&fields.name, &fields.avatar, &fields.notes, &fields.quote, &(owner as i64), &timestamp, &timestamp],
+    ).map_err(pg_error("create_character"))?;
+    let id = row.get::<_, i64>(0) as IdType;
+    record_change(tx, owner, id, Action::Create, None)?;
+    Ok(id)
+}
+
+pub fn update_character(tx: &mut Transaction, actor: IdType, id: IdType, patch: CharacterPatch) -> Result<(), AppError> {
+    let timestamp = now() as i64;
+    let before = get_character(tx, id)?;
+    let mut assignments = Assignments::default();
+    assignments.set("name", patch.name);
+    assignments.set("avatar", patch.avatar);
+    assignments.set("notes", patch.notes);
+    assignments.set("quote", patch.quote);
+    assignments.set("updated_at", Some(timestamp));
+    let num_rows_updated = assignments.execute(tx, "character", id).map_err(pg_error("update_character"))?;
+    if num_rows_updated == 0 {
+        return Err(AppError::NotFound);
+    }
+    record_change(tx, actor, id, Action::Update, Some(&before))
+}
+
+pub fn touch(tx: &mut Transaction, id: IdType, timestamp: TimeType) -> Result<(), AppError> {
+    let num_rows_updated = tx.execute(
+        "UPDATE character SET updated_at = $1 WHERE id = $2",
+        &[&(timestamp as i64), &(id as i64)],
+    ).map_err(pg_error("touch character"))?;
+    if num_rows_updated == 0 {
+        return Err(AppError::NotFound);
+    }
+    Ok(())
+}
+
+/// Move the character to the trash, with its skills and tasks that are not in there yet. They
+/// all get the same `deleted_at`, restoring the character brings back just those.
+pub fn delete_character(tx: &mut Transaction, actor: IdType, id: IdType) -> Result<(), AppError> {
+    let before = get_character(tx, id)?;
+    let params: [&(dyn ToSql + Sync); 2] = [&(now() as i64), &(id as i64)];
+    let num_rows_deleted = tx.execute("UPDATE character SET deleted_at = $1 WHERE id = $2 AND deleted_at IS NULL", &params)
+        .map_err(pg_error("delete_character"))?;
+    if num_rows_deleted == 0 {
+        return Err(AppError::NotFound);
+    }
+    tx.execute("UPDATE skill SET deleted_at = $1 WHERE character_id = $2 AND deleted_at IS NULL", &params)
+        .map_err(pg_error("delete_character, skills"))?;
+    tx.execute(
+        "UPDATE task SET deleted_at = $1
+        WHERE skill_id IN (SELECT id FROM skill WHERE character_id = $2) AND deleted_at IS NULL",
+        &params,
+    ).map_err(pg_error("delete_character, tasks"))?;
+    record_change(tx, actor, id, Action::Delete, Some(&before))
+}
+
+/// Bring the character back from the trash, with the skills and tasks trashed along with it
+pub fn restore_character(tx: &mut Transaction, actor: IdType, id: IdType) -> Result<(), AppError> {
+    let before = get_character(tx, id)?;
+    let deleted_at = before.deleted_at.ok_or(AppError::NotFound)?;
+    let params: [&(dyn ToSql + Sync); 2] = [&(id as i64), &(deleted_at as i64)];
+    tx.execute(
+        "UPDATE task SET deleted_at = NULL
+        WHERE skill_id IN (SELECT id FROM skill WHERE character_id = $1) AND deleted_at = $2",
+        &params,
+    ).map_err(pg_error("restore_character, tasks"))?;
+    tx.execute("UPDATE skill SET deleted_at = NULL WHERE character_id = $1 AND deleted_at = $2", &params)
+        .map_err(pg_error("restore_character, skills"))?;
+    tx.execute("UPDATE character SET deleted_at = NULL, updated_at = $1 WHERE id = $2", &[&(now() as i64), &(id as i64)])
+        .map_err(pg_error("restore_character"))?;
+    record_change(tx, actor, id, Action::Restore, Some(&before))
+}
+
+/// Characters of `owner` in the trash, most recently trashed first
+pub fn get_trashed_characters(tx: &mut Transaction, owner: IdType) -> Result<Vec<Character>, AppError> {
+    let characters = tx.query(
+        &format!("SELECT {} FROM character WHERE owner_id = $1 AND deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC", COLUMNS),
+        &[&(owner as i64)],
+    ).map_err(pg_error("get_trashed_characters"))?
+        .iter().map(to_character)
+        .collect();
+    Ok(characters)
+}
+
+/// Permanently delete the characters trashed before `before`, their skills and tasks cascade
+pub fn purge_characters(tx: &mut Transaction, before: TimeType) -> Result<u64, AppError> {
+    tx.execute("DELETE FROM character WHERE deleted_at < $1", &[&(before as i64)])
+        .map_err(pg_error("purge_characters"))
+}
+
+/// Delete all characters of `owner`, their skills and tasks go along with them.
+pub fn delete_all_characters(tx: &mut Transaction, owner: IdType) -> Result<(), AppError> {
+    tx.execute("DELETE FROM character WHERE owner_id = $1", &[&(owner as i64)])
+        .map_err(pg_error("reset_db:clear_db"))?;
+    Ok(())
+}
+
+/// Record what `actor` did to the character, `before` is how it was before the change
+fn record_change(tx: &mut Transaction, actor: IdType, id: IdType, action: Action, before: Option<&Character>) -> Result<(), AppError> {
+    let after = get_character(tx, id)?;
+    record(tx, actor, NewActivity::new(Entity::Character, id, id, action).diff(before, &after))
+}
+
+fn to_character(row: &Row) -> Character {
+    Character {
+        id: row.get::<_, i64>(0) as IdType,
+        fields: CharacterFields { name: row.get(1), avatar: row.get(2), notes: row.get(3), quote: row.get(4) },
+        created_at: row.get::<_, i64>(5) as TimeType,
+        updated_at: row.get::<_, i64>(6) as TimeType,
+        deleted_at: row.get::<_, Option<i64>>(7).map(|deleted_at| deleted_at as TimeType),
+    }
+}
@@ -0,0 +1,94 @@
+use std::error::Error;
+use postgres::types::{ FromSql, Type };
+use serde_json::Value;
+use crate::db::Entity;
+use crate::model::activity::Action;
+use crate::recurrence::Recurrence;
+
+/// TEXT column holding a `T`. A value `T` can't be read from fails `Row::try_get` like a
+/// column of the wrong type does, so a bad row becomes a database error instead of a panic.
+pub struct Decoded<T>(pub T);
+
+/// Values stored as TEXT
+pub trait FromText: Sized {
+    fn from_text(text: &str) -> Option<Self>;
+}
+
+impl<'a, T: FromText> FromSql<'a> for Decoded<T> {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let text = <&str>::from_sql(ty, raw)?;
+        T::from_text(text)
+            .map(Decoded)
+            .ok_or_else(|| format!("cannot decode {:?} as {}", text, std::any::type_name::<T>()).into())
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
+impl FromText for Entity {
+    fn from_text(text: &str) -> Option<Self> {
+        Entity::from_table(text)
+    }
+}
+
+impl FromText for Action {
+    fn from_text(text: &str) -> Option<Self> {
+        Action::parse(text)
+    }
+}
+
+impl FromText for Recurrence {
+    fn from_text(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+impl FromText for Value {
+    fn from_text(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ AppError, IdType };
+    use crate::model::user::AuthUser;
+    use crate::repo::TaskRepo;
+    use crate::repo::postgres::{ blocking, test_repo, test_session };
+
+    #[test]
+    fn rejects_text_it_cannot_read() {
+        assert!(matches!(Decoded::<Action>::from_sql(&Type::TEXT, b"create"), Ok(Decoded(Action::Create))));
+        assert!(Decoded::<Action>::from_sql(&Type::TEXT, b"explode").is_err());
+        assert!(Decoded::<Entity>::from_sql(&Type::TEXT, b"dragon").is_err());
+        assert!(Decoded::<Recurrence>::from_sql(&Type::TEXT, b"not json").is_err());
+    }
+
+    #[actix_web::test]
+    async fn undecodable_row_is_a_database_error() {
+        let repo = test_repo();
+        let (user_id, _) = test_session(&repo, "hero");
+        let connections = repo.connections().clone();
+        let task_id: i64 = blocking(move || {
+            let mut conn = connections.get().unwrap();
+            let character_id: i64 = conn.query_one(
+                "INSERT INTO character (name, avatar, notes, quote, owner_id, created_at, updated_at) VALUES ('hero', '', '', '', $1, 0, 0) RETURNING id",
+                &[&(user_id as i64)],
+            ).unwrap().get(0);
+            let skill_id: i64 = conn.query_one(
+                "INSERT INTO skill (name, progress, level, character_id, created_at, updated_at) VALUES ('guitar', 0, 0, $1, 0, 0) RETURNING id",
+                &[&character_id],
+            ).unwrap().get(0);
+            conn.query_one(
+                "INSERT INTO task (name, description, completed, recurrence, skill_id, created_at, updated_at) VALUES ('scales', '', 0, 'not json', $1, 0, 0) RETURNING id",
+                &[&skill_id],
+            ).unwrap().get(0)
+        });
+
+        let found = repo.get_task(&AuthUser { id: user_id }, task_id as IdType).await;
+        assert!(matches!(found, Err(AppError::DBError)));
+    }
+}
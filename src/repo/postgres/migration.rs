@@ -0,0 +1,209 @@
+use derive_more::derive::Display;
+use postgres::Client;
+use crate::db::migration::SchemaVersion;
+
+/// Forward migrations, in order, like the SQLite ones. Migration `i` brings the schema from
+/// version `i` to `i + 1`. Never edit an applied migration, append a new one instead.
+///
+/// The schema matches the latest SQLite one, with `BIGINT` where SQLite stores 64-bit
+/// integers, `SMALLINT` for the small ones and indexes on the foreign keys, which PostgreSQL
+/// doesn't create on its own.
+const MIGRATIONS: &[&str] = &[
+    // 1: the SQLite schema at version 3
+    r#"CREATE TABLE "user" (
+        id              BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        username        TEXT NOT NULL UNIQUE,
+        password_hash   TEXT NOT NULL,
+        created_at      BIGINT NOT NULL,
+        updated_at      BIGINT NOT NULL
+    );
+
+    CREATE TABLE session (
+        token_hash      TEXT PRIMARY KEY,
+        user_id         BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE,
+        created_at      BIGINT NOT NULL,
+        expires_at      BIGINT NOT NULL
+    );
+    CREATE INDEX session_user_id ON session(user_id);
+
+    CREATE TABLE character (
+        id          BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        name        TEXT,
+        avatar      TEXT,
+        notes       TEXT,
+        quote       TEXT,
+        owner_id    BIGINT REFERENCES "user"(id) ON DELETE CASCADE,
+
+        created_at  BIGINT NOT NULL,
+        updated_at  BIGINT NOT NULL
+    );
+    CREATE INDEX character_owner_id ON character(owner_id);
+
+    CREATE TABLE skill (
+        id              BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        name            TEXT,
+        progress        SMALLINT,
+        level           SMALLINT,
+        xp              BIGINT NOT NULL DEFAULT 0,
+        character_id    BIGINT NOT NULL REFERENCES character(id) ON DELETE CASCADE,
+        created_at      BIGINT NOT NULL,
+        updated_at      BIGINT NOT NULL
+    );
+    CREATE INDEX skill_character_id ON skill(character_id);
+
+    CREATE TABLE task (
+        id              BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        name            TEXT,
+        description     TEXT,
+        completed       SMALLINT NOT NULL CHECK (completed IN (0, 1)),
+        skill_id        BIGINT NOT NULL REFERENCES skill(id) ON DELETE CASCADE,
+        created_at      BIGINT NOT NULL,
+        updated_at      BIGINT NOT NULL
+    );
+    CREATE INDEX task_skill_id ON task(skill_id);"#,
+    // 2: trash, like SQLite's version 4
+    "ALTER TABLE character ADD COLUMN deleted_at BIGINT;
+    ALTER TABLE skill ADD COLUMN deleted_at BIGINT;
+    ALTER TABLE task ADD COLUMN deleted_at BIGINT;
+    CREATE INDEX character_deleted_at ON character(deleted_at);
+    CREATE INDEX skill_deleted_at ON skill(deleted_at);
+    CREATE INDEX task_deleted_at ON task(deleted_at);",
+    // 3: activity, like SQLite's version 5
+    r#"CREATE TABLE activity (
+        id              BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        actor_id        BIGINT NOT NULL REFERENCES "user"(id) ON DELETE CASCADE,
+        character_id    BIGINT NOT NULL REFERENCES character(id) ON DELETE CASCADE,
+        entity          TEXT NOT NULL CHECK (entity IN ('character', 'skill', 'task')),
+        entity_id       BIGINT NOT NULL,
+        action          TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete', 'restore')),
+        changes         TEXT NOT NULL,
+        created_at      BIGINT NOT NULL
+    );
+    CREATE INDEX activity_actor_id ON activity(actor_id);
+    CREATE INDEX activity_character_id ON activity(character_id, created_at);"#,
+    // 4: recurring tasks, like SQLite's version 6
+    "ALTER TABLE task ADD COLUMN recurrence TEXT;
+    ALTER TABLE task ADD COLUMN reopens_at BIGINT;
+    CREATE INDEX task_reopens_at ON task(reopens_at);
+
+    CREATE TABLE task_completion (
+        task_id         BIGINT NOT NULL REFERENCES task(id) ON DELETE CASCADE,
+        occurrence      BIGINT NOT NULL,
+        completed_at    BIGINT NOT NULL,
+        PRIMARY KEY (task_id, occurrence)
+    );",
+    // 5: XP a task's completion gave, like SQLite's version 7
+    "ALTER TABLE task ADD COLUMN xp_awarded BIGINT NOT NULL DEFAULT 0;",
+];
+
+/// Key of the advisory lock migrations hold, servers starting at once take turns
+const MIGRATION_LOCK: i64 = 0x676f6c5f6d696772; // "gol_migr"
+
+#[derive(Debug, Display)]
+pub enum MigrationError {
+    #[display("database schema version {found} is newer than the latest supported version {latest}")]
+    NewerSchema { found: SchemaVersion, latest: SchemaVersion },
+    #[display("migration to schema version {version} failed: {error}")]
+    Failed { version: SchemaVersion, error: postgres::Error },
+    #[display("cannot read or write schema version: {_0}")]
+    Version(postgres::Error),
+}
+
+/// Latest schema version known to this build.
+pub fn latest_version() -> SchemaVersion {
+    MIGRATIONS.len()
+}
+
+/// Schema version recorded in the database, 0 for a fresh database.
+pub fn current_version(conn: &mut impl postgres::GenericClient) -> Result<SchemaVersion, postgres::Error> {
+    conn.batch_execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")?;
+    let version: i32 = conn.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?.get(0);
+    Ok(version as SchemaVersion)
+}
+
+/// Bring the database up to the latest schema version.
+pub fn migrate(conn: &mut Client) -> Result<(), MigrationError> {
+    run(conn, MIGRATIONS, latest_version())
+}
+
+/// Apply pending `migrations` up to and including version `target`. Each migration runs in
+/// its own transaction together with the version bump, so a failed migration leaves the
+/// database at the previous version. The version is read again under the lock, another
+/// server may have migrated in the meantime.
+fn run(conn: &mut Client, migrations: &[&str], target: SchemaVersion) -> Result<(), MigrationError> {
+    for (index, sql) in migrations.iter().enumerate().take(target) {
+        let version = index + 1;
+        let mut tx = conn.transaction().map_err(MigrationError::Version)?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK]).map_err(MigrationError::Version)?;
+
+        let found = current_version(&mut tx).map_err(MigrationError::Version)?;
+        if found > migrations.len() {
+            return Err(MigrationError::NewerSchema { found, latest: migrations.len() });
+        }
+        if found >= version {
+            continue;
+        }
+
+        tx.batch_execute(sql).map_err(|error| MigrationError::Failed { version, error })?;
+        tx.execute("INSERT INTO schema_version (version) VALUES ($1)", &[&(version as i32)])
+            .map_err(MigrationError::Version)?;
+        tx.commit().map_err(|error| MigrationError::Failed { version, error })?;
+        log::info!("database migrated to schema version {}", version);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::postgres::test_repo;
+
+    fn table_exists(conn: &mut Client, name: &str) -> bool {
+        conn.query_one(
+            "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1",
+            &[&name],
+        ).unwrap().get::<_, i64>(0) == 1
+    }
+
+    #[test]
+    fn migrates_to_latest_once() {
+        let repo = test_repo();
+        let mut conn = repo.connections().get().unwrap();
+        assert_eq!(current_version(&mut *conn).unwrap(), latest_version());
+        for table in ["user", "session", "character", "skill", "task", "activity", "task_completion"] {
+            assert!(table_exists(&mut conn, table), "missing table {}", table);
+        }
+
+        migrate(&mut conn).unwrap();
+        let versions: i64 = conn.query_one("SELECT COUNT(*) FROM schema_version", &[]).unwrap().get(0);
+        assert_eq!(versions as SchemaVersion, latest_version());
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let repo = test_repo();
+        let mut conn = repo.connections().get().unwrap();
+        conn.execute("INSERT INTO schema_version (version) VALUES ($1)", &[&(latest_version() as i32 + 1)]).unwrap();
+        match migrate(&mut conn) {
+            Err(MigrationError::NewerSchema { found, latest }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(latest, latest_version());
+            },
+            _ => panic!("expected NewerSchema error"),
+        }
+    }
+
+    #[test]
+    fn failed_migration_keeps_previous_version() {
+        let repo = test_repo();
+        let mut conn = repo.connections().get().unwrap();
+        // a fresh schema, the test repo's is already migrated
+        conn.batch_execute("DROP TABLE task_completion, activity, task, skill, character, session, \"user\", schema_version").unwrap();
+
+        let migrations = [MIGRATIONS[0], "CREATE TABLE broken (id INTEGER); INSERT INTO missing VALUES (1);"];
+        assert!(matches!(run(&mut conn, &migrations, 2), Err(MigrationError::Failed { version: 2, .. })));
+        assert_eq!(current_version(&mut *conn).unwrap(), 1);
+        assert!(!table_exists(&mut conn, "broken"));
+    }
+}
@@ -0,0 +1,72 @@
+use postgres::{ types::ToSql, Row };
+use crate::model::page::{ Page, PageRequest };
+use super::Transaction;
+
+/// Filters of a list query, joined with `AND`. Each condition uses one `?` placeholder, or
+/// none when added with `require`. They are numbered when the query is built.
+#[derive(Default)]
+pub struct Conditions {
+    clauses: Vec<&'static str>,
+    params: Vec<Box<dyn ToSql + Sync>>,
+}
+
+impl Conditions {
+    pub fn add(&mut self, clause: &'static str, param: impl ToSql + Sync + 'static) {
+        self.clauses.push(clause);
+        self.params.push(Box::new(param));
+    }
+
+    /// Condition without a placeholder
+    pub fn require(&mut self, clause: &'static str) {
+        self.clauses.push(clause);
+    }
+
+    fn where_clause(&self) -> String {
+        if self.clauses.is_empty() {
+            return String::new();
+        }
+        let mut placeholders = 0;
+        let clauses = self.clauses.iter()
+            .map(|clause| if clause.contains('?') {
+                placeholders += 1;
+                clause.replacen('?', &format!("${}", placeholders), 1)
+            } else {
+                clause.to_string()
+            })
+            .collect::<Vec<_>>();
+        format!(" WHERE {}", clauses.join(" AND "))
+    }
+}
+
+/// Count the rows of `table` matching `conditions` and select one page of them. `columns` is
+/// the select list that `to_item` reads, a row it can't read fails the page. Ties in the sort column are broken by id so pages
+/// never overlap.
+pub fn get_page<T>(
+    tx: &mut Transaction,
+    table: &str,
+    columns: &str,
+    conditions: &Conditions,
+    page: &PageRequest,
+    to_item: fn(&Row) -> Result<T, postgres::Error>,
+) -> Result<Page<T>, postgres::Error> {
+    let where_clause = conditions.where_clause();
+    let mut params: Vec<&(dyn ToSql + Sync)> = conditions.params.iter().map(|param| param.as_ref()).collect();
+
+    let total: i64 = tx.query_one(&format!("SELECT COUNT(*) FROM {}{}", table, where_clause), &params)?.get(0);
+
+    let direction = if page.descending { "DESC" } else { "ASC" };
+    let (limit, offset) = (page.limit as i64, page.offset as i64);
+    let sql = format!(
+        "SELECT {} FROM {}{} ORDER BY {} {}, id {} LIMIT ${} OFFSET ${}",
+        columns, table, where_clause, page.sort, direction, direction, params.len() + 1, params.len() + 2
+    );
+    params.push(&limit);
+    params.push(&offset);
+    let items: Vec<T> = tx.query(&sql, &params)?.iter().map(to_item).collect::<Result<_, _>>()?;
+
+    let total = total as u64;
+    let next_offset = page.offset + items.len() as u64;
+    let next_cursor = (next_offset < total).then(|| next_offset.to_string());
+
+    Ok(Page { items, total, next_cursor })
+}
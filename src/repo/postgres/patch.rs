@@ -0,0 +1,36 @@
+use postgres::types::ToSql;
+use crate::IdType;
+use super::Transaction;
+
+/// SET clause of an UPDATE, built from the fields a patch supplies
+#[derive(Default)]
+pub struct Assignments {
+    columns: Vec<&'static str>,
+    params: Vec<Box<dyn ToSql + Sync>>,
+}
+
+impl Assignments {
+    /// Set `column` to `value` if it was supplied, skip it otherwise
+    pub fn set(&mut self, column: &'static str, value: Option<impl ToSql + Sync + 'static>) {
+        if let Some(value) = value {
+            self.columns.push(column);
+            self.params.push(Box::new(value));
+        }
+    }
+
+    /// Run the UPDATE on the row with `id`, returns the number of updated rows
+    pub fn execute(self, tx: &mut Transaction, table: &str, id: IdType) -> Result<u64, postgres::Error> {
+        let set_clause = self.columns.iter()
+            .enumerate()
+            .map(|(index, column)| format!("{} = ${}", column, index + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let id = id as i64;
+        let mut params: Vec<&(dyn ToSql + Sync)> = self.params.iter().map(|param| param.as_ref()).collect();
+        params.push(&id);
+        tx.execute(
+            &format!("UPDATE {} SET {} WHERE id = ${}", table, set_clause, params.len()),
+            &params,
+        )
+    }
+}
@@ -0,0 +1,226 @@
+use postgres::{ types::ToSql, Row };
+use crate::{ AppError, IdType, now, TimeType };
+use crate::db::Entity;
+use crate::model::activity::{ Action, NewActivity };
+use crate::model::page::{ Page, PageRequest };
+use crate::model::skill::{ Skill, SkillFields, SkillFilter, SkillList, SkillPatch };
+use crate::progression::{ LevelCurve, XpType };
+use super::{ pg_error, Transaction };
+use super::activity::record;
+use super::character::touch as touch_character;
+use super::page::{ get_page, Conditions };
+use super::patch::Assignments;
+
+const COLUMNS: &str = "id, name, progress, level, xp, character_id, created_at, updated_at, deleted_at";
+
+pub fn get_skill_list(tx: &mut Transaction, character_id: IdType) -> Result<SkillList, AppError> {
+    let skills = tx.query(&format!("SELECT {} FROM skill WHERE character_id = $1 AND deleted_at IS NULL ORDER BY id", COLUMNS), &[&(character_id as i64)])
+        .map_err(pg_error("get_skill_list"))?
+        .iter().map(to_skill)
+        .collect();
+    Ok(SkillList(skills))
+}
+
+/// Skills of every character in `character_ids`, grouped by character
+pub fn get_characters_skill_list(tx: &mut Transaction, character_ids: &[IdType]) -> Result<SkillList, AppError> {
+    let ids: Vec<i64> = character_ids.iter().map(|id| *id as i64).collect();
+    let skills = tx.query(
+        &format!("SELECT {} FROM skill WHERE character_id = ANY($1) AND deleted_at IS NULL ORDER BY character_id, id", COLUMNS),
+        &[&ids],
+    ).map_err(pg_error("get_characters_skill_list"))?
+        .iter().map(to_skill)
+        .collect();
+    Ok(SkillList(skills))
+}
+
+pub fn get_skill_page(tx: &mut Transaction, owner: IdType, filter: &SkillFilter, page: &PageRequest) -> Result<Page<Skill>, AppError> {
+    let mut conditions = Conditions::default();
+    conditions.add("character_id IN (SELECT id FROM character WHERE owner_id = ?)", owner as i64);
+    if let Some(character_id) = filter.character_id {
+        conditions.add("character_id = ?", character_id as i64);
+    }
+    if let Some(updated_since) = filter.updated_since {
+        conditions.add("updated_at >= ?", updated_since as i64);
+    }
+    if !filter.include_trashed {
+        conditions.require("deleted_at IS NULL");
+    }
+    get_page(tx, "skill", COLUMNS, &conditions, page, |row| Ok(to_skill(row))).map_err(pg_error("get_skill_page"))
+}
+
+pub fn get_skill(tx: &mut Transaction, id: IdType) -> Result<Skill, AppError> {
+    let row = tx.query_opt(&format!("SELECT {} FROM skill WHERE id = $1", COLUMNS), &[&(id as i64)])
+        .map_err(pg_error("get_skill"))?
+        .ok_or(AppError::NotFound)?;
+    Ok(to_skill(&row))
+}
+
+/// Create a skill, the level and progress in `fields` start it off with the XP they take on `curve`
+pub fn create_skill(tx: &mut Transaction, actor: IdType, character_id: IdType, fields: SkillFields, curve: &LevelCurve) -> Result<IdType, AppError> {
+    let timestamp = now();
+    let xp = curve.xp_for(fields.level, fields.progress);
+    let (level, progress) = curve.level_for(xp);
+    let row = tx.query_one(
+        "INSERT INTO skill (name, progress, level, xp, character_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        &[&fields.name, &i16::from(progress), &i16::from(level), &(xp as i64), &(character_id as i64), &(timestamp as i64), &(timestamp as i64)],
+    ).map_err(pg_error("create_skill"))?;
+    let id = row.get::<_, i64>(0) as IdType;
+    record_change(tx, actor, id, Action::Create, None)?;
+
+    // update parent's updated_at attribute
+    touch_character(tx, character_id, timestamp)?;
+
+    Ok(id)
+}
+
+/// Update a skill. A level or progress set by hand replaces its XP with the XP they take on
+/// `curve`, so tasks completed later build on them.
+pub fn update_skill(tx: &mut Transaction, actor: IdType, id: IdType, patch: SkillPatch, curve: &LevelCurve) -> Result<(), AppError> {
+    let timestamp = now();
+    let before = get_skill(tx, id)?;
+    let mut assignments = Assignments::default();
+    assignments.set("name", patch.name);
+    let level = patch.level.unwrap_or(before.fields.level);
+    let progress = patch.progress.unwrap_or(before.fields.progress);
+    if (level, progress) != (before.fields.level, before.fields.progress) {
+        let xp = curve.xp_for(level, progress);
+        let (level, progress) = curve.level_for(xp);
+        assignments.set("xp", Some(xp as i64));
+        assignments.set("level", Some(i16::from(level)));
+        assignments.set("progress", Some(i16::from(progress)));
+    }
+    assignments.set("updated_at", Some(timestamp as i64));
+    let num_rows_updated = assignments.execute(tx, "skill", id).map_err(pg_error("update_skill"))?;
+    if num_rows_updated == 0 {
+        return Err(AppError::NotFound);
+    }
+    record_change(tx, actor, id, Action::Update, Some(&before))?;
+
+    // update parent's updated_at attribute
+    touch_character(tx, before.character_id, timestamp)
+}
+
+/// Move the skill to the trash, with its tasks that are not in there yet
+pub fn delete_skill(tx: &mut Transaction, actor: IdType, id: IdType) -> Result<(), AppError> {
+    let timestamp = now();
+    let before = get_skill(tx, id)?;
+
+    let params: [&(dyn ToSql + Sync); 2] = [&(timestamp as i64), &(id as i64)];
+    let num_rows_deleted = tx.execute("UPDATE skill SET deleted_at = $1 WHERE id = $2 AND deleted_at IS NULL", &params)
+        .map_err(pg_error("delete_skill"))?;
+    if num_rows_deleted == 0 {
+        return Err(AppError::NotFound);
+    }
+    tx.execute("UPDATE task SET deleted_at = $1 WHERE skill_id = $2 AND deleted_at IS NULL", &params)
+        .map_err(pg_error("delete_skill, tasks"))?;
+    record_change(tx, actor, id, Action::Delete, Some(&before))?;
+
+    touch_character(tx, before.character_id, timestamp)
+}
+
+/// Bring the skill back from the trash, with the tasks trashed along with it
+pub fn restore_skill(tx: &mut Transaction, actor: IdType, id: IdType) -> Result<(), AppError> {
+    let timestamp = now();
+    let before = get_skill(tx, id)?;
+    let deleted_at = before.deleted_at.ok_or(AppError::NotFound)?;
+    tx.execute(
+        "UPDATE task SET deleted_at = NULL WHERE skill_id = $1 AND deleted_at = $2",
+        &[&(id as i64), &(deleted_at as i64)],
+    ).map_err(pg_error("restore_skill, tasks"))?;
+    tx.execute("UPDATE skill SET deleted_at = NULL, updated_at = $1 WHERE id = $2", &[&(timestamp as i64), &(id as i64)])
+        .map_err(pg_error("restore_skill"))?;
+    record_change(tx, actor, id, Action::Restore, Some(&before))?;
+
+    touch_character(tx, before.character_id, timestamp)
+}
+
+/// Skills of `owner` trashed on their own, most recently trashed first
+pub fn get_trashed_skills(tx: &mut Transaction, owner: IdType) -> Result<Vec<Skill>, AppError> {
+    let skills = tx.query(
+        &format!(
+            "SELECT {} FROM skill WHERE deleted_at IS NOT NULL
+            AND character_id IN (SELECT id FROM character WHERE owner_id = $1 AND deleted_at IS NULL)
+            ORDER BY deleted_at DESC, id DESC",
+            COLUMNS,
+        ),
+        &[&(owner as i64)],
+    ).map_err(pg_error("get_trashed_skills"))?
+        .iter().map(to_skill)
+        .collect();
+    Ok(skills)
+}
+
+/// Permanently delete the skills trashed before `before`, their tasks cascade
+pub fn purge_skills(tx: &mut Transaction, before: TimeType) -> Result<u64, AppError> {
+    tx.execute("DELETE FROM skill WHERE deleted_at < $1", &[&(before as i64)])
+        .map_err(pg_error("purge_skills"))
+}
+
+pub fn touch(tx: &mut Transaction, id: IdType, timestamp: TimeType) -> Result<(), AppError> {
+    let num_rows_updated = tx.execute(
+        "UPDATE skill SET updated_at = $1 WHERE id = $2",
+        &[&(timestamp as i64), &(id as i64)],
+    ).map_err(pg_error("touch skill"))?;
+    if num_rows_updated == 0 {
+        return Err(AppError::NotFound);
+    }
+    Ok(())
+}
+
+/// Give the skill `delta` XP (take it away when negative) and recompute its level and
+/// progress from `curve`. Also updates `updated_at`, so callers don't need to `touch` it.
+/// The new XP is recorded as a change `actor` made to the skill.
+pub fn add_xp(tx: &mut Transaction, actor: IdType, id: IdType, delta: i64, curve: &LevelCurve, timestamp: TimeType) -> Result<(), AppError> {
+    let before = get_skill(tx, id)?;
+    let xp = before.xp.saturating_add_signed(delta);
+    let (level, progress) = curve.level_for(xp);
+
+    tx.execute(
+        "UPDATE skill SET xp = $1, level = $2, progress = $3, updated_at = $4 WHERE id = $5",
+        &[&(xp as i64), &i16::from(level), &i16::from(progress), &(timestamp as i64), &(id as i64)],
+    ).map_err(pg_error("add_xp"))?;
+    record_change(tx, actor, id, Action::Update, Some(&before))
+}
+
+/// Give the skills from before XP was tracked, those with a level or progress but no XP, the
+/// XP their level and progress take on `curve`, so completing a task builds on them. Returns
+/// how many skills were filled in.
+pub fn backfill_xp(tx: &mut Transaction, curve: &LevelCurve) -> Result<usize, postgres::Error> {
+    let rows = tx.query("SELECT id, level, progress FROM skill WHERE xp = 0 AND (level > 0 OR progress > 0)", &[])?;
+    for row in &rows {
+        let (id, level, progress): (i64, i16, i16) = (row.get(0), row.get(1), row.get(2));
+        let xp = curve.xp_for(level as u8, progress as u8);
+        tx.execute("UPDATE skill SET xp = $1 WHERE id = $2", &[&(xp as i64), &id])?;
+    }
+    Ok(rows.len())
+}
+
+/// Character the skill with `id` belongs to
+pub fn character_id(tx: &mut Transaction, id: IdType) -> Result<IdType, AppError> {
+    let row = tx.query_opt("SELECT character_id FROM skill WHERE id = $1", &[&(id as i64)])
+        .map_err(pg_error("skill character_id"))?
+        .ok_or(AppError::NotFound)?;
+    Ok(row.get::<_, i64>(0) as IdType)
+}
+
+/// Record what `actor` did to the skill, `before` is how it was before the change
+fn record_change(tx: &mut Transaction, actor: IdType, id: IdType, action: Action, before: Option<&Skill>) -> Result<(), AppError> {
+    let after = get_skill(tx, id)?;
+    record(tx, actor, NewActivity::new(Entity::Skill, id, after.character_id, action).diff(before, &after))
+}
+
+fn to_skill(row: &Row) -> Skill {
+    Skill {
+        id: row.get::<_, i64>(0) as IdType,
+        fields: SkillFields {
+            name: row.get(1),
+            progress: row.get::<_, i16>(2) as u8,
+            level: row.get::<_, i16>(3) as u8,
+        },
+        xp: row.get::<_, i64>(4) as XpType,
+        character_id: row.get::<_, i64>(5) as IdType,
+        created_at: row.get::<_, i64>(6) as TimeType,
+        updated_at: row.get::<_, i64>(7) as TimeType,
+        deleted_at: row.get::<_, Option<i64>>(8).map(|deleted_at| deleted_at as TimeType),
+    }
+}
@@ -0,0 +1,303 @@
+use postgres::Row;
+use crate::{ AppError, IdType, now, TimeType };
+use crate::db::Entity;
+use crate::model::activity::{ Action, NewActivity };
+use crate::model::page::{ Page, PageRequest };
+use crate::model::task::{ Task, TaskCompletion, TaskFields, TaskFilter, TaskHistory, TaskList, TaskPatch };
+use crate::progression::{ LevelCurve, XpType };
+use crate::recurrence::Recurrence;
+use super::{ pg_error, Transaction };
+use super::activity::record;
+use super::decode::Decoded;
+use super::character::touch as touch_character;
+use super::page::{ get_page, Conditions };
+use super::patch::Assignments;
+use super::skill::{ add_xp, character_id as skill_character_id, touch as touch_skill };
+
+const COLUMNS: &str = "id, name, description, completed, skill_id, created_at, updated_at, deleted_at, recurrence, reopens_at";
+
+pub fn get_skill_task_list(tx: &mut Transaction, skill_id: IdType) -> Result<TaskList, AppError> {
+    let tasks = tx.query(&format!("SELECT {} FROM task WHERE skill_id = $1 AND deleted_at IS NULL ORDER BY id", COLUMNS), &[&(skill_id as i64)])
+        .map_err(pg_error("get_skill_task_list"))?
+        .iter().map(to_task)
+        .collect::<Result<_, _>>()
+        .map_err(pg_error("get_skill_task_list"))?;
+    Ok(TaskList(tasks))
+}
+
+/// Tasks of every skill of the characters in `character_ids`, grouped by character and skill
+pub fn get_characters_task_list(tx: &mut Transaction, character_ids: &[IdType]) -> Result<TaskList, AppError> {
+    let ids: Vec<i64> = character_ids.iter().map(|id| *id as i64).collect();
+    let tasks = tx.query(
+        "SELECT task.id, task.name, task.description, task.completed, task.skill_id, task.created_at, task.updated_at, task.deleted_at,
+                task.recurrence, task.reopens_at
+            FROM task JOIN skill ON skill.id = task.skill_id
+            WHERE skill.character_id = ANY($1) AND task.deleted_at IS NULL ORDER BY skill.character_id, task.skill_id, task.id",
+        &[&ids],
+    ).map_err(pg_error("get_characters_task_list"))?
+        .iter().map(to_task)
+        .collect::<Result<_, _>>()
+        .map_err(pg_error("get_characters_task_list"))?;
+    Ok(TaskList(tasks))
+}
+
+pub fn get_task_page(tx: &mut Transaction, owner: IdType, filter: &TaskFilter, page: &PageRequest) -> Result<Page<Task>, AppError> {
+    let mut conditions = Conditions::default();
+    conditions.add(
+        "skill_id IN (SELECT skill.id FROM skill JOIN character ON character.id = skill.character_id WHERE character.owner_id = ?)",
+        owner as i64,
+    );
+    if let Some(completed) = filter.completed {
+        conditions.add("completed = ?", i16::from(completed));
+    }
+    if let Some(skill_id) = filter.skill_id {
+        conditions.add("skill_id = ?", skill_id as i64);
+    }
+    if let Some(character_id) = filter.character_id {
+        conditions.add("skill_id IN (SELECT id FROM skill WHERE character_id = ?)", character_id as i64);
+    }
+    if let Some(updated_since) = filter.updated_since {
+        conditions.add("updated_at >= ?", updated_since as i64);
+    }
+    if !filter.include_trashed {
+        conditions.require("deleted_at IS NULL");
+    }
+    get_page(tx, "task", COLUMNS, &conditions, page, to_task).map_err(pg_error("get_task_page"))
+}
+
+pub fn get_task(tx: &mut Transaction, id: IdType) -> Result<Task, AppError> {
+    let row = tx.query_opt(&format!("SELECT {} FROM task WHERE id = $1", COLUMNS), &[&(id as i64)])
+        .map_err(pg_error("get_task"))?
+        .ok_or(AppError::NotFound)?;
+    to_task(&row).map_err(pg_error("get_task"))
+}
+
+pub fn create_task(tx: &mut Transaction, actor: IdType, skill_id: IdType, fields: TaskFields, curve: &LevelCurve) -> Result<IdType, AppError> {
+    let timestamp = now();
+    // a task created as completed counts, so un-completing it later takes back what it gave
+    let xp_delta = curve.task_xp_delta(false, fields.completed == 1, 0);
+    let row = tx.query_one(
+        "INSERT INTO task (name, description, completed, recurrence, xp_awarded, skill_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        &[
+            &fields.name, &fields.description, &i16::from(fields.completed), &fields.recurrence.as_ref().map(to_json),
+            &xp_delta, &(skill_id as i64), &(timestamp as i64), &(timestamp as i64),
+        ],
+    ).map_err(pg_error("create_task"))?;
+    let id = row.get::<_, i64>(0) as IdType;
+    track_occurrence(tx, id, false, timestamp)?;
+    record_change(tx, actor, id, Action::Create, None)?;
+
+    // add_xp also updates the skill's updated_at
+    add_xp(tx, actor, skill_id, xp_delta, curve, timestamp)?;
+
+    // update parents' updated_at attributes
+    let character_id = skill_character_id(tx, skill_id)?;
+    touch_character(tx, character_id, timestamp)?;
+
+    Ok(id)
+}
+
+pub fn update_task(tx: &mut Transaction, actor: IdType, id: IdType, patch: TaskPatch, curve: &LevelCurve) -> Result<(), AppError> {
+    let timestamp = now();
+
+    // previous completed flag decides whether the skill gains or loses XP
+    let before = get_task(tx, id)?;
+    let skill_id = before.skill_id;
+    let was_completed = before.fields.completed == 1;
+
+    let is_completed = patch.completed.map_or(was_completed, |completed| completed == 1);
+    let awarded = tx.query_one("SELECT xp_awarded FROM task WHERE id = $1", &[&(id as i64)])
+        .map_err(pg_error("update_task, xp_awarded"))?
+        .get::<_, i64>(0) as XpType;
+    let xp_delta = curve.task_xp_delta(was_completed, is_completed, awarded);
+
+    let mut assignments = Assignments::default();
+    assignments.set("name", patch.name);
+    assignments.set("description", patch.description);
+    assignments.set("completed", patch.completed.map(i16::from));
+    assignments.set("recurrence", patch.recurrence.map(|recurrence| recurrence.as_ref().map(to_json)));
+    assignments.set("xp_awarded", (xp_delta != 0).then(|| awarded.saturating_add_signed(xp_delta) as i64));
+    assignments.set("updated_at", Some(timestamp as i64));
+    let num_rows_updated = assignments.execute(tx, "task", id).map_err(pg_error("update_task"))?;
+    if num_rows_updated == 0 {
+        return Err(AppError::NotFound);
+    }
+    track_occurrence(tx, id, was_completed, timestamp)?;
+    record_change(tx, actor, id, Action::Update, Some(&before))?;
+
+    // update parents' updated_at attributes, add_xp takes care of the skill's
+    add_xp(tx, actor, skill_id, xp_delta, curve, timestamp)?;
+
+    let character_id = skill_character_id(tx, skill_id)?;
+    touch_character(tx, character_id, timestamp)
+}
+
+/// Move the task to the trash
+pub fn delete_task(tx: &mut Transaction, actor: IdType, id: IdType) -> Result<(), AppError> {
+    let timestamp = now();
+
+    // get parents' ids before deleting for updating their updated_at attrs
+    let before = get_task(tx, id)?;
+    let skill_id = before.skill_id;
+    let character_id = skill_character_id(tx, skill_id)?;
+
+    let num_rows_deleted = tx.execute(
+        "UPDATE task SET deleted_at = $1 WHERE id = $2 AND deleted_at IS NULL",
+        &[&(timestamp as i64), &(id as i64)],
+    ).map_err(pg_error("delete_task"))?;
+    if num_rows_deleted == 0 {
+        return Err(AppError::NotFound);
+    }
+    record_change(tx, actor, id, Action::Delete, Some(&before))?;
+
+    touch_skill(tx, skill_id, timestamp)?;
+    touch_character(tx, character_id, timestamp)
+}
+
+/// Bring the task back from the trash
+pub fn restore_task(tx: &mut Transaction, actor: IdType, id: IdType) -> Result<(), AppError> {
+    let timestamp = now();
+    let before = get_task(tx, id)?;
+    if before.deleted_at.is_none() {
+        return Err(AppError::NotFound);
+    }
+    let skill_id = before.skill_id;
+    let character_id = skill_character_id(tx, skill_id)?;
+
+    tx.execute("UPDATE task SET deleted_at = NULL, updated_at = $1 WHERE id = $2", &[&(timestamp as i64), &(id as i64)])
+        .map_err(pg_error("restore_task"))?;
+    record_change(tx, actor, id, Action::Restore, Some(&before))?;
+
+    touch_skill(tx, skill_id, timestamp)?;
+    touch_character(tx, character_id, timestamp)
+}
+
+/// Tasks of `owner` trashed on their own, most recently trashed first
+pub fn get_trashed_tasks(tx: &mut Transaction, owner: IdType) -> Result<Vec<Task>, AppError> {
+    let tasks = tx.query(
+        &format!(
+            "SELECT {} FROM task WHERE deleted_at IS NOT NULL
+            AND skill_id IN (SELECT skill.id FROM skill JOIN character ON character.id = skill.character_id
+                WHERE character.owner_id = $1 AND skill.deleted_at IS NULL)
+            ORDER BY deleted_at DESC, id DESC",
+            COLUMNS,
+        ),
+        &[&(owner as i64)],
+    ).map_err(pg_error("get_trashed_tasks"))?
+        .iter().map(to_task)
+        .collect::<Result<_, _>>()
+        .map_err(pg_error("get_trashed_tasks"))?;
+    Ok(tasks)
+}
+
+/// Permanently delete the tasks trashed before `before`
+pub fn purge_tasks(tx: &mut Transaction, before: TimeType) -> Result<u64, AppError> {
+    tx.execute("DELETE FROM task WHERE deleted_at < $1", &[&(before as i64)])
+        .map_err(pg_error("purge_tasks"))
+}
+
+/// Completed occurrences of the task and its streaks as of `at`
+pub fn get_task_history(tx: &mut Transaction, id: IdType, at: TimeType) -> Result<TaskHistory, AppError> {
+    let task = get_task(tx, id)?;
+    let completions = tx.query(
+        "SELECT occurrence, completed_at FROM task_completion WHERE task_id = $1 ORDER BY occurrence DESC",
+        &[&(id as i64)],
+    ).map_err(pg_error("get_task_history"))?
+        .iter().map(to_completion)
+        .collect();
+    Ok(TaskHistory::new(&task, completions, at))
+}
+
+/// Mark the completed recurring tasks whose next occurrence has started by `at` not completed,
+/// returns how many. Their completions stay in the history and their XP with the skill. The
+/// schedule reopens them rather than a user, so it isn't in the activity log.
+pub fn reopen_tasks(tx: &mut Transaction, at: TimeType) -> Result<usize, AppError> {
+    let timestamp = now();
+    let due = tx.query(
+        "SELECT task.id, task.skill_id, skill.character_id FROM task JOIN skill ON skill.id = task.skill_id
+            WHERE task.reopens_at <= $1 AND task.deleted_at IS NULL",
+        &[&(at as i64)],
+    ).map_err(pg_error("reopen_tasks"))?;
+    for row in &due {
+        tx.execute(
+            "UPDATE task SET completed = 0, reopens_at = NULL, xp_awarded = 0, updated_at = $1 WHERE id = $2",
+            &[&(timestamp as i64), &row.get::<_, i64>(0)],
+        ).map_err(pg_error("reopen_tasks"))?;
+        touch_skill(tx, row.get::<_, i64>(1) as IdType, timestamp)?;
+        touch_character(tx, row.get::<_, i64>(2) as IdType, timestamp)?;
+    }
+    Ok(due.len())
+}
+
+/// Keep the completion history and `reopens_at` of the task in step with its completed flag
+/// and recurrence, after it was created or changed at `timestamp`. A task that stays completed
+/// reopens after the occurrence it was completed for, however late it is edited.
+fn track_occurrence(tx: &mut Transaction, id: IdType, was_completed: bool, timestamp: TimeType) -> Result<(), AppError> {
+    let task = get_task(tx, id)?;
+    let is_completed = task.fields.completed == 1;
+    if was_completed && !is_completed {
+        tx.execute(
+            "DELETE FROM task_completion WHERE task_id = $1 AND occurrence = (SELECT MAX(occurrence) FROM task_completion WHERE task_id = $1)",
+            &[&(id as i64)],
+        ).map_err(pg_error("track_occurrence"))?;
+    }
+
+    let schedule = task.fields.recurrence.as_ref().and_then(|recurrence| recurrence.schedule().ok());
+    let reopens_at = match schedule {
+        Some(schedule) if is_completed && !was_completed => {
+            let occurrence = schedule.completed_occurrence(task.created_at, timestamp);
+            tx.execute(
+                "INSERT INTO task_completion (task_id, occurrence, completed_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&(id as i64), &(occurrence as i64), &(timestamp as i64)],
+            ).map_err(pg_error("track_occurrence"))?;
+            Some(schedule.next(task.created_at, occurrence))
+        },
+        Some(schedule) if is_completed => {
+            // none recorded when it was completed before it got a recurrence
+            let completed: Option<i64> = tx.query_one("SELECT MAX(occurrence) FROM task_completion WHERE task_id = $1", &[&(id as i64)])
+                .map_err(pg_error("track_occurrence"))?
+                .get(0);
+            let occurrence = completed.map_or_else(|| schedule.completed_occurrence(task.created_at, timestamp), |occurrence| occurrence as TimeType);
+            Some(schedule.next(task.created_at, occurrence))
+        },
+        _ => None,
+    };
+    tx.execute("UPDATE task SET reopens_at = $1 WHERE id = $2", &[&reopens_at.map(|at| at as i64), &(id as i64)])
+        .map_err(pg_error("track_occurrence"))?;
+    Ok(())
+}
+
+/// Record what `actor` did to the task, `before` is how it was before the change
+fn record_change(tx: &mut Transaction, actor: IdType, id: IdType, action: Action, before: Option<&Task>) -> Result<(), AppError> {
+    let after = get_task(tx, id)?;
+    let character_id = skill_character_id(tx, after.skill_id)?;
+    record(tx, actor, NewActivity::new(Entity::Task, id, character_id, action).diff(before, &after))
+}
+
+fn to_json(recurrence: &Recurrence) -> String {
+    serde_json::to_string(recurrence).unwrap()
+}
+
+fn to_task(row: &Row) -> Result<Task, postgres::Error> {
+    Ok(Task {
+        id: row.get::<_, i64>(0) as IdType,
+        fields: TaskFields {
+            name: row.get(1),
+            description: row.get(2),
+            completed: row.get::<_, i16>(3) as u8,
+            recurrence: row.try_get::<_, Option<Decoded<Recurrence>>>(8)?.map(|recurrence| recurrence.0),
+        },
+        skill_id: row.get::<_, i64>(4) as IdType,
+        created_at: row.get::<_, i64>(5) as TimeType,
+        updated_at: row.get::<_, i64>(6) as TimeType,
+        deleted_at: row.get::<_, Option<i64>>(7).map(|deleted_at| deleted_at as TimeType),
+        reopens_at: row.get::<_, Option<i64>>(9).map(|reopens_at| reopens_at as TimeType),
+    })
+}
+
+fn to_completion(row: &Row) -> TaskCompletion {
+    TaskCompletion {
+        occurrence: row.get::<_, i64>(0) as TimeType,
+        completed_at: row.get::<_, i64>(1) as TimeType,
+    }
+}
@@ -0,0 +1,21 @@
+use crate::{ AppError, IdType, TimeType };
+use crate::model::trash::Trash;
+use super::Transaction;
+use super::character::{ get_trashed_characters, purge_characters };
+use super::skill::{ get_trashed_skills, purge_skills };
+use super::task::{ get_trashed_tasks, purge_tasks };
+
+pub fn get_trash(tx: &mut Transaction, owner: IdType) -> Result<Trash, AppError> {
+    Ok(Trash {
+        characters: get_trashed_characters(tx, owner)?,
+        skills: get_trashed_skills(tx, owner)?,
+        tasks: get_trashed_tasks(tx, owner)?,
+    })
+}
+
+/// Permanently delete everything trashed before `before`, returns the number of entries
+/// removed, not counting the children removed along with them
+pub fn purge_trash(tx: &mut Transaction, before: TimeType) -> Result<usize, AppError> {
+    let removed = purge_characters(tx, before)? + purge_skills(tx, before)? + purge_tasks(tx, before)?;
+    Ok(removed as usize)
+}
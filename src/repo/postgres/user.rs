@@ -0,0 +1,86 @@
+use postgres::Row;
+use crate::{ AppError, IdType, now, TimeType };
+use crate::db::auth_error;
+use crate::db::user::{ hash_password, hash_token, new_token, verify_password, AuthError };
+use crate::model::user::{ AuthUser, Credentials, Session, User };
+use super::{ pg_error, Transaction };
+
+pub fn get_user(tx: &mut Transaction, id: IdType) -> Result<User, AppError> {
+    let row = tx.query_opt(r#"SELECT id, username, created_at, updated_at FROM "user" WHERE id = $1"#, &[&(id as i64)])
+        .map_err(pg_error("get_user"))?
+        .ok_or(AppError::NotFound)?;
+    Ok(to_user(&row))
+}
+
+/// Store a new user with a salted argon2 hash of the password. The first user to register
+/// adopts the characters without owner, like SQLite's.
+pub fn create_user(tx: &mut Transaction, credentials: &Credentials) -> Result<IdType, AppError> {
+    let taken = tx.query_opt(r#"SELECT 1 FROM "user" WHERE username = $1"#, &[&credentials.username])
+        .map_err(pg_error("create_user"))?;
+    if taken.is_some() {
+        return Err(auth_error("create_user")(AuthError::UsernameTaken));
+    }
+
+    let password_hash = hash_password(&credentials.password).map_err(auth_error("create_user"))?;
+
+    let timestamp = now() as i64;
+    let row = tx.query_one(
+        r#"INSERT INTO "user" (username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4) RETURNING id"#,
+        &[&credentials.username, &password_hash, &timestamp, &timestamp],
+    ).map_err(pg_error("create_user"))?;
+    let id: i64 = row.get(0);
+
+    let users: i64 = tx.query_one(r#"SELECT COUNT(*) FROM "user""#, &[]).map_err(pg_error("create_user, count"))?.get(0);
+    if users == 1 {
+        tx.execute("UPDATE character SET owner_id = $1 WHERE owner_id IS NULL", &[&id])
+            .map_err(pg_error("create_user, adopt characters"))?;
+    }
+    Ok(id as IdType)
+}
+
+/// Check the credentials and open a session that lasts `ttl` milliseconds
+pub fn create_session(tx: &mut Transaction, credentials: &Credentials, ttl: TimeType) -> Result<Session, AppError> {
+    let row = tx.query_opt(r#"SELECT id, password_hash FROM "user" WHERE username = $1"#, &[&credentials.username])
+        .map_err(pg_error("create_session"))?
+        .ok_or(AppError::Unauthorized)?;
+    let user_id: i64 = row.get(0);
+    verify_password(&credentials.password, row.get(1)).map_err(auth_error("create_session"))?;
+
+    let token = new_token();
+    let timestamp = now();
+    let expires_at = timestamp + ttl;
+    tx.execute(
+        "INSERT INTO session (token_hash, user_id, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+        &[&hash_token(&token), &user_id, &(timestamp as i64), &(expires_at as i64)],
+    ).map_err(pg_error("create_session"))?;
+
+    let user = get_user(tx, user_id as IdType)?;
+    Ok(Session { token, user, expires_at })
+}
+
+/// User of an unexpired session, `None` for unknown or expired tokens
+pub fn get_session_user(tx: &mut Transaction, token: &str) -> Result<Option<AuthUser>, AppError> {
+    let row = tx.query_opt(
+        r#"SELECT "user".id FROM session JOIN "user" ON "user".id = session.user_id
+            WHERE session.token_hash = $1 AND session.expires_at > $2"#,
+        &[&hash_token(token), &(now() as i64)],
+    ).map_err(pg_error("get_session_user"))?;
+    Ok(row.map(|row| AuthUser { id: row.get::<_, i64>(0) as IdType }))
+}
+
+pub fn delete_session(tx: &mut Transaction, token: &str) -> Result<(), AppError> {
+    tx.execute(
+        "DELETE FROM session WHERE token_hash = $1 OR expires_at <= $2",
+        &[&hash_token(token), &(now() as i64)],
+    ).map_err(pg_error("delete_session"))?;
+    Ok(())
+}
+
+fn to_user(row: &Row) -> User {
+    User {
+        id: row.get::<_, i64>(0) as IdType,
+        username: row.get(1),
+        created_at: row.get::<_, i64>(2) as TimeType,
+        updated_at: row.get::<_, i64>(3) as TimeType,
+    }
+}
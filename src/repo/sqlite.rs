@@ -0,0 +1,467 @@
+use actix_web::http::header::IfMatch;
+use async_trait::async_trait;
+use crate::{ AppError, IdType, now, TimeType };
+use crate::config::DatabaseConfig;
+use crate::db::{ auth_error, check_parent_restored, clear_db, db_error, migration, transaction, Entity, Pool, Scope };
+use crate::db::activity::get_activity_page;
+use crate::db::character::{
+    create_character, delete_character, get_character, get_character_list, get_character_trees, restore_character, update_character,
+};
+use crate::db::skill::{ backfill_xp, create_skill, delete_skill, get_skill, get_skill_list, get_skill_page, restore_skill, update_skill };
+use crate::db::task::{
+    create_task, delete_task, get_characters_task_list, get_task, get_task_history, get_task_list, get_task_page, reopen_tasks,
+    restore_task, update_task,
+};
+use crate::db::trash::{ get_trash, purge_trash };
+use crate::db::user::{ create_session, create_user, delete_session, get_session_user, get_user };
+use crate::model::activity::{ Activity, ActivityFilter };
+use crate::model::character::{ Character, CharacterFields, CharacterFilter, CharacterPatch, CharacterTree, Include };
+use crate::model::page::{ Page, PageRequest };
+use crate::model::skill::{ Skill, SkillFields, SkillFilter, SkillList, SkillPatch };
+use crate::model::task::{ Task, TaskFields, TaskFilter, TaskHistory, TaskList, TaskPatch };
+use crate::model::trash::Trash;
+use crate::model::user::{ AuthUser, Credentials, Session, User };
+use crate::progression::LevelCurve;
+use super::{ ActivityRepo, CharacterRepo, OpenError, SkillRepo, TaskRepo, TrashRepo, UserRepo };
+
+/// Repositories on the SQLite database behind `pool`, every call runs in its own transaction
+pub struct SqliteRepo {
+    pool: Pool,
+}
+
+impl SqliteRepo {
+    pub fn new(pool: Pool) -> Self {
+        SqliteRepo { pool }
+    }
+
+    /// Open the file at `config.path`, creating it when missing, and migrate it. Refuses a
+    /// database from a newer build. Skills from before XP was tracked get theirs from `curve`.
+    pub fn open(config: &DatabaseConfig, curve: &LevelCurve) -> Result<Self, OpenError> {
+        let connect_error = |error: r2d2::Error| OpenError::Connect {
+            target: config.path.display().to_string(),
+            error: error.to_string(),
+        };
+        let pool = Pool::open(config).map_err(connect_error)?;
+        let mut conn = pool.get().map_err(connect_error)?;
+        migration::migrate(&mut conn).map_err(|e| OpenError::Migrate(e.to_string()))?;
+        let filled = backfill_xp(&conn, curve).map_err(|e| OpenError::Migrate(e.to_string()))?;
+        if filled > 0 {
+            log::info!("filled in the XP of {} skills from before XP was tracked", filled);
+        }
+        drop(conn);
+        Ok(SqliteRepo::new(pool))
+    }
+}
+
+#[async_trait(?Send)]
+impl CharacterRepo for SqliteRepo {
+    async fn list_characters(&self, user: &AuthUser, filter: CharacterFilter, page: PageRequest, include: Include) -> Result<Page<CharacterTree>, AppError> {
+        let owner = user.id;
+        transaction(&self.pool, Scope::read(), move |conn| {
+            let page = get_character_list(conn, owner, &filter, &page).map_err(db_error("get_character_list"))?;
+            let items = get_character_trees(conn, page.items, include).map_err(db_error("get_character_list, get_character_trees"))?;
+            Ok(Page { items, total: page.total, next_cursor: page.next_cursor })
+        }).await
+    }
+
+    async fn get_character(&self, user: &AuthUser, id: IdType, include: Include) -> Result<CharacterTree, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Character, id), move |conn| {
+            let character = get_character(conn, id).map_err(db_error("get_character"))?;
+            let mut trees = get_character_trees(conn, vec![character], include).map_err(db_error("get_character, get_character_trees"))?;
+            Ok(trees.remove(0))
+        }).await
+    }
+
+    async fn create_character(&self, user: &AuthUser, fields: CharacterFields) -> Result<Character, AppError> {
+        let owner = user.id;
+        transaction(&self.pool, Scope::write(), move |conn| {
+            let id = create_character(conn, owner, fields.clone()).map_err(db_error("create_character"))?;
+            get_character(conn, id).map_err(db_error("create_character, get_character"))
+        }).await
+    }
+
+    async fn update_character(&self, user: &AuthUser, id: IdType, patch: CharacterPatch, if_match: Option<IfMatch>) -> Result<Character, AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Character, id).if_match(if_match);
+        transaction(&self.pool, scope, move |conn| {
+            update_character(conn, actor, id, patch.clone()).map_err(db_error("update_character"))?;
+            get_character(conn, id).map_err(db_error("update_character, get_character"))
+        }).await
+    }
+
+    async fn delete_character(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Character, id).if_match(if_match);
+        transaction(&self.pool, scope, move |conn| {
+            delete_character(conn, actor, id).map_err(db_error("delete_character"))
+        }).await
+    }
+
+    async fn restore_character(&self, user: &AuthUser, id: IdType) -> Result<Character, AppError> {
+        let actor = user.id;
+        transaction(&self.pool, Scope::write().trashed_owned_by(actor, Entity::Character, id), move |conn| {
+            restore_character(conn, actor, id).map_err(db_error("restore_character"))?;
+            get_character(conn, id).map_err(db_error("restore_character, get_character"))
+        }).await
+    }
+
+    async fn delete_all_characters(&self, user: &AuthUser) -> Result<(), AppError> {
+        let owner = user.id;
+        transaction(&self.pool, Scope::write(), move |conn| {
+            clear_db(conn, owner).map_err(db_error("reset_db:clear_db"))
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl SkillRepo for SqliteRepo {
+    async fn list_skills(&self, user: &AuthUser, filter: SkillFilter, page: PageRequest) -> Result<Page<Skill>, AppError> {
+        let owner = user.id;
+        transaction(&self.pool, Scope::read(), move |conn| {
+            get_skill_page(conn, owner, &filter, &page).map_err(db_error("get_skill_page"))
+        }).await
+    }
+
+    async fn character_skills(&self, user: &AuthUser, character_id: IdType) -> Result<SkillList, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Character, character_id), move |conn| {
+            get_skill_list(conn, Some(character_id)).map_err(db_error("get_character_skill_list, get_skill_list"))
+        }).await
+    }
+
+    async fn get_skill(&self, user: &AuthUser, id: IdType) -> Result<Skill, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Skill, id), move |conn| {
+            get_skill(conn, id).map_err(db_error("get_skill"))
+        }).await
+    }
+
+    async fn create_skill(&self, user: &AuthUser, character_id: IdType, fields: SkillFields, curve: LevelCurve) -> Result<Skill, AppError> {
+        let actor = user.id;
+        transaction(&self.pool, Scope::write().owned_by(actor, Entity::Character, character_id), move |conn| {
+            let id = create_skill(conn, actor, character_id, fields.clone(), &curve).map_err(db_error("create_skill"))?;
+            get_skill(conn, id).map_err(db_error("create_skill, get_skill"))
+        }).await
+    }
+
+    async fn update_skill(&self, user: &AuthUser, id: IdType, patch: SkillPatch, curve: LevelCurve, if_match: Option<IfMatch>) -> Result<Skill, AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Skill, id).if_match(if_match);
+        transaction(&self.pool, scope, move |conn| {
+            update_skill(conn, actor, id, patch.clone(), &curve).map_err(db_error("update_skill"))?;
+            get_skill(conn, id).map_err(db_error("update_skill, get_skill"))
+        }).await
+    }
+
+    async fn delete_skill(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Skill, id).if_match(if_match);
+        transaction(&self.pool, scope, move |conn| {
+            delete_skill(conn, actor, id).map_err(db_error("delete_skill"))
+        }).await
+    }
+
+    async fn restore_skill(&self, user: &AuthUser, id: IdType) -> Result<Skill, AppError> {
+        let actor = user.id;
+        transaction(&self.pool, Scope::write().trashed_owned_by(actor, Entity::Skill, id), move |conn| {
+            check_parent_restored(conn, Entity::Skill, id)?;
+            restore_skill(conn, actor, id).map_err(db_error("restore_skill"))?;
+            get_skill(conn, id).map_err(db_error("restore_skill, get_skill"))
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl TaskRepo for SqliteRepo {
+    async fn list_tasks(&self, user: &AuthUser, filter: TaskFilter, page: PageRequest) -> Result<Page<Task>, AppError> {
+        let owner = user.id;
+        transaction(&self.pool, Scope::read(), move |conn| {
+            get_task_page(conn, owner, &filter, &page).map_err(db_error("get_task_page"))
+        }).await
+    }
+
+    async fn skill_tasks(&self, user: &AuthUser, skill_id: IdType) -> Result<TaskList, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Skill, skill_id), move |conn| {
+            get_task_list(conn, Some(skill_id)).map_err(db_error("get_skill_task_list, get_task_list"))
+        }).await
+    }
+
+    async fn character_tasks(&self, user: &AuthUser, character_id: IdType) -> Result<TaskList, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Character, character_id), move |conn| {
+            get_characters_task_list(conn, &[character_id]).map_err(db_error("get_character_task_list"))
+        }).await
+    }
+
+    async fn get_task(&self, user: &AuthUser, id: IdType) -> Result<Task, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Task, id), move |conn| {
+            get_task(conn, id).map_err(db_error("get_task"))
+        }).await
+    }
+
+    async fn create_task(&self, user: &AuthUser, skill_id: IdType, fields: TaskFields, curve: LevelCurve) -> Result<Task, AppError> {
+        let actor = user.id;
+        transaction(&self.pool, Scope::write().owned_by(actor, Entity::Skill, skill_id), move |conn| {
+            let id = create_task(conn, actor, skill_id, fields.clone(), &curve).map_err(db_error("create_task"))?;
+            get_task(conn, id).map_err(db_error("create_task, get_task"))
+        }).await
+    }
+
+    async fn update_task(&self, user: &AuthUser, id: IdType, patch: TaskPatch, curve: LevelCurve, if_match: Option<IfMatch>) -> Result<Task, AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Task, id).if_match(if_match);
+        transaction(&self.pool, scope, move |conn| {
+            update_task(conn, actor, id, patch.clone(), &curve).map_err(db_error("update_task"))?;
+            get_task(conn, id).map_err(db_error("update_task, get_task"))
+        }).await
+    }
+
+    async fn delete_task(&self, user: &AuthUser, id: IdType, if_match: Option<IfMatch>) -> Result<(), AppError> {
+        let actor = user.id;
+        let scope = Scope::write().owned_by(actor, Entity::Task, id).if_match(if_match);
+        transaction(&self.pool, scope, move |conn| {
+            delete_task(conn, actor, id).map_err(db_error("delete_task"))
+        }).await
+    }
+
+    async fn restore_task(&self, user: &AuthUser, id: IdType) -> Result<Task, AppError> {
+        let actor = user.id;
+        transaction(&self.pool, Scope::write().trashed_owned_by(actor, Entity::Task, id), move |conn| {
+            check_parent_restored(conn, Entity::Task, id)?;
+            restore_task(conn, actor, id).map_err(db_error("restore_task"))?;
+            get_task(conn, id).map_err(db_error("restore_task, get_task"))
+        }).await
+    }
+
+    async fn task_history(&self, user: &AuthUser, id: IdType) -> Result<TaskHistory, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Task, id), move |conn| {
+            get_task_history(conn, id, now()).map_err(db_error("get_task_history"))
+        }).await
+    }
+
+    async fn reopen_tasks(&self, at: TimeType) -> Result<usize, AppError> {
+        transaction(&self.pool, Scope::write(), move |conn| {
+            reopen_tasks(conn, at).map_err(db_error("reopen_tasks"))
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl ActivityRepo for SqliteRepo {
+    async fn character_activity(&self, user: &AuthUser, character_id: IdType, filter: ActivityFilter, page: PageRequest) -> Result<Page<Activity>, AppError> {
+        transaction(&self.pool, Scope::read().owned_by(user.id, Entity::Character, character_id), move |conn| {
+            get_activity_page(conn, character_id, &filter, &page).map_err(db_error("get_activity_page"))
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl TrashRepo for SqliteRepo {
+    async fn list_trash(&self, user: &AuthUser) -> Result<Trash, AppError> {
+        let owner = user.id;
+        transaction(&self.pool, Scope::read(), move |conn| {
+            get_trash(conn, owner).map_err(db_error("get_trash"))
+        }).await
+    }
+
+    async fn purge_trash(&self, before: TimeType) -> Result<usize, AppError> {
+        transaction(&self.pool, Scope::write(), move |conn| {
+            purge_trash(conn, before).map_err(db_error("purge_trash"))
+        }).await
+    }
+}
+
+#[async_trait(?Send)]
+impl UserRepo for SqliteRepo {
+    async fn register(&self, credentials: Credentials) -> Result<User, AppError> {
+        transaction(&self.pool, Scope::write(), move |conn| {
+            let id = create_user(conn, &credentials).map_err(auth_error("create_user"))?;
+            get_user(conn, id).map_err(db_error("create_user, get_user"))
+        }).await
+    }
+
+    async fn login(&self, credentials: Credentials, ttl: TimeType) -> Result<Session, AppError> {
+        transaction(&self.pool, Scope::write(), move |conn| {
+            create_session(conn, &credentials, ttl).map_err(auth_error("create_session"))
+        }).await
+    }
+
+    async fn authenticate(&self, token: String) -> Result<Option<AuthUser>, AppError> {
+        transaction(&self.pool, Scope::read(), move |conn| {
+            get_session_user(conn, &token).map_err(db_error("get_session_user"))
+        }).await
+    }
+
+    async fn logout(&self, token: String) -> Result<(), AppError> {
+        transaction(&self.pool, Scope::write(), move |conn| {
+            delete_session(conn, &token).map_err(db_error("delete_session"))
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ test_pool, test_user, Connection };
+    use crate::etag::entity_tag;
+
+    /// Repo with a user owning a character with one skill, returns the user and skill id
+    async fn setup() -> (SqliteRepo, AuthUser, IdType) {
+        let pool = test_pool();
+        let user = AuthUser { id: test_user(&pool.get().unwrap(), "hero") };
+        let repo = SqliteRepo::new(pool);
+        let fields = CharacterFields { name: "hero".to_string(), avatar: String::new(), notes: String::new(), quote: String::new() };
+        let character = repo.create_character(&user, fields).await.unwrap();
+        let fields = SkillFields { name: "guitar".to_string(), progress: 0, level: 0 };
+        let skill = repo.create_skill(&user, character.id, fields, LevelCurve::default()).await.unwrap();
+        (repo, user, skill.id)
+    }
+
+    fn task_fields(completed: u8) -> TaskFields {
+        TaskFields { name: "scales".to_string(), description: String::new(), completed, recurrence: None }
+    }
+
+    /// Make every later write to `table` fail, after the earlier statements of a call ran
+    fn inject_failure(repo: &SqliteRepo, table: &str, operation: &str) {
+        repo.pool.get().unwrap().execute_batch(&format!(
+            "CREATE TRIGGER fail_{table} BEFORE {operation} ON {table} BEGIN SELECT RAISE(ABORT, 'injected failure'); END;"
+        )).unwrap();
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0)).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn committed_on_success() {
+        let (repo, user, skill_id) = setup().await;
+        repo.create_task(&user, skill_id, task_fields(1), LevelCurve::default()).await.unwrap();
+        assert_eq!(count(&repo.pool.get().unwrap(), "task"), 1);
+        assert_eq!(repo.get_skill(&user, skill_id).await.unwrap().xp, LevelCurve::default().task_xp);
+    }
+
+    #[actix_web::test]
+    async fn failed_create_task_rolls_back() {
+        let (repo, user, skill_id) = setup().await;
+        let skill_before = repo.get_skill(&user, skill_id).await.unwrap();
+        inject_failure(&repo, "character", "UPDATE");
+
+        let result = repo.create_task(&user, skill_id, task_fields(1), LevelCurve::default()).await;
+
+        assert!(matches!(result, Err(AppError::DBError)));
+        assert_eq!(count(&repo.pool.get().unwrap(), "task"), 0);
+        let skill_after = repo.get_skill(&user, skill_id).await.unwrap();
+        assert_eq!(skill_after.xp, skill_before.xp);
+        assert_eq!(skill_after.updated_at, skill_before.updated_at);
+    }
+
+    #[actix_web::test]
+    async fn failed_update_task_rolls_back() {
+        let (repo, user, skill_id) = setup().await;
+        let task = repo.create_task(&user, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+        inject_failure(&repo, "character", "UPDATE");
+
+        let result = repo.update_task(&user, task.id, task_fields(1).into(), LevelCurve::default(), None).await;
+
+        assert!(result.is_err());
+        assert_eq!(repo.get_task(&user, task.id).await.unwrap().fields.completed, 0);
+        assert_eq!(repo.get_skill(&user, skill_id).await.unwrap().xp, 0);
+    }
+
+    #[actix_web::test]
+    async fn failed_delete_task_rolls_back() {
+        let (repo, user, skill_id) = setup().await;
+        let task = repo.create_task(&user, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+        inject_failure(&repo, "skill", "UPDATE");
+
+        assert!(repo.delete_task(&user, task.id, None).await.is_err());
+        assert!(repo.get_task(&user, task.id).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn failed_reset_rolls_back() {
+        let (repo, user, skill_id) = setup().await;
+        repo.create_task(&user, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+        inject_failure(&repo, "character", "DELETE");
+
+        assert!(repo.delete_all_characters(&user).await.is_err());
+        let conn = repo.pool.get().unwrap();
+        assert_eq!(count(&conn, "task"), 1);
+        assert_eq!(count(&conn, "skill"), 1);
+    }
+
+    #[actix_web::test]
+    async fn missing_entities_are_not_found() {
+        let pool = test_pool();
+        let user = AuthUser { id: test_user(&pool.get().unwrap(), "hero") };
+        let repo = SqliteRepo::new(pool);
+        let missing = 42;
+        let character_fields = CharacterFields { name: "hero".to_string(), avatar: String::new(), notes: String::new(), quote: String::new() };
+        let skill_fields = SkillFields { name: "guitar".to_string(), progress: 0, level: 0 };
+        let curve = LevelCurve::default;
+
+        assert!(matches!(repo.get_character(&user, missing, Include::default()).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.character_skills(&user, missing).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.character_tasks(&user, missing).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.update_character(&user, missing, character_fields.into(), None).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.delete_character(&user, missing, None).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.create_skill(&user, missing, skill_fields, curve()).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.get_skill(&user, missing).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.skill_tasks(&user, missing).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.update_skill(&user, missing, SkillPatch::default(), curve(), None).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.delete_skill(&user, missing, None).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.create_task(&user, missing, task_fields(0), curve()).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.get_task(&user, missing).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.update_task(&user, missing, TaskPatch::default(), curve(), None).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.delete_task(&user, missing, None).await, Err(AppError::NotFound)));
+    }
+
+    #[actix_web::test]
+    async fn writes_honour_if_match() {
+        let (repo, user, skill_id) = setup().await;
+        let task = repo.create_task(&user, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+
+        let stale = || Some(IfMatch::Items(vec![entity_tag(task.id, task.updated_at - 1)]));
+        let patch = || TaskPatch { completed: Some(1), ..Default::default() };
+        let result = repo.update_task(&user, task.id, patch(), LevelCurve::default(), stale()).await;
+        assert!(matches!(result, Err(AppError::PreconditionFailed)));
+        assert!(matches!(repo.delete_task(&user, task.id, stale()).await, Err(AppError::PreconditionFailed)));
+        assert_eq!(repo.get_task(&user, task.id).await.unwrap().fields.completed, 0);
+
+        let current = Some(IfMatch::Items(vec![entity_tag(task.id, task.updated_at)]));
+        assert!(repo.update_task(&user, task.id, patch(), LevelCurve::default(), current).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn other_users_entities_are_hidden() {
+        let (repo, owner, skill_id) = setup().await;
+        let task = repo.create_task(&owner, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+        let other = AuthUser { id: test_user(&repo.pool.get().unwrap(), "villain") };
+
+        assert!(matches!(repo.get_skill(&other, skill_id).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.update_skill(&other, skill_id, SkillPatch::default(), LevelCurve::default(), None).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.create_task(&other, skill_id, task_fields(0), LevelCurve::default()).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.get_task(&other, task.id).await, Err(AppError::NotFound)));
+        assert!(matches!(repo.delete_task(&other, task.id, None).await, Err(AppError::NotFound)));
+
+        let page = || PageRequest { limit: 10, offset: 0, sort: "created_at", descending: false };
+        assert_eq!(repo.list_tasks(&other, TaskFilter::default(), page()).await.unwrap().total, 0);
+        assert!(repo.delete_all_characters(&other).await.is_ok());
+        assert_eq!(count(&repo.pool.get().unwrap(), "task"), 1);
+        assert_eq!(repo.list_tasks(&owner, TaskFilter::default(), page()).await.unwrap().total, 1);
+    }
+
+    #[actix_web::test]
+    async fn purge_removes_expired_trash_only() {
+        let (repo, user, skill_id) = setup().await;
+        let kept = repo.create_task(&user, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+        let trashed = repo.create_task(&user, skill_id, task_fields(0), LevelCurve::default()).await.unwrap();
+        repo.delete_task(&user, trashed.id, None).await.unwrap();
+        let deleted_at = repo.list_trash(&user).await.unwrap().tasks[0].deleted_at.unwrap();
+
+        assert_eq!(repo.purge_trash(deleted_at).await.unwrap(), 0);
+        assert_eq!(count(&repo.pool.get().unwrap(), "task"), 2);
+
+        assert_eq!(repo.purge_trash(deleted_at + 1).await.unwrap(), 1);
+        assert!(repo.list_trash(&user).await.unwrap().tasks.is_empty());
+        assert!(matches!(repo.restore_task(&user, trashed.id).await, Err(AppError::NotFound)));
+        assert!(repo.get_task(&user, kept.id).await.is_ok());
+    }
+}
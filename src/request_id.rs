@@ -0,0 +1,197 @@
+use std::future::{ ready, Ready };
+use actix_web::{
+    body::EitherBody,
+    dev::{ Service, ServiceRequest, ServiceResponse, Transform },
+    http::{ header::{ self, HeaderName, HeaderValue }, StatusCode },
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use derive_more::derive::Display;
+use futures::future::LocalBoxFuture;
+use rand::Rng;
+use crate::problem;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest `X-Request-Id` taken from a client
+const MAX_LEN: usize = 64;
+
+/// Id of a request, set on the request extensions by `RequestIds`
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The client's `X-Request-Id` when it is a sane one, a random one otherwise
+    fn of(req: &ServiceRequest) -> Self {
+        let given = req.headers().get(&X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| (1..=MAX_LEN).contains(&id.len()))
+            .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)));
+        match given {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId(format!("{:016x}", rand::thread_rng().gen::<u64>())),
+        }
+    }
+}
+
+/// Middleware that gives every request an id, echoed in `X-Request-Id` and in the body of
+/// error responses, which are all rendered as `application/problem+json`. Wrap it around
+/// everything else, so it also sees the errors of the other middleware.
+pub struct RequestIds;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIds
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequestIdsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdsMiddleware { service }))
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let id = RequestId::of(&req);
+        req.extensions_mut().insert(id.clone());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = match fut.await {
+                Ok(res) => match res.response().error() {
+                    Some(error) => {
+                        let mut problem = problem::error_response(error, Some(&id.0));
+                        // keep what other middleware added, like the CORS headers
+                        for (name, value) in res.headers() {
+                            if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH && !problem.headers().contains_key(name) {
+                                problem.headers_mut().append(name.clone(), value.clone());
+                            }
+                        }
+                        res.into_response(problem).map_into_right_body()
+                    },
+                    None => res.map_into_left_body(),
+                },
+                Err(error) => return Err(Identified { error, id }.into()),
+            };
+            if let Ok(value) = HeaderValue::from_str(&id.0) {
+                res.headers_mut().insert(X_REQUEST_ID, value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Error of an inner middleware that gave up on the request, rendered with the request id
+#[derive(Debug, Display)]
+#[display("{error}")]
+struct Identified {
+    error: Error,
+    id: RequestId,
+}
+
+impl ResponseError for Identified {
+    fn status_code(&self) -> StatusCode {
+        self.error.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = problem::error_response(&self.error, Some(&self.id.0));
+        if let Ok(value) = HeaderValue::from_str(&self.id.0) {
+            res.headers_mut().insert(X_REQUEST_ID, value);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{ body::to_bytes, test::{ call_service, init_service, read_body_json, try_call_service, TestRequest }, web, App, HttpRequest };
+    use serde_json::Value;
+    use crate::{ config::FaultConfig, fault::FaultInjection, AppError };
+
+    #[actix_web::test]
+    async fn echoes_sane_ids_and_makes_up_others() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestIds)
+                .route("/", web::get().to(|req: HttpRequest| async move {
+                    req.extensions().get::<RequestId>().unwrap().0.clone()
+                }))
+        ).await;
+
+        let res = call_service(&app, TestRequest::get().insert_header(("X-Request-Id", "abc-123")).to_request()).await;
+        assert_eq!(res.headers().get(&X_REQUEST_ID).unwrap(), "abc-123");
+
+        let req = TestRequest::get().insert_header(("X-Request-Id", "<script>")).to_request();
+        let res = call_service(&app, req).await;
+        let id = res.headers().get(&X_REQUEST_ID).unwrap().to_str().unwrap().to_string();
+        assert_eq!(id.len(), 16);
+        assert_eq!(actix_web::test::read_body(res).await, id);
+    }
+
+    #[actix_web::test]
+    async fn renders_errors_as_problems() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestIds)
+                .route("/db", web::get().to(|| async { Err::<String, _>(AppError::DBError) }))
+                .route("/busy", web::get().to(|| async { Err::<String, _>(AppError::DatabaseBusy) }))
+                .route("/{id}", web::get().to(|id: web::Path<u64>| async move { id.to_string() }))
+        ).await;
+
+        let req = TestRequest::get().uri("/db").insert_header(("X-Request-Id", "r1")).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), problem::CONTENT_TYPE);
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body["type"], "database_error");
+        assert_eq!(body["status"], 500);
+        assert_eq!(body["request_id"], "r1");
+
+        let res = call_service(&app, TestRequest::get().uri("/busy").to_request()).await;
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "1");
+
+        // errors of actix itself look the same
+        let res = call_service(&app, TestRequest::get().uri("/nope").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body["type"], "not_found");
+    }
+
+    #[actix_web::test]
+    async fn tags_errors_of_inner_middleware() {
+        let fault = FaultConfig { delay_ms: 0, jitter_ms: 0, error_rate: 1.0, error_status: 503 };
+        let app = init_service(
+            App::new()
+                .wrap(FaultInjection::new(fault))
+                .wrap(RequestIds)
+                .route("/", web::get().to(|| async { "never" }))
+        ).await;
+
+        let req = TestRequest::get().insert_header(("X-Request-Id", "r2")).to_request();
+        let res = try_call_service(&app, req).await.err().unwrap().error_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers().get(&X_REQUEST_ID).unwrap(), "r2");
+        let body: Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!((&body["type"], &body["request_id"]), (&Value::from("injected_fault"), &Value::from("r2")));
+    }
+}
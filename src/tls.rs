@@ -0,0 +1,310 @@
+use std::{ fmt, future::{ ready, Ready }, path::{ Path, PathBuf }, sync::{ Arc, RwLock } };
+use actix_web::{
+    body::EitherBody,
+    dev::{ Service, ServiceRequest, ServiceResponse, Transform },
+    http::header::LOCATION,
+    Error, HttpResponse,
+};
+use derive_more::derive::Display;
+use futures::future::LocalBoxFuture;
+use rustls::{
+    crypto::{ ring, CryptoProvider },
+    pki_types::{ pem::{ self, PemObject }, CertificateDer, PrivateKeyDer },
+    server::{ ClientHello, ResolvesServerCert },
+    sign::CertifiedKey,
+    ServerConfig,
+};
+
+#[derive(Debug, Display)]
+pub enum TlsError {
+    #[display("cannot read {}: {error}", path.display())]
+    Read { path: PathBuf, error: pem::Error },
+    #[display("no certificate found in {}", path.display())]
+    NoCertificate { path: PathBuf },
+    #[display("unusable private key in {}: {error}", path.display())]
+    Key { path: PathBuf, error: rustls::Error },
+}
+
+/// Certificate and key read from PEM files, handed to every new TLS connection.
+/// `reload` swaps them for the current file contents without dropping open connections.
+pub struct CertReloader {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertReloader {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+        let current = load_certified_key(&provider, cert_path, key_path)?;
+        Ok(CertReloader {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            provider,
+            current: RwLock::new(Arc::new(current)),
+        })
+    }
+
+    /// Read the files again, a broken certificate or key keeps the previous one in use
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let certified_key = load_certified_key(&self.provider, &self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone())
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+impl fmt::Debug for CertReloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertReloader")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+fn load_certified_key(provider: &CryptoProvider, cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, TlsError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| TlsError::Read { path: cert_path.to_path_buf(), error })?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate { path: cert_path.to_path_buf() });
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|error| TlsError::Read { path: key_path.to_path_buf(), error })?;
+    let key = provider.key_provider.load_private_key(key)
+        .map_err(|error| TlsError::Key { path: key_path.to_path_buf(), error })?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Reload the certificate whenever the process gets SIGHUP, like after a renewal.
+/// Has to be called from within the actix runtime.
+#[cfg(unix)]
+pub fn reload_on_sighup(reloader: Arc<CertReloader>) -> std::io::Result<()> {
+    use actix_web::rt::signal::unix::{ signal, SignalKind };
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match reloader.reload() {
+                Ok(()) => log::info!("reloaded tls certificate from {}", reloader.cert_path.display()),
+                Err(e) => log::error!("keeping the previous tls certificate, {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Middleware that answers plain HTTP requests with a permanent redirect to the same URL
+/// over HTTPS. Requests that reached a trusted proxy over HTTPS are left alone.
+#[derive(Clone)]
+pub struct RedirectHttps {
+    https_port: u16,
+}
+
+impl RedirectHttps {
+    pub fn new(https_port: u16) -> Self {
+        RedirectHttps { https_port }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RedirectHttps
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RedirectHttpsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RedirectHttpsMiddleware { service, https_port: self.https_port }))
+    }
+}
+
+pub struct RedirectHttpsMiddleware<S> {
+    service: S,
+    https_port: u16,
+}
+
+impl<S, B> Service<ServiceRequest> for RedirectHttpsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if req.connection_info().scheme() != "http" {
+            let res = self.service.call(req);
+            return Box::pin(async move { res.await.map(ServiceResponse::map_into_left_body) });
+        }
+
+        let location = https_url(req.connection_info().host(), self.https_port, req.uri().path_and_query().map_or("/", |pq| pq.as_str()));
+        let res = HttpResponse::PermanentRedirect()
+            .insert_header((LOCATION, location))
+            .finish()
+            .map_into_right_body();
+        Box::pin(ready(Ok(req.into_response(res))))
+    }
+}
+
+/// `host` with its port replaced by `port`, left out when it is the default 443
+fn https_url(host: &str, port: u16, path_and_query: &str) -> String {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() && (!name.contains(':') || name.ends_with(']')) => name,
+        _ => host,
+    };
+    if port == 443 {
+        format!("https://{}{}", name, path_and_query)
+    } else {
+        format!("https://{}:{}{}", name, port, path_and_query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ fs, io::{ Read, Write }, net::{ TcpListener, TcpStream } };
+    use actix_web::{ rt::task::spawn_blocking, web, App, HttpServer };
+    use rustls::{ pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned };
+
+    /// Self-signed certificate for localhost written to a fresh directory
+    struct TestCert {
+        dir: PathBuf,
+        der: CertificateDer<'static>,
+    }
+
+    impl TestCert {
+        fn generate(dir: &Path) -> TestCert {
+            let rcgen::CertifiedKey { cert, signing_key } = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+            fs::write(dir.join("key.pem"), signing_key.serialize_pem()).unwrap();
+            TestCert { dir: dir.to_path_buf(), der: cert.der().clone() }
+        }
+
+        fn cert_path(&self) -> PathBuf {
+            self.dir.join("cert.pem")
+        }
+
+        fn key_path(&self) -> PathBuf {
+            self.dir.join("key.pem")
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("game-of-life-{}-{}", name, std::process::id()))
+    }
+
+    /// GET `/` over TLS, trusting only `roots`. Returns the response and the certificate the server sent.
+    fn https_get(port: u16, roots: &[CertificateDer<'static>]) -> (String, CertificateDer<'static>) {
+        let mut root_store = RootCertStore::empty();
+        for root in roots {
+            root_store.add(root.clone()).unwrap();
+        }
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions().unwrap()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        let conn = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut stream = StreamOwned::new(conn, TcpStream::connect(("127.0.0.1", port)).unwrap());
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        // the server may close without close_notify, what arrived until then is the response
+        let _ = stream.read_to_end(&mut response);
+        let cert = stream.conn.peer_certificates().unwrap()[0].clone().into_owned();
+        (String::from_utf8_lossy(&response).into_owned(), cert)
+    }
+
+    fn http_get(port: u16) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(b"GET /path?q=1 HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn keeps_the_previous_certificate_when_reload_fails() {
+        let dir = temp_dir("broken-reload");
+        let cert = TestCert::generate(&dir);
+        let reloader = CertReloader::load(&cert.cert_path(), &cert.key_path()).unwrap();
+
+        fs::write(cert.key_path(), "not a key").unwrap();
+        assert!(matches!(reloader.reload(), Err(TlsError::Read { .. })));
+        assert_eq!(reloader.current.read().unwrap().cert[0], cert.der);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn redirects_keep_path_and_swap_port() {
+        assert_eq!(https_url("localhost:3000", 3443, "/api?x=1"), "https://localhost:3443/api?x=1");
+        assert_eq!(https_url("game.example", 443, "/"), "https://game.example/");
+        assert_eq!(https_url("[::1]:3000", 3443, "/"), "https://[::1]:3443/");
+    }
+
+    #[actix_web::test]
+    async fn serves_https_redirects_http_and_reloads_certificates() {
+        let dir = temp_dir("server");
+        let first = TestCert::generate(&dir);
+        let reloader = Arc::new(CertReloader::load(&first.cert_path(), &first.key_path()).unwrap());
+
+        let https = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (https_port, http_port) = (https.local_addr().unwrap().port(), http.local_addr().unwrap().port());
+        let server = HttpServer::new(move || {
+            App::new()
+                .wrap(RedirectHttps::new(https_port))
+                .route("/", web::get().to(|| async { "over tls" }))
+        })
+            .workers(1)
+            .listen_rustls_0_23(https, reloader.server_config()).unwrap()
+            .listen(http).unwrap()
+            .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let roots = vec![first.der.clone()];
+        let (response, cert) = spawn_blocking(move || https_get(https_port, &roots)).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("over tls"));
+        assert_eq!(cert, first.der);
+
+        let response = spawn_blocking(move || http_get(http_port)).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 308"));
+        assert!(response.to_lowercase().contains(&format!("location: https://localhost:{}/path?q=1", https_port)));
+
+        // a renewed certificate is picked up by new connections
+        let second = TestCert::generate(&dir);
+        reloader.reload().unwrap();
+        let roots = vec![first.der.clone(), second.der.clone()];
+        let (_, cert) = spawn_blocking(move || https_get(https_port, &roots)).await.unwrap();
+        assert_eq!(cert, second.der);
+
+        handle.stop(true).await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -13,3 +13,42 @@ pub fn now() -> TimeType {
         .expect("error calculating duration since unix epoch")
         .as_millis() as TimeType
 }
+
+/// Midnight UTC of a `YYYY-MM-DD` day, `None` when it is not a valid day
+pub fn parse_date(date: &str) -> Option<TimeType> {
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (i64, u32, u32) = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month[month as usize - 1] || year < 1970 {
+        return None;
+    }
+    // days before the month, counted from a year starting in march
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m as i64 + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some(days as TimeType * 24 * 60 * 60 * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_days() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        // the monday recurrence.rs counts from
+        assert_eq!(parse_date("2024-01-01"), Some(19723 * 24 * 60 * 60 * 1000));
+        assert_eq!(parse_date("2024-02-29"), Some(19782 * 24 * 60 * 60 * 1000));
+        for date in ["2023-02-29", "2024-13-01", "2024-1-01", "01-01-2024", "1969-12-31", "tomorrow"] {
+            assert_eq!(parse_date(date), None, "{}", date);
+        }
+    }
+}
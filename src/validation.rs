@@ -0,0 +1,260 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::AppError;
+use crate::model::activity::ActivityFilter;
+use crate::model::character::{ CharacterFields, CharacterPatch };
+use crate::model::skill::{ SkillFields, SkillPatch };
+use crate::model::task::{ TaskFields, TaskFilter, TaskPatch };
+use crate::model::user::Credentials;
+use crate::recurrence::Recurrence;
+
+pub const NAME_MAX_LEN: usize = 100;
+pub const TEXT_MAX_LEN: usize = 4096;
+pub const AVATAR_MAX_LEN: usize = 2048;
+pub const QUOTE_MAX_LEN: usize = 500;
+pub const USERNAME_MIN_LEN: usize = 3;
+pub const USERNAME_MAX_LEN: usize = 32;
+pub const PASSWORD_MIN_LEN: usize = 8;
+pub const PASSWORD_MAX_LEN: usize = 128;
+
+/// A single rule broken by a field of a request
+#[derive(Debug, Serialize, PartialEq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub rule: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, rule: impl Into<String>) -> Self {
+        FieldError { field: field.to_string(), rule: rule.into() }
+    }
+
+    /// Comma separated names of the offending fields
+    pub fn names(errors: &[FieldError]) -> String {
+        errors.iter().map(|e| e.field.as_str()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Rule broken by an unparsable id in the path
+pub const ID_RULE: &str = "must be a non-negative integer";
+
+/// Server side checks for incoming fields, run by the handlers before calling a repository.
+pub trait Validate {
+    /// Every rule the value breaks, empty when it is valid
+    fn field_errors(&self) -> Vec<FieldError>;
+
+    fn validate(&self) -> Result<(), AppError> {
+        let errors = self.field_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::ValidationError { errors })
+        }
+    }
+}
+
+impl Validate for CharacterFields {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_name(&mut errors, "name", &self.name);
+        check_max_len(&mut errors, "avatar", &self.avatar, AVATAR_MAX_LEN);
+        check_max_len(&mut errors, "notes", &self.notes, TEXT_MAX_LEN);
+        check_max_len(&mut errors, "quote", &self.quote, QUOTE_MAX_LEN);
+        errors
+    }
+}
+
+impl Validate for CharacterPatch {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let Some(name) = &self.name {
+            check_name(&mut errors, "name", name);
+        }
+        if let Some(avatar) = &self.avatar {
+            check_max_len(&mut errors, "avatar", avatar, AVATAR_MAX_LEN);
+        }
+        if let Some(notes) = &self.notes {
+            check_max_len(&mut errors, "notes", notes, TEXT_MAX_LEN);
+        }
+        if let Some(quote) = &self.quote {
+            check_max_len(&mut errors, "quote", quote, QUOTE_MAX_LEN);
+        }
+        errors
+    }
+}
+
+impl Validate for SkillFields {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_name(&mut errors, "name", &self.name);
+        check_progress(&mut errors, self.progress);
+        errors
+    }
+}
+
+impl Validate for SkillPatch {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let Some(name) = &self.name {
+            check_name(&mut errors, "name", name);
+        }
+        if let Some(progress) = self.progress {
+            check_progress(&mut errors, progress);
+        }
+        errors
+    }
+}
+
+impl Validate for TaskFields {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check_name(&mut errors, "name", &self.name);
+        check_max_len(&mut errors, "description", &self.description, TEXT_MAX_LEN);
+        check_completed(&mut errors, self.completed);
+        if let Some(recurrence) = &self.recurrence {
+            check_recurrence(&mut errors, recurrence);
+        }
+        errors
+    }
+}
+
+impl Validate for TaskPatch {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let Some(name) = &self.name {
+            check_name(&mut errors, "name", name);
+        }
+        if let Some(description) = &self.description {
+            check_max_len(&mut errors, "description", description, TEXT_MAX_LEN);
+        }
+        if let Some(completed) = self.completed {
+            check_completed(&mut errors, completed);
+        }
+        if let Some(Some(recurrence)) = &self.recurrence {
+            check_recurrence(&mut errors, recurrence);
+        }
+        errors
+    }
+}
+
+impl Validate for TaskFilter {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let Some(completed) = self.completed {
+            check_completed(&mut errors, completed);
+        }
+        errors
+    }
+}
+
+impl Validate for ActivityFilter {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if until < since {
+                errors.push(FieldError::new("until", "must not be before since"));
+            }
+        }
+        errors
+    }
+}
+
+impl Validate for Credentials {
+    fn field_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let username_len = self.username.chars().count();
+        if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&username_len) {
+            errors.push(FieldError::new("username", format!("must be {} to {} characters", USERNAME_MIN_LEN, USERNAME_MAX_LEN)));
+        } else if !self.username.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+            errors.push(FieldError::new("username", "may only contain letters, digits, '_', '.' and '-'"));
+        }
+        let password_len = self.password.chars().count();
+        if !(PASSWORD_MIN_LEN..=PASSWORD_MAX_LEN).contains(&password_len) {
+            errors.push(FieldError::new("password", format!("must be {} to {} characters", PASSWORD_MIN_LEN, PASSWORD_MAX_LEN)));
+        }
+        errors
+    }
+}
+
+fn check_name(errors: &mut Vec<FieldError>, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        errors.push(FieldError::new(field, "must not be empty"));
+    }
+    check_max_len(errors, field, value, NAME_MAX_LEN);
+}
+
+fn check_progress(errors: &mut Vec<FieldError>, progress: u8) {
+    if progress > 100 {
+        errors.push(FieldError::new("progress", "must be a percentage between 0 and 100"));
+    }
+}
+
+fn check_completed(errors: &mut Vec<FieldError>, completed: u8) {
+    if completed > 1 {
+        errors.push(FieldError::new("completed", "must be 0 or 1"));
+    }
+}
+
+fn check_recurrence(errors: &mut Vec<FieldError>, recurrence: &Recurrence) {
+    if let Err(rule) = recurrence.schedule() {
+        errors.push(FieldError::new("recurrence", rule));
+    }
+}
+
+fn check_max_len(errors: &mut Vec<FieldError>, field: &str, value: &str, max_len: usize) {
+    if value.chars().count() > max_len {
+        errors.push(FieldError::new(field, format!("must be at most {} characters", max_len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_fields_pass() {
+        let skill = SkillFields { name: "guitar".to_string(), progress: 100, level: 3 };
+        assert!(skill.validate().is_ok());
+        let task = TaskFields { name: "scales".to_string(), description: String::new(), completed: 1, recurrence: Some(Recurrence::Daily) };
+        assert!(task.validate().is_ok());
+    }
+
+    #[test]
+    fn reports_every_broken_field() {
+        let character = CharacterFields {
+            name: " ".to_string(),
+            avatar: String::new(),
+            notes: "x".repeat(TEXT_MAX_LEN + 1),
+            quote: "y".repeat(QUOTE_MAX_LEN + 1),
+        };
+        let fields: Vec<String> = character.field_errors().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["name", "notes", "quote"]);
+    }
+
+    #[test]
+    fn rejects_out_of_range_numbers() {
+        let skill = SkillFields { name: "guitar".to_string(), progress: 101, level: 0 };
+        assert_eq!(skill.field_errors(), vec![FieldError::new("progress", "must be a percentage between 0 and 100")]);
+        let recurrence = Some(Recurrence::EveryNDays { days: 0 });
+        let task = TaskFields { name: "n".repeat(NAME_MAX_LEN + 1), description: String::new(), completed: 2, recurrence };
+        let fields: Vec<String> = task.field_errors().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["name", "completed", "recurrence"]);
+    }
+
+    #[test]
+    fn patch_checks_only_given_fields() {
+        assert!(TaskPatch { completed: Some(1), ..Default::default() }.validate().is_ok());
+        let patch = SkillPatch { name: Some(String::new()), progress: Some(150), level: None };
+        let fields: Vec<String> = patch.field_errors().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["name", "progress"]);
+    }
+
+    #[test]
+    fn checks_credentials() {
+        let credentials = Credentials { username: "hero_1".to_string(), password: "correct horse".to_string() };
+        assert!(credentials.validate().is_ok());
+        let credentials = Credentials { username: "he ro".to_string(), password: "short".to_string() };
+        let fields: Vec<String> = credentials.field_errors().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["username", "password"]);
+    }
+}